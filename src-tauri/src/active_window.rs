@@ -0,0 +1,225 @@
+use serde_json::Value;
+use std::process::Command;
+
+const MAX_WINDOW_TREE_DEPTH: usize = 8;
+
+fn is_wayland() -> bool {
+    std::env::var("WAYLAND_DISPLAY").is_ok()
+}
+
+/// Returns the window class (e.g. "firefox") of the application that owns the clipboard, falling back to the
+/// focused application when the owner can't be identified. Wayland compositors don't expose
+/// selection owners, so there this is always the focused application.
+pub fn clipboard_owner_app_name() -> Option<String> {
+    if is_wayland() {
+        wayland::focused_window_class()
+    } else {
+        x11::selection_owner_class("CLIPBOARD").or_else(x11::active_window_class)
+    }
+}
+
+/// Returns the window class of the focused application.
+pub fn focused_app_name() -> Option<String> {
+    if is_wayland() {
+        wayland::focused_window_class()
+    } else {
+        x11::active_window_class()
+    }
+}
+
+/// WM_CLASS holds two NUL-terminated strings, the instance and the class name.
+fn parse_wm_class(value: &[u8]) -> Option<String> {
+    let mut parts = value.split(|b| *b == 0).filter(|part| !part.is_empty());
+    let instance = parts.next();
+    let class = parts.next().or(instance)?;
+    Some(String::from_utf8_lossy(class).into_owned())
+}
+
+fn find_focused_sway_node(node: &Value) -> Option<String> {
+    if node["focused"].as_bool() == Some(true) {
+        return node["app_id"]
+            .as_str()
+            .or_else(|| node["window_properties"]["class"].as_str())
+            .map(str::to_string);
+    }
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node[key].as_array())
+        .flatten()
+        .find_map(find_focused_sway_node)
+}
+
+#[cfg(target_os = "linux")]
+mod x11 {
+    use super::{parse_wm_class, MAX_WINDOW_TREE_DEPTH};
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt, Window};
+    use x11rb::rust_connection::RustConnection;
+
+    fn intern(conn: &RustConnection, name: &str) -> Option<Atom> {
+        Some(
+            conn.intern_atom(false, name.as_bytes())
+                .ok()?
+                .reply()
+                .ok()?
+                .atom,
+        )
+    }
+
+    fn window_property(
+        conn: &RustConnection,
+        window: Window,
+        property: Atom,
+        kind: AtomEnum,
+    ) -> Option<Window> {
+        let reply = conn
+            .get_property(false, window, property, kind, 0, 1)
+            .ok()?
+            .reply()
+            .ok()?;
+        let value = reply.value32()?.next();
+        value.filter(|w| *w != x11rb::NONE)
+    }
+
+    fn wm_class(conn: &RustConnection, window: Window) -> Option<String> {
+        let reply = conn
+            .get_property(false, window, AtomEnum::WM_CLASS, AtomEnum::STRING, 0, 256)
+            .ok()?
+            .reply()
+            .ok()?;
+        parse_wm_class(&reply.value)
+    }
+
+    fn process_name(conn: &RustConnection, window: Window) -> Option<String> {
+        let pid = window_property(
+            conn,
+            window,
+            intern(conn, "_NET_WM_PID")?,
+            AtomEnum::CARDINAL,
+        )?;
+        let comm = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+        Some(comm.trim().to_string()).filter(|name| !name.is_empty())
+    }
+
+    /// Selection owners are usually unmapped helper windows, so look at the client leader
+    /// and the window's ancestors until one of them identifies the application.
+    fn describe(conn: &RustConnection, window: Window) -> Option<String> {
+        let client_leader = intern(conn, "WM_CLIENT_LEADER")
+            .and_then(|atom| window_property(conn, window, atom, AtomEnum::WINDOW));
+        if let Some(name) = client_leader.and_then(|leader| wm_class(conn, leader)) {
+            return Some(name);
+        }
+
+        let mut current = window;
+        for _ in 0..MAX_WINDOW_TREE_DEPTH {
+            if let Some(name) = wm_class(conn, current).or_else(|| process_name(conn, current)) {
+                return Some(name);
+            }
+            let tree = conn.query_tree(current).ok()?.reply().ok()?;
+            if tree.parent == x11rb::NONE || tree.parent == tree.root {
+                return None;
+            }
+            current = tree.parent;
+        }
+        None
+    }
+
+    pub fn active_window_class() -> Option<String> {
+        let (conn, screen_num) = x11rb::connect(None).ok()?;
+        let root = conn.setup().roots.get(screen_num)?.root;
+        let active = window_property(
+            &conn,
+            root,
+            intern(&conn, "_NET_ACTIVE_WINDOW")?,
+            AtomEnum::WINDOW,
+        )?;
+        describe(&conn, active)
+    }
+
+    pub fn selection_owner_class(selection: &str) -> Option<String> {
+        let (conn, _) = x11rb::connect(None).ok()?;
+        let owner = conn
+            .get_selection_owner(intern(&conn, selection)?)
+            .ok()?
+            .reply()
+            .ok()?
+            .owner;
+        if owner == x11rb::NONE {
+            return None;
+        }
+        describe(&conn, owner)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod x11 {
+    pub fn active_window_class() -> Option<String> {
+        None
+    }
+
+    pub fn selection_owner_class(_selection: &str) -> Option<String> {
+        None
+    }
+}
+
+mod wayland {
+    use super::{find_focused_sway_node, Command, Value};
+
+    fn json_output(program: &str, args: &[&str]) -> Option<Value> {
+        let output = Command::new(program).args(args).output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        serde_json::from_slice(&output.stdout).ok()
+    }
+
+    /// There is no cross-compositor protocol for this, so ask the compositors that expose
+    /// focus over IPC.
+    pub fn focused_window_class() -> Option<String> {
+        if std::env::var("HYPRLAND_INSTANCE_SIGNATURE").is_ok() {
+            let window = json_output("hyprctl", &["activewindow", "-j"])?;
+            return window["class"]
+                .as_str()
+                .filter(|class| !class.is_empty())
+                .map(str::to_string);
+        }
+        if std::env::var("SWAYSOCK").is_ok() {
+            return find_focused_sway_node(&json_output("swaymsg", &["-t", "get_tree", "-r"])?);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_wm_class_prefers_class_name() {
+        assert_eq!(
+            parse_wm_class(b"Navigator\0firefox\0"),
+            Some("firefox".to_string())
+        );
+        assert_eq!(parse_wm_class(b"xterm\0"), Some("xterm".to_string()));
+        assert_eq!(parse_wm_class(b""), None);
+    }
+
+    #[test]
+    fn test_find_focused_sway_node() {
+        let tree = json!({
+            "focused": false,
+            "nodes": [{
+                "focused": false,
+                "nodes": [{ "focused": false, "app_id": "foot" }],
+                "floating_nodes": [{
+                    "focused": true,
+                    "app_id": null,
+                    "window_properties": { "class": "Gimp" }
+                }]
+            }]
+        });
+        assert_eq!(find_focused_sway_node(&tree), Some("Gimp".to_string()));
+        assert_eq!(find_focused_sway_node(&json!({ "nodes": [] })), None);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::clipboard_history::{self, types::ClipboardFormats};
 use enigo::{Enigo, Key, Keyboard, Settings};
 use std::{thread, time::Duration};
 use tauri_plugin_clipboard_manager::ClipboardExt;
@@ -16,6 +17,20 @@ pub struct ClipboardContent {
     text: Option<String>,
     html: Option<String>,
     file: Option<String>,
+    rtf: Option<String>,
+    uri_list: Option<String>,
+}
+
+impl ClipboardContent {
+    pub fn text(text: String) -> Self {
+        Self {
+            text: Some(text),
+            html: None,
+            file: None,
+            rtf: None,
+            uri_list: None,
+        }
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone)]
@@ -60,10 +75,35 @@ pub async fn clipboard_read(app: tauri::AppHandle) -> Result<ReadResult, String>
 pub async fn clipboard_copy(
     app: tauri::AppHandle,
     content: ClipboardContent,
-    _options: Option<CopyOptions>,
+    options: Option<CopyOptions>,
 ) -> Result<(), String> {
     let clipboard = app.clipboard();
 
+    if options.and_then(|o| o.concealed).unwrap_or(false) {
+        if let Some(text) = content.file.as_ref().or(content.text.as_ref()) {
+            clipboard_history::conceal_text(text);
+        }
+    }
+
+    // Rich formats need to be offered together with the text at once, which the plugin can't do.
+    let formats = ClipboardFormats {
+        html: content.html.clone(),
+        rtf: content.rtf.clone(),
+        uri_list: content.uri_list.clone().or_else(|| {
+            content
+                .file
+                .as_deref()
+                .and_then(clipboard_history::path_to_uri_list)
+        }),
+    };
+    if formats.html.is_some() || formats.rtf.is_some() || formats.uri_list.is_some() {
+        let text = content.text.as_deref().or(content.file.as_deref());
+        match clipboard_history::write_clipboard_formats(text, &formats) {
+            Ok(()) => return Ok(()),
+            Err(e) => eprintln!("Failed to write rich clipboard formats: {}", e),
+        }
+    }
+
     if let Some(file_path) = &content.file {
         clipboard
             .write_text(file_path.clone())
@@ -0,0 +1,205 @@
+use super::encryption::{decrypt_bytes, encrypt_bytes};
+use super::types::{ClipboardFormats, ContentType};
+use crate::error::AppError;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+const EXPORT_VERSION: u32 = 1;
+pub const KDF_NAME: &str = "pbkdf2-hmac-sha256";
+pub const KDF_ITERATIONS: u32 = 600_000;
+pub const SALT_BYTES: usize = 16;
+/// Iteration counts read from files are kept within these bounds, so a crafted file can
+/// neither weaken the key derivation nor hang the app deriving it.
+pub const MIN_KDF_ITERATIONS: u32 = 100_000;
+const MAX_KDF_ITERATIONS: u32 = 10_000_000;
+
+/// The file written by `history_export`. Only the KDF parameters are readable without the
+/// passphrase.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportEnvelope {
+    version: u32,
+    kdf: String,
+    iterations: u32,
+    salt: String,
+    data: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedItem {
+    pub hash: String,
+    pub content_type: ContentType,
+    pub content: String,
+    #[serde(default)]
+    pub formats: ClipboardFormats,
+    pub source_app_name: Option<String>,
+    pub first_copied_at: i64,
+    pub last_copied_at: i64,
+    pub times_copied: i32,
+    pub is_pinned: bool,
+    /// See `modified_at` in the history table. Exports written before it existed leave it 0.
+    #[serde(default)]
+    pub modified_at: i64,
+    /// Base64 PNG of image items, whose `content` is only a path on the exporting machine.
+    #[serde(default)]
+    pub image: Option<String>,
+    #[serde(default)]
+    pub ocr_text: Option<String>,
+}
+
+impl ExportedItem {
+    pub fn image_bytes(&self) -> Result<Option<Vec<u8>>, AppError> {
+        self.image
+            .as_deref()
+            .map(|image| {
+                BASE64
+                    .decode(image)
+                    .map_err(|e| AppError::Serialization(e.to_string()))
+            })
+            .transpose()
+    }
+
+    pub fn set_image_bytes(&mut self, png: &[u8]) {
+        self.image = Some(BASE64.encode(png));
+    }
+}
+
+/// Checks an iteration count read from a file. Outside of tests `min_iterations` is
+/// `MIN_KDF_ITERATIONS`.
+pub fn check_iterations(iterations: u32, min_iterations: u32) -> Result<(), AppError> {
+    if (min_iterations..=MAX_KDF_ITERATIONS).contains(&iterations) {
+        Ok(())
+    } else {
+        Err(AppError::ClipboardHistory(format!(
+            "Unsupported key derivation iteration count {}",
+            iterations
+        )))
+    }
+}
+
+pub fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+    key
+}
+
+pub fn seal(items: &[ExportedItem], passphrase: &str) -> Result<Vec<u8>, AppError> {
+    seal_with_iterations(items, passphrase, KDF_ITERATIONS)
+}
+
+fn seal_with_iterations(
+    items: &[ExportedItem],
+    passphrase: &str,
+    iterations: u32,
+) -> Result<Vec<u8>, AppError> {
+    if passphrase.is_empty() {
+        return Err(AppError::ClipboardHistory(
+            "A passphrase is required to export clipboard history".into(),
+        ));
+    }
+    let salt: [u8; SALT_BYTES] = rand::random();
+    let key = derive_key(passphrase, &salt, iterations);
+    let payload = serde_json::to_vec(items).map_err(|e| AppError::Serialization(e.to_string()))?;
+
+    let envelope = ExportEnvelope {
+        version: EXPORT_VERSION,
+        kdf: KDF_NAME.to_string(),
+        iterations,
+        salt: hex::encode(salt),
+        data: BASE64.encode(encrypt_bytes(&payload, &key)?),
+    };
+    serde_json::to_vec_pretty(&envelope).map_err(|e| AppError::Serialization(e.to_string()))
+}
+
+pub fn open(data: &[u8], passphrase: &str) -> Result<Vec<ExportedItem>, AppError> {
+    open_with_min_iterations(data, passphrase, MIN_KDF_ITERATIONS)
+}
+
+fn open_with_min_iterations(
+    data: &[u8],
+    passphrase: &str,
+    min_iterations: u32,
+) -> Result<Vec<ExportedItem>, AppError> {
+    let envelope: ExportEnvelope =
+        serde_json::from_slice(data).map_err(|e| AppError::Serialization(e.to_string()))?;
+    if envelope.version != EXPORT_VERSION || envelope.kdf != KDF_NAME {
+        return Err(AppError::ClipboardHistory(format!(
+            "Unsupported export format (version {}, {})",
+            envelope.version, envelope.kdf
+        )));
+    }
+
+    check_iterations(envelope.iterations, min_iterations)?;
+
+    let salt = hex::decode(&envelope.salt).map_err(|e| AppError::Serialization(e.to_string()))?;
+    let ciphertext = BASE64
+        .decode(&envelope.data)
+        .map_err(|e| AppError::Serialization(e.to_string()))?;
+    let key = derive_key(passphrase, &salt, envelope.iterations);
+    let payload = decrypt_bytes(&ciphertext, &key)
+        .map_err(|_| AppError::ClipboardHistory("Wrong passphrase or corrupted export".into()))?;
+    serde_json::from_slice(&payload).map_err(|e| AppError::Serialization(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(hash: &str) -> ExportedItem {
+        ExportedItem {
+            hash: hash.into(),
+            content_type: ContentType::Text,
+            content: format!("content of {}", hash),
+            formats: ClipboardFormats::default(),
+            source_app_name: None,
+            first_copied_at: 1,
+            last_copied_at: 2,
+            times_copied: 3,
+            is_pinned: false,
+            modified_at: 2,
+            image: None,
+            ocr_text: None,
+        }
+    }
+
+    #[test]
+    fn test_seal_and_open_roundtrip() {
+        let items = vec![item("a"), item("b")];
+        let sealed = seal_with_iterations(&items, "correct horse", 10).unwrap();
+
+        assert!(!String::from_utf8_lossy(&sealed).contains("content of"));
+        assert_eq!(
+            open_with_min_iterations(&sealed, "correct horse", 10).unwrap(),
+            items
+        );
+    }
+
+    #[test]
+    fn test_open_rejects_wrong_passphrase() {
+        let sealed = seal_with_iterations(&[item("a")], "correct horse", 10).unwrap();
+        let err = open_with_min_iterations(&sealed, "battery staple", 10).unwrap_err();
+        assert!(err.to_string().contains("Wrong passphrase"));
+    }
+
+    #[test]
+    fn test_open_rejects_out_of_range_iterations() {
+        let sealed = seal_with_iterations(&[item("a")], "correct horse", 10).unwrap();
+        let err = open(&sealed, "correct horse").unwrap_err();
+        assert!(err.to_string().contains("iteration count"));
+
+        let mut envelope: ExportEnvelope = serde_json::from_slice(&sealed).unwrap();
+        for iterations in [1, u32::MAX] {
+            envelope.iterations = iterations;
+            let tampered = serde_json::to_vec(&envelope).unwrap();
+            let err = open_with_min_iterations(&tampered, "correct horse", 10).unwrap_err();
+            assert!(err.to_string().contains("iteration count"));
+        }
+    }
+
+    #[test]
+    fn test_seal_requires_passphrase() {
+        assert!(seal_with_iterations(&[item("a")], "", 10).is_err());
+    }
+}
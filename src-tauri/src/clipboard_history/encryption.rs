@@ -27,30 +27,46 @@ pub fn get_encryption_key() -> Result<[u8; 32], AppError> {
     get_encryption_key_impl(&entry)
 }
 
-pub fn encrypt(data: &str, key: &[u8; 32]) -> Result<String, AppError> {
+/// Replaces the stored key, e.g. after the history was re-encrypted with a new one.
+pub fn set_encryption_key(key: &[u8; 32]) -> Result<(), AppError> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, KEYRING_USERNAME)?;
+    entry.set_password(&hex::encode(key))?;
+    Ok(())
+}
+
+/// Encrypts raw bytes, returning the nonce followed by the ciphertext.
+pub fn encrypt_bytes(data: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, AppError> {
     let cipher = Aes256Gcm::new(key.into());
     let nonce_bytes: [u8; 12] = rand::random();
     let nonce = Nonce::from_slice(&nonce_bytes);
     let ciphertext = cipher
-        .encrypt(nonce, data.as_bytes())
+        .encrypt(nonce, data)
         .map_err(|e| AppError::ClipboardHistory(e.to_string()))?;
 
     let mut result = nonce_bytes.to_vec();
     result.extend_from_slice(&ciphertext);
-    Ok(hex::encode(result))
+    Ok(result)
 }
 
-pub fn decrypt(hex_data: &str, key: &[u8; 32]) -> Result<String, AppError> {
-    let data = hex::decode(hex_data).map_err(|e| AppError::ClipboardHistory(e.to_string()))?;
+pub fn decrypt_bytes(data: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, AppError> {
     if data.len() < 12 {
         return Err(AppError::ClipboardHistory("Invalid encrypted data".into()));
     }
     let (nonce_bytes, ciphertext) = data.split_at(12);
     let nonce = Nonce::from_slice(nonce_bytes);
     let cipher = Aes256Gcm::new(key.into());
-    let decrypted_bytes = cipher
+    cipher
         .decrypt(nonce, ciphertext)
-        .map_err(|e| AppError::ClipboardHistory(e.to_string()))?;
+        .map_err(|e| AppError::ClipboardHistory(e.to_string()))
+}
+
+pub fn encrypt(data: &str, key: &[u8; 32]) -> Result<String, AppError> {
+    Ok(hex::encode(encrypt_bytes(data.as_bytes(), key)?))
+}
+
+pub fn decrypt(hex_data: &str, key: &[u8; 32]) -> Result<String, AppError> {
+    let data = hex::decode(hex_data).map_err(|e| AppError::ClipboardHistory(e.to_string()))?;
+    let decrypted_bytes = decrypt_bytes(&data, key)?;
     String::from_utf8(decrypted_bytes).map_err(|e| AppError::ClipboardHistory(e.to_string()))
 }
 
@@ -202,4 +218,19 @@ mod tests {
         let err_string = result.unwrap_err().to_string();
         assert!(err_string.contains("Invalid encrypted data"));
     }
+
+    #[test]
+    fn encrypt_decrypt_bytes_roundtrip_works_for_binary_data() {
+        // Given: a key and bytes that are not valid UTF-8
+        let key = get_random_key();
+        let data = vec![0x89, b'P', b'N', b'G', 0xff, 0x00, 0xfe];
+
+        // When: we encrypt and then decrypt the bytes
+        let encrypted = encrypt_bytes(&data, &key).unwrap();
+        let decrypted = decrypt_bytes(&encrypted, &key).unwrap();
+
+        // Then: the ciphertext hides the data and the roundtrip restores it
+        assert!(!encrypted.windows(4).any(|w| w == b"\x89PNG"));
+        assert_eq!(decrypted, data);
+    }
 }
@@ -0,0 +1,266 @@
+use super::backup::{
+    check_iterations, derive_key, KDF_ITERATIONS, KDF_NAME, MIN_KDF_ITERATIONS, SALT_BYTES,
+};
+use super::encryption::{decrypt_bytes, encrypt_bytes, get_encryption_key, set_encryption_key};
+use crate::error::AppError;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PASSPHRASE_KEY_FILENAME: &str = "clipboard_history_key.json";
+const PLAINTEXT_KEY_FILENAME: &str = "clipboard_history_key.plain";
+
+/// Where the history encryption key is kept.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum KeyProviderKind {
+    /// The Secret Service / platform keyring.
+    #[default]
+    Keyring,
+    /// A key file encrypted with a passphrase that has to be entered once per session.
+    PassphraseFile,
+    /// An unprotected key file next to the database. Anyone who can read the data directory
+    /// can read the history, so this has to be chosen explicitly.
+    Plaintext,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyProviderStatus {
+    pub provider: KeyProviderKind,
+    pub unlocked: bool,
+    pub error: Option<String>,
+}
+
+pub trait KeyProvider: Send {
+    fn kind(&self) -> KeyProviderKind;
+
+    /// Returns the stored key, creating and storing a new one if there is none yet.
+    fn load_key(&self) -> Result<[u8; 32], AppError>;
+
+    fn store_key(&self, key: &[u8; 32]) -> Result<(), AppError>;
+}
+
+pub struct KeyringProvider;
+
+impl KeyProvider for KeyringProvider {
+    fn kind(&self) -> KeyProviderKind {
+        KeyProviderKind::Keyring
+    }
+
+    fn load_key(&self) -> Result<[u8; 32], AppError> {
+        get_encryption_key()
+    }
+
+    fn store_key(&self, key: &[u8; 32]) -> Result<(), AppError> {
+        set_encryption_key(key)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WrappedKey {
+    kdf: String,
+    iterations: u32,
+    salt: String,
+    key: String,
+}
+
+pub struct PassphraseFileProvider {
+    path: PathBuf,
+    passphrase: String,
+    iterations: u32,
+    /// The fewest iterations accepted from the key file.
+    min_iterations: u32,
+}
+
+impl PassphraseFileProvider {
+    pub fn new(data_dir: &Path, passphrase: String) -> Result<Self, AppError> {
+        if passphrase.is_empty() {
+            return Err(AppError::ClipboardHistory(
+                "A passphrase is required to unlock clipboard history".into(),
+            ));
+        }
+        Ok(Self {
+            path: data_dir.join(PASSPHRASE_KEY_FILENAME),
+            passphrase,
+            iterations: KDF_ITERATIONS,
+            min_iterations: MIN_KDF_ITERATIONS,
+        })
+    }
+}
+
+impl KeyProvider for PassphraseFileProvider {
+    fn kind(&self) -> KeyProviderKind {
+        KeyProviderKind::PassphraseFile
+    }
+
+    fn load_key(&self) -> Result<[u8; 32], AppError> {
+        if !self.path.exists() {
+            let key: [u8; 32] = rand::random();
+            self.store_key(&key)?;
+            return Ok(key);
+        }
+        let wrapped: WrappedKey = serde_json::from_slice(&fs::read(&self.path)?)
+            .map_err(|e| AppError::Serialization(e.to_string()))?;
+        if wrapped.kdf != KDF_NAME {
+            return Err(AppError::ClipboardHistory(format!(
+                "Unsupported key derivation {}",
+                wrapped.kdf
+            )));
+        }
+        check_iterations(wrapped.iterations, self.min_iterations)?;
+        let salt =
+            hex::decode(&wrapped.salt).map_err(|e| AppError::Serialization(e.to_string()))?;
+        let ciphertext =
+            hex::decode(&wrapped.key).map_err(|e| AppError::Serialization(e.to_string()))?;
+        let wrapping_key = derive_key(&self.passphrase, &salt, wrapped.iterations);
+        let key = decrypt_bytes(&ciphertext, &wrapping_key)
+            .map_err(|_| AppError::ClipboardHistory("Wrong passphrase".into()))?;
+        key.try_into()
+            .map_err(|_| AppError::ClipboardHistory("Invalid key file".into()))
+    }
+
+    fn store_key(&self, key: &[u8; 32]) -> Result<(), AppError> {
+        let salt: [u8; SALT_BYTES] = rand::random();
+        let wrapping_key = derive_key(&self.passphrase, &salt, self.iterations);
+        let wrapped = WrappedKey {
+            kdf: KDF_NAME.to_string(),
+            iterations: self.iterations,
+            salt: hex::encode(salt),
+            key: hex::encode(encrypt_bytes(key, &wrapping_key)?),
+        };
+        let content = serde_json::to_vec_pretty(&wrapped)
+            .map_err(|e| AppError::Serialization(e.to_string()))?;
+        write_private(&self.path, &content)
+    }
+}
+
+pub struct PlaintextProvider {
+    path: PathBuf,
+}
+
+impl PlaintextProvider {
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            path: data_dir.join(PLAINTEXT_KEY_FILENAME),
+        }
+    }
+}
+
+impl KeyProvider for PlaintextProvider {
+    fn kind(&self) -> KeyProviderKind {
+        KeyProviderKind::Plaintext
+    }
+
+    fn load_key(&self) -> Result<[u8; 32], AppError> {
+        if !self.path.exists() {
+            let key: [u8; 32] = rand::random();
+            self.store_key(&key)?;
+            return Ok(key);
+        }
+        let key = hex::decode(fs::read_to_string(&self.path)?.trim())
+            .map_err(|e| AppError::ClipboardHistory(e.to_string()))?;
+        key.try_into()
+            .map_err(|_| AppError::ClipboardHistory("Invalid key file".into()))
+    }
+
+    fn store_key(&self, key: &[u8; 32]) -> Result<(), AppError> {
+        write_private(&self.path, hex::encode(key).as_bytes())
+    }
+}
+
+/// Writes a file only the current user can read.
+fn write_private(path: &Path, content: &[u8]) -> Result<(), AppError> {
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, content)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&temp_path, fs::Permissions::from_mode(0o600))?;
+    }
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Builds the provider for `kind`. Only the passphrase file provider needs `passphrase`.
+pub fn create_provider(
+    kind: KeyProviderKind,
+    data_dir: &Path,
+    passphrase: Option<String>,
+) -> Result<Box<dyn KeyProvider>, AppError> {
+    Ok(match kind {
+        KeyProviderKind::Keyring => Box::new(KeyringProvider),
+        KeyProviderKind::PassphraseFile => Box::new(PassphraseFileProvider::new(
+            data_dir,
+            passphrase.unwrap_or_default(),
+        )?),
+        KeyProviderKind::Plaintext => Box::new(PlaintextProvider::new(data_dir)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("raycast_test_{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn passphrase_provider(dir: &Path, passphrase: &str) -> PassphraseFileProvider {
+        PassphraseFileProvider {
+            iterations: 10,
+            min_iterations: 10,
+            ..PassphraseFileProvider::new(dir, passphrase.to_string()).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_passphrase_file_creates_and_reloads_key() {
+        let dir = temp_dir();
+        let key = passphrase_provider(&dir, "hunter2").load_key().unwrap();
+
+        assert_eq!(
+            passphrase_provider(&dir, "hunter2").load_key().unwrap(),
+            key
+        );
+        let stored = fs::read_to_string(dir.join(PASSPHRASE_KEY_FILENAME)).unwrap();
+        assert!(!stored.contains(&hex::encode(key)));
+
+        let err = passphrase_provider(&dir, "wrong").load_key().unwrap_err();
+        assert!(err.to_string().contains("Wrong passphrase"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_passphrase_file_rejects_out_of_range_iterations() {
+        let dir = temp_dir();
+        passphrase_provider(&dir, "hunter2").load_key().unwrap();
+        let path = dir.join(PASSPHRASE_KEY_FILENAME);
+        let mut wrapped: WrappedKey = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        wrapped.iterations = u32::MAX;
+        fs::write(&path, serde_json::to_vec(&wrapped).unwrap()).unwrap();
+
+        let err = passphrase_provider(&dir, "hunter2").load_key().unwrap_err();
+        assert!(err.to_string().contains("iteration count"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_passphrase_file_requires_passphrase() {
+        let dir = std::env::temp_dir();
+        assert!(create_provider(KeyProviderKind::PassphraseFile, &dir, None).is_err());
+    }
+
+    #[test]
+    fn test_plaintext_provider_roundtrip() {
+        let dir = temp_dir();
+        let provider = PlaintextProvider::new(&dir);
+        let key: [u8; 32] = rand::random();
+        provider.store_key(&key).unwrap();
+        assert_eq!(provider.load_key().unwrap(), key);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
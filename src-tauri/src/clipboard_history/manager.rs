@@ -1,16 +1,31 @@
 use super::{
-    encryption::{decrypt, encrypt, get_encryption_key},
-    monitor::start_monitoring,
-    types::{ClipboardItem, ContentType, INLINE_CONTENT_THRESHOLD_BYTES, PREVIEW_LENGTH_CHARS},
+    backup::{self, ExportedItem},
+    detect::{detect, is_subtype_filter},
+    encryption::{decrypt, decrypt_bytes, encrypt, encrypt_bytes},
+    key_provider::{create_provider, KeyProvider, KeyProviderKind, KeyProviderStatus},
+    monitor::{start_monitoring, text_hash},
+    ocr::start_ocr_worker,
+    primary::{start_primary_monitoring, PRIMARY_HASH_PREFIX},
+    retention::{start_pruning, PruneCandidate, PruneReport},
+    search,
+    settings::{read_settings, write_settings, ClipboardHistorySettings, SETTINGS_FILENAME},
+    sync::start_sync,
+    types::{
+        ClipboardFormats, ClipboardItem, ClipboardStats, ContentType, DailyCopies, MergeOrder,
+        Selection, SourceAppUsage, TypeStorage, INLINE_CONTENT_THRESHOLD_BYTES,
+        PREVIEW_LENGTH_CHARS,
+    },
 };
 use crate::error::AppError;
 use crate::store::Store;
 use chrono::{DateTime, Utc};
-use once_cell::sync::Lazy;
-use rusqlite::{params, Result as RusqliteResult};
-use std::path::PathBuf;
+use once_cell::sync::{Lazy, OnceCell};
+use rusqlite::{params, Connection, OptionalExtension, Result as RusqliteResult};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicBool;
-use std::sync::Mutex;
+use std::sync::{Mutex, Once};
 use tauri::{AppHandle, Manager};
 
 const CLIPBOARD_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS clipboard_history (
@@ -27,12 +42,161 @@ const CLIPBOARD_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS clipboard_history (
     is_pinned INTEGER NOT NULL DEFAULT 0
 )";
 
+/// The key shared with paired sync devices, encrypted with the history key. Holds at most
+/// one row.
+const SYNC_KEY_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS clipboard_sync_key (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    encrypted_key TEXT NOT NULL
+)";
+
+/// The key a rotation switches to, encrypted with the key it replaces. Committed together
+/// with the re-encrypted items and removed once the rotation has finished, so whichever key
+/// the key provider holds after a crash can still read the history. See `rotate_key`.
+const KEY_ROTATION_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS clipboard_key_rotation (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    encrypted_key TEXT NOT NULL
+)";
+
+/// This device's id towards sync peers, and the last `sync_seq` handed out. Holds one row.
+const SYNC_STATE_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS clipboard_sync_state (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    device_id TEXT NOT NULL,
+    last_seq INTEGER NOT NULL DEFAULT 0
+)";
+
+const IMAGE_EXTENSION: &str = "enc";
+/// Images re-encrypted by a key rotation wait under this extension until it commits.
+const STAGED_IMAGE_EXTENSION: &str = "rotating";
+/// Images younger than this are never swept as orphans, since the monitor writes an image
+/// before the row that references it is inserted.
+const ORPHAN_GRACE_PERIOD: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+/// Images were stored unencrypted before, see `migrate_plaintext_images`.
+const PLAINTEXT_IMAGE_EXTENSION: &str = "png";
+
+/// Columns added after the table was first shipped, with their definitions.
+const ADDED_COLUMNS: &[(&str, &str)] = &[
+    ("encrypted_formats", "TEXT"),
+    // NULL until the OCR worker has looked at the image, empty if it found no text.
+    ("encrypted_ocr_text", "TEXT"),
+    // NULL until classified, empty if no detector matched. See `subtype_of`.
+    ("content_subtype", "TEXT"),
+    // Set for highlighted text recorded from PRIMARY, see `add_primary_item`.
+    ("is_primary", "INTEGER NOT NULL DEFAULT 0"),
+    // When the item last changed in a way sync carries over, i.e. was copied or (un)pinned.
+    // 0 for items stored before it existed, see `init_tables`.
+    ("modified_at", "INTEGER NOT NULL DEFAULT 0"),
+    // When the item last changed on this device, in the order of `next_sync_seq`. Peers pull
+    // by it, since `modified_at` may come from another device's clock.
+    ("sync_seq", "INTEGER NOT NULL DEFAULT 0"),
+];
+
+fn init_tables(store: &Store) -> Result<(), AppError> {
+    store.init_table(CLIPBOARD_SCHEMA)?;
+    store.init_table(search::SEARCH_INDEX_SCHEMA)?;
+    store.init_table(search::SEARCH_INDEX_ITEM_SCHEMA)?;
+    store.init_table(SYNC_KEY_SCHEMA)?;
+    store.init_table(KEY_ROTATION_SCHEMA)?;
+    store.init_table(SYNC_STATE_SCHEMA)?;
+
+    let db = store.conn();
+    let mut stmt = db.prepare("PRAGMA table_info(clipboard_history)")?;
+    let columns: Vec<String> = stmt
+        .query_map([], |row| row.get(1))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (name, definition) in ADDED_COLUMNS {
+        if !columns.iter().any(|column| column == name) {
+            db.execute(
+                &format!(
+                    "ALTER TABLE clipboard_history ADD COLUMN {} {}",
+                    name, definition
+                ),
+                [],
+            )?;
+        }
+    }
+    db.execute(
+        "UPDATE clipboard_history SET modified_at = last_copied_at WHERE modified_at = 0",
+        [],
+    )?;
+    db.execute(
+        "INSERT OR IGNORE INTO clipboard_sync_state (id, device_id) VALUES (1, ?)",
+        params![uuid::Uuid::new_v4().to_string()],
+    )?;
+    Ok(())
+}
+
+/// The `sync_seq` for a change made now. Counts up for good, so a number a peer has seen is
+/// never handed out again, even after the item that had it is deleted.
+fn next_sync_seq(db: &Connection) -> RusqliteResult<i64> {
+    db.execute(
+        "UPDATE clipboard_sync_state SET last_seq = last_seq + 1 WHERE id = 1",
+        [],
+    )?;
+    db.query_row(
+        "SELECT last_seq FROM clipboard_sync_state WHERE id = 1",
+        [],
+        |row| row.get(0),
+    )
+}
+
 pub struct ClipboardHistoryManager {
     store: Store,
     key: [u8; 32],
+    search_key: [u8; 32],
+    key_provider: Box<dyn KeyProvider>,
+    settings: ClipboardHistorySettings,
+    settings_path: PathBuf,
     pub image_dir: PathBuf,
 }
 
+fn preview_of(content_value: &str) -> String {
+    let mut preview_text = content_value
+        .chars()
+        .take(PREVIEW_LENGTH_CHARS)
+        .collect::<String>();
+    if content_value.chars().count() > PREVIEW_LENGTH_CHARS {
+        preview_text.push_str("...");
+    }
+    preview_text
+}
+
+/// The detected subtype stored for new items. Images and file lists aren't classified.
+fn subtype_of(content_type: &ContentType, content: &str) -> String {
+    match content_type {
+        ContentType::Image | ContentType::File => String::new(),
+        _ => detect(content)
+            .subtype
+            .map(|subtype| subtype.to_string())
+            .unwrap_or_default(),
+    }
+}
+
+/// Image hashes name files in the image directory, so they must be a plain SHA-256 in hex.
+fn check_image_hash(hash: &str) -> Result<(), AppError> {
+    if is_sha256_hex(hash) {
+        Ok(())
+    } else {
+        Err(AppError::ClipboardHistory("Invalid image hash".into()))
+    }
+}
+
+fn is_sha256_hex(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Whether an imported item carries the hash this device would have stored for it: the
+/// SHA-256 of the pixels of an image, or of the text of anything else. File items are hashed
+/// by the text they were copied as rather than by their paths, so only their form is checked.
+fn hash_matches_content(item: &ExportedItem, png: Option<&[u8]>) -> bool {
+    match (&item.content_type, png) {
+        (ContentType::Image, Some(png)) => image::load_from_memory(png)
+            .is_ok_and(|image| hex::encode(Sha256::digest(image.to_rgba8().as_raw())) == item.hash),
+        (ContentType::Image, None) => false,
+        (ContentType::File, _) => is_sha256_hex(&item.hash),
+        _ => text_hash(&item.content) == item.hash,
+    }
+}
+
 fn row_to_clipboard_item(row: &rusqlite::Row, key: &[u8; 32]) -> RusqliteResult<ClipboardItem> {
     let conditional_encrypted_content: Option<String> = row.get(10)?;
     let content_value = conditional_encrypted_content.and_then(|cec| decrypt(&cec, key).ok());
@@ -55,28 +219,47 @@ fn row_to_clipboard_item(row: &rusqlite::Row, key: &[u8; 32]) -> RusqliteResult<
         last_copied_at: DateTime::from_timestamp_nanos(last_ts),
         times_copied: row.get(6)?,
         is_pinned: row.get::<_, i32>(7)? == 1,
+        content_subtype: row
+            .get::<_, Option<String>>(11)?
+            .filter(|subtype| !subtype.is_empty()),
     })
 }
 
 impl ClipboardHistoryManager {
-    fn new(app_handle: &AppHandle) -> Result<Self, AppError> {
-        let data_dir = app_handle
-            .path()
-            .app_local_data_dir()
-            .map_err(|_| AppError::DirectoryNotFound)?;
+    fn new(app_handle: &AppHandle, key_provider: Box<dyn KeyProvider>) -> Result<Self, AppError> {
+        let data_dir = data_dir(app_handle)?;
         let image_dir = data_dir.join("clipboard_images");
         std::fs::create_dir_all(&image_dir)?;
 
         let store = Store::new(app_handle, "clipboard_history.sqlite")?;
-        store.init_table(CLIPBOARD_SCHEMA)?;
+        init_tables(&store)?;
 
-        let key = get_encryption_key()?;
+        let key = key_provider.load_key()?;
 
-        Ok(Self {
+        let settings_path = data_dir.join(SETTINGS_FILENAME);
+        let mut settings = read_settings(&settings_path).unwrap_or_else(|e| {
+            eprintln!("Failed to read clipboard history settings: {:?}", e);
+            ClipboardHistorySettings::default()
+        });
+        if settings.key_provider != key_provider.kind() {
+            settings.key_provider = key_provider.kind();
+            write_settings(&settings_path, &settings)?;
+        }
+
+        let mut manager = Self {
             store,
             key,
+            search_key: search::derive_search_key(&key),
+            key_provider,
+            settings,
+            settings_path,
             image_dir,
-        })
+        };
+        manager.finish_key_rotation()?;
+        manager.index_unindexed_items()?;
+        manager.classify_unclassified_items()?;
+        manager.migrate_plaintext_images()?;
+        Ok(manager)
     }
 
     #[cfg(test)]
@@ -85,25 +268,207 @@ impl ClipboardHistoryManager {
         std::fs::create_dir_all(&temp_dir)?;
 
         let store = Store::new_in_memory()?;
-        store.init_table(CLIPBOARD_SCHEMA)?;
+        init_tables(&store)?;
 
         let key: [u8; 32] = [0; 32];
 
         Ok(Self {
             store,
             key,
+            search_key: search::derive_search_key(&key),
+            key_provider: Box::new(super::key_provider::PlaintextProvider::new(&temp_dir)),
+            settings: ClipboardHistorySettings::default(),
+            settings_path: temp_dir.join(SETTINGS_FILENAME),
             image_dir: temp_dir,
         })
     }
 
+    pub fn settings(&self) -> &ClipboardHistorySettings {
+        &self.settings
+    }
+
+    /// The key provider can't be changed here, since that needs the key to be moved over;
+    /// see `set_key_provider`.
+    pub fn set_settings(&mut self, mut settings: ClipboardHistorySettings) -> Result<(), AppError> {
+        settings.key_provider = self.settings.key_provider;
+        write_settings(&self.settings_path, &settings)?;
+        self.settings = settings;
+        Ok(())
+    }
+
+    /// Stores the current key with `key_provider` and uses it from now on.
+    pub fn set_key_provider(&mut self, key_provider: Box<dyn KeyProvider>) -> Result<(), AppError> {
+        key_provider.store_key(&self.key)?;
+        let mut settings = self.settings.clone();
+        settings.key_provider = key_provider.kind();
+        write_settings(&self.settings_path, &settings)?;
+        self.settings = settings;
+        self.key_provider = key_provider;
+        Ok(())
+    }
+
+    pub fn is_excluded_app(&self, app_name: Option<&str>) -> bool {
+        self.settings.is_excluded_app(app_name)
+    }
+
+    pub fn image_path(&self, hash: &str) -> PathBuf {
+        self.image_dir.join(format!("{}.{}", hash, IMAGE_EXTENSION))
+    }
+
+    /// Encrypts a PNG and writes it to the image directory.
+    pub fn save_image(&self, hash: &str, png: &[u8]) -> Result<PathBuf, AppError> {
+        check_image_hash(hash)?;
+        let path = self.image_path(hash);
+        let temp_path = path.with_extension("tmp");
+        std::fs::write(&temp_path, encrypt_bytes(png, &self.key)?)?;
+        std::fs::rename(&temp_path, &path)?;
+        Ok(path)
+    }
+
+    /// Returns the decrypted PNG for the image item with `hash`.
+    pub fn read_image(&self, hash: &str) -> Result<Vec<u8>, AppError> {
+        check_image_hash(hash)?;
+        decrypt_bytes(&std::fs::read(self.image_path(hash))?, &self.key)
+    }
+
+    /// Encrypts the PNGs written by earlier versions and points their items at the new files.
+    fn migrate_plaintext_images(&self) -> Result<(), AppError> {
+        let mut migrated = 0;
+        for entry in std::fs::read_dir(&self.image_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(PLAINTEXT_IMAGE_EXTENSION) {
+                continue;
+            }
+            let Some(hash) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .filter(|hash| is_sha256_hex(hash))
+            else {
+                continue;
+            };
+
+            let new_path = self.save_image(hash, &std::fs::read(&path)?)?;
+            let new_value = new_path.to_string_lossy().to_string();
+            self.store.conn().execute(
+                "UPDATE clipboard_history SET encrypted_content = ?, encrypted_preview = ?, content_size_bytes = ?
+                 WHERE hash = ? AND content_type = 'image'",
+                params![
+                    encrypt(&new_value, &self.key)?,
+                    encrypt(&new_value, &self.key)?,
+                    new_value.len() as i64,
+                    hash
+                ],
+            )?;
+            std::fs::remove_file(&path)?;
+            migrated += 1;
+        }
+        if migrated > 0 {
+            println!("[ClipboardHistory] Encrypted {} stored images", migrated);
+        }
+        Ok(())
+    }
+
+    /// Builds index entries for rows stored before the search index existed.
+    fn index_unindexed_items(&self) -> Result<(), AppError> {
+        let db = self.store.conn();
+        let rows = {
+            let mut stmt = db.prepare(
+                "SELECT id, encrypted_content FROM clipboard_history
+                 WHERE content_type != 'image'
+                 AND id NOT IN (SELECT DISTINCT item_id FROM clipboard_search_index)",
+            )?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+                })?
+                .collect::<RusqliteResult<Vec<_>>>()?;
+            rows
+        };
+
+        for (id, encrypted_content) in rows {
+            if let Ok(content) = decrypt(&encrypted_content, &self.key) {
+                search::index_item(&db, id, &content, &self.search_key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills in `content_subtype` for items stored before content detection existed.
+    fn classify_unclassified_items(&self) -> Result<(), AppError> {
+        let db = self.store.conn();
+        let rows = {
+            let mut stmt = db.prepare(
+                "SELECT id, content_type, encrypted_content FROM clipboard_history
+                 WHERE content_subtype IS NULL",
+            )?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                    ))
+                })?
+                .collect::<RusqliteResult<Vec<_>>>()?;
+            rows
+        };
+
+        for (id, content_type, encrypted_content) in rows {
+            let content_type = ContentType::from_str(&content_type).unwrap_or(ContentType::Text);
+            // Like `row_to_clipboard_item`, an unreadable row doesn't fail startup; it just
+            // gets no subtype.
+            let subtype = match content_type {
+                ContentType::Image | ContentType::File => String::new(),
+                _ => decrypt(&encrypted_content, &self.key)
+                    .map(|content| subtype_of(&content_type, &content))
+                    .unwrap_or_default(),
+            };
+            db.execute(
+                "UPDATE clipboard_history SET content_subtype = ? WHERE id = ?",
+                params![subtype, id],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn encrypt_formats(&self, formats: &ClipboardFormats) -> Result<Option<String>, AppError> {
+        if formats.is_empty() {
+            return Ok(None);
+        }
+        let json =
+            serde_json::to_string(formats).map_err(|e| AppError::Serialization(e.to_string()))?;
+        Ok(Some(encrypt(&json, &self.key)?))
+    }
+
+    /// Stores a copied item, or bumps it if it's already in the history. Returns its id.
     pub fn add_item(
         &self,
         hash: String,
         content_type: ContentType,
         content_value: String,
         source_app_name: Option<String>,
-    ) -> Result<(), AppError> {
+    ) -> Result<i64, AppError> {
+        self.add_item_with_formats(
+            hash,
+            content_type,
+            content_value,
+            ClipboardFormats::default(),
+            source_app_name,
+        )
+    }
+
+    /// Like `add_item`, but also keeps the rich representations of the content. Re-copying
+    /// an existing item only replaces its formats when new ones were captured.
+    pub fn add_item_with_formats(
+        &self,
+        hash: String,
+        content_type: ContentType,
+        content_value: String,
+        formats: ClipboardFormats,
+        source_app_name: Option<String>,
+    ) -> Result<i64, AppError> {
         let db = self.store.conn();
+        let encrypted_formats = self.encrypt_formats(&formats)?;
         let now_nanos = Utc::now().timestamp_nanos_opt().unwrap_or_default();
 
         let existing_item: RusqliteResult<i64> = db.query_row(
@@ -112,52 +477,196 @@ impl ClipboardHistoryManager {
             |row| row.get(0),
         );
 
-        if let Ok(_id) = existing_item {
+        if let Ok(id) = existing_item {
             db.execute(
-                "UPDATE clipboard_history SET last_copied_at = ?, times_copied = times_copied + 1 WHERE hash = ?",
-                params![now_nanos, &hash],
+                "UPDATE clipboard_history SET last_copied_at = ?1, modified_at = ?1, sync_seq = ?2, times_copied = times_copied + 1, encrypted_formats = COALESCE(?3, encrypted_formats) WHERE hash = ?4",
+                params![now_nanos, next_sync_seq(&db)?, encrypted_formats, &hash],
             )?;
+            Ok(id)
         } else {
             let content_size_bytes = content_value.len() as i64;
-            let mut preview_text = content_value
-                .chars()
-                .take(PREVIEW_LENGTH_CHARS)
-                .collect::<String>();
-            if content_value.chars().count() > PREVIEW_LENGTH_CHARS {
-                preview_text.push_str("...");
-            }
-
-            let encrypted_preview = encrypt(&preview_text, &self.key)?;
+            let encrypted_preview = encrypt(&preview_of(&content_value), &self.key)?;
             let encrypted_content = encrypt(&content_value, &self.key)?;
             db.execute(
-                "INSERT INTO clipboard_history (hash, content_type, encrypted_content, encrypted_preview, content_size_bytes, source_app_name, first_copied_at, last_copied_at)
-                 VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
-                params![hash, content_type.as_str(), encrypted_content, encrypted_preview, content_size_bytes, source_app_name, now_nanos, now_nanos],
+                "INSERT INTO clipboard_history (hash, content_type, content_subtype, encrypted_content, encrypted_preview, encrypted_formats, content_size_bytes, source_app_name, first_copied_at, last_copied_at, modified_at, sync_seq)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                params![hash, content_type.as_str(), subtype_of(&content_type, &content_value), encrypted_content, encrypted_preview, encrypted_formats, content_size_bytes, source_app_name, now_nanos, now_nanos, now_nanos, next_sync_seq(&db)?],
             )?;
+
+            let id = db.last_insert_rowid();
+            if content_type != ContentType::Image {
+                search::index_item(&db, id, &content_value, &self.search_key)?;
+            }
+            Ok(id)
         }
-        Ok(())
+    }
+
+    /// Adds text that didn't come from the clipboard, hashed and classified like a copy.
+    fn add_text_item(&self, text: &str) -> Result<i64, AppError> {
+        let text = text.trim();
+        self.add_item(
+            text_hash(text),
+            detect(text).content_type,
+            text.to_string(),
+            None,
+        )
+    }
+
+    /// Records text highlighted in the PRIMARY selection. These items are kept apart from
+    /// the copied ones: only the `primary` filter lists them and they have their own
+    /// retention limits.
+    pub fn add_primary_item(
+        &self,
+        text: &str,
+        source_app_name: Option<String>,
+    ) -> Result<i64, AppError> {
+        let text = text.trim();
+        let id = self.add_item(
+            format!("{}{}", PRIMARY_HASH_PREFIX, text_hash(text)),
+            detect(text).content_type,
+            text.to_string(),
+            source_app_name,
+        )?;
+        self.store.conn().execute(
+            "UPDATE clipboard_history SET is_primary = 1 WHERE id = ?",
+            params![id],
+        )?;
+        Ok(id)
+    }
+
+    /// Joins the text of `ids` into a new entry and returns its id.
+    pub fn merge_items(
+        &self,
+        ids: &[i64],
+        separator: &str,
+        order: MergeOrder,
+    ) -> Result<i64, AppError> {
+        if ids.len() < 2 {
+            return Err(AppError::ClipboardHistory(
+                "Select at least two items to merge".into(),
+            ));
+        }
+        let mut items = ids
+            .iter()
+            .map(|&id| {
+                Ok((
+                    self.get_item(id)?.first_copied_at,
+                    self.get_text_content(id)?,
+                ))
+            })
+            .collect::<Result<Vec<_>, AppError>>()?;
+        match order {
+            MergeOrder::Selection => {}
+            MergeOrder::OldestFirst => items.sort_by_key(|(copied_at, _)| *copied_at),
+            MergeOrder::NewestFirst => {
+                items.sort_by_key(|(copied_at, _)| std::cmp::Reverse(*copied_at))
+            }
+        }
+        let merged = items
+            .into_iter()
+            .map(|(_, text)| text)
+            .collect::<Vec<_>>()
+            .join(separator);
+        self.add_text_item(&merged)
+    }
+
+    /// Adds every non-empty line of `id` as its own entry and returns their ids. The
+    /// original entry is kept.
+    pub fn split_item(&self, id: i64) -> Result<Vec<i64>, AppError> {
+        let content = self.get_text_content(id)?;
+        let lines: Vec<&str> = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        if lines.len() < 2 {
+            return Err(AppError::ClipboardHistory(
+                "Only entries with several lines can be split".into(),
+            ));
+        }
+        // Added last line first so the first line ends up most recent.
+        let mut ids = lines
+            .iter()
+            .rev()
+            .map(|line| self.add_text_item(line))
+            .collect::<Result<Vec<_>, AppError>>()?;
+        ids.reverse();
+        Ok(ids)
+    }
+
+    pub fn get_item(&self, id: i64) -> Result<ClipboardItem, AppError> {
+        let db = self.store.conn();
+        let key = self.key;
+        let item = db.query_row(
+            "SELECT h.id, h.hash, h.content_type, h.source_app_name, h.first_copied_at, h.last_copied_at, h.times_copied, h.is_pinned, h.content_size_bytes, h.encrypted_preview, CASE WHEN h.content_size_bytes <= ? THEN h.encrypted_content ELSE NULL END as conditional_encrypted_content, h.content_subtype FROM clipboard_history h WHERE h.id = ?",
+            params![INLINE_CONTENT_THRESHOLD_BYTES, id],
+            |row| row_to_clipboard_item(row, &key),
+        )?;
+        Ok(item)
     }
 
     pub fn get_items(
         &self,
         filter: String,
         search_term: Option<String>,
+        source_app_name: Option<String>,
         limit: u32,
         offset: u32,
     ) -> Result<Vec<ClipboardItem>, AppError> {
         let db = self.store.conn();
-        let mut query = "SELECT id, hash, content_type, source_app_name, first_copied_at, last_copied_at, times_copied, is_pinned, content_size_bytes, encrypted_preview, CASE WHEN content_size_bytes <= ? THEN encrypted_content ELSE NULL END as conditional_encrypted_content FROM clipboard_history".to_string();
+        let mut query = "SELECT h.id, h.hash, h.content_type, h.source_app_name, h.first_copied_at, h.last_copied_at, h.times_copied, h.is_pinned, h.content_size_bytes, h.encrypted_preview, CASE WHEN h.content_size_bytes <= ? THEN h.encrypted_content ELSE NULL END as conditional_encrypted_content, h.content_subtype FROM clipboard_history h".to_string();
         let mut where_clauses: Vec<String> = Vec::new();
         let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> =
             vec![Box::new(INLINE_CONTENT_THRESHOLD_BYTES)];
 
+        let tokens = search_term
+            .map(|term| search::query_tokens(&term, &self.search_key))
+            .unwrap_or_default();
+        let is_search = !tokens.is_empty();
+        if is_search {
+            let placeholders = vec!["?"; tokens.len()].join(", ");
+            query.push_str(&format!(
+                " JOIN (SELECT item_id, SUM(weight) AS score FROM clipboard_search_index WHERE token IN ({}) GROUP BY item_id HAVING COUNT(*) = ?) m ON m.item_id = h.id",
+                placeholders
+            ));
+            let token_count = tokens.len() as i64;
+            for token in tokens {
+                params_vec.push(Box::new(token));
+            }
+            params_vec.push(Box::new(token_count));
+        }
+
         match filter.as_str() {
-            "pinned" => where_clauses.push("is_pinned = 1".to_string()),
-            "text" => where_clauses.push("content_type = 'text'".to_string()),
-            "image" => where_clauses.push("content_type = 'image'".to_string()),
-            "link" => where_clauses.push("content_type = 'link'".to_string()),
-            "color" => where_clauses.push("content_type = 'color'".to_string()),
-            _ => {}
+            "pinned" => where_clauses.push("h.is_pinned = 1".to_string()),
+            "text" => where_clauses.push("h.content_type = 'text'".to_string()),
+            "image" => where_clauses.push("h.content_type = 'image'".to_string()),
+            "link" => where_clauses.push("h.content_type = 'link'".to_string()),
+            "color" => where_clauses.push("h.content_type = 'color'".to_string()),
+            "file" => where_clauses.push("h.content_type = 'file'".to_string()),
+            "primary" => where_clauses.push("h.is_primary = 1".to_string()),
+            "all" | "" => {}
+            // Detected subtypes; `code` matches every `code:<language>`.
+            subtype if is_subtype_filter(subtype) => {
+                where_clauses
+                    .push("(h.content_subtype = ? OR h.content_subtype LIKE ?)".to_string());
+                params_vec.push(Box::new(subtype.to_string()));
+                params_vec.push(Box::new(format!("{}:%", subtype)));
+            }
+            unknown => {
+                return Err(AppError::ClipboardHistory(format!(
+                    "Unknown filter: {}",
+                    unknown
+                )))
+            }
+        }
+
+        if filter != "primary" {
+            where_clauses.push("h.is_primary = 0".to_string());
+        }
+
+        if let Some(app_name) = source_app_name.filter(|name| !name.is_empty()) {
+            where_clauses.push("h.source_app_name = ? COLLATE NOCASE".to_string());
+            params_vec.push(Box::new(app_name));
         }
 
         if !where_clauses.is_empty() {
@@ -165,7 +674,11 @@ impl ClipboardHistoryManager {
             query.push_str(&where_clauses.join(" AND "));
         }
 
-        query.push_str(" ORDER BY last_copied_at DESC LIMIT ? OFFSET ?");
+        if is_search {
+            query.push_str(" ORDER BY m.score DESC, h.last_copied_at DESC LIMIT ? OFFSET ?");
+        } else {
+            query.push_str(" ORDER BY h.last_copied_at DESC LIMIT ? OFFSET ?");
+        }
         params_vec.push(Box::new(limit));
         params_vec.push(Box::new(offset));
 
@@ -175,31 +688,123 @@ impl ClipboardHistoryManager {
         let key = self.key;
         let items_iter = stmt.query_map(&params_ref[..], |row| row_to_clipboard_item(row, &key))?;
 
-        let mut all_items = items_iter.collect::<Result<Vec<_>, _>>()?;
+        let all_items = items_iter.collect::<Result<Vec<_>, _>>()?;
+        Ok(all_items)
+    }
 
-        if let Some(term) = search_term {
-            if !term.is_empty() {
-                let lower_term = term.to_lowercase();
-                all_items.retain(|item| {
-                    if let Some(preview) = &item.preview {
-                        preview.to_lowercase().contains(&lower_term)
-                    } else if let Some(value) = &item.content_value {
-                        value.to_lowercase().contains(&lower_term)
-                    } else {
-                        false
-                    }
-                });
-            }
+    /// Aggregates usage over the whole history. `days` limits the daily breakdown and
+    /// `limit` the item and app rankings.
+    pub fn get_stats(&self, days: u32, limit: u32) -> Result<ClipboardStats, AppError> {
+        let db = self.store.conn();
+        let key = self.key;
+
+        let (total_items, total_copies) = db.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(times_copied), 0) FROM clipboard_history",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        let most_reused = db
+            .prepare(
+                "SELECT h.id, h.hash, h.content_type, h.source_app_name, h.first_copied_at, h.last_copied_at, h.times_copied, h.is_pinned, h.content_size_bytes, h.encrypted_preview, CASE WHEN h.content_size_bytes <= ? THEN h.encrypted_content ELSE NULL END as conditional_encrypted_content, h.content_subtype FROM clipboard_history h
+                 WHERE h.times_copied > 1 AND h.is_pinned = 0
+                 ORDER BY h.times_copied DESC, h.last_copied_at DESC LIMIT ?",
+            )?
+            .query_map(params![INLINE_CONTENT_THRESHOLD_BYTES, limit], |row| {
+                row_to_clipboard_item(row, &key)
+            })?
+            .collect::<RusqliteResult<Vec<_>>>()?;
+
+        // A range reaching past what the timestamps can hold covers the whole history.
+        let since_nanos = Utc::now()
+            .checked_sub_signed(chrono::Duration::days(days.into()))
+            .and_then(|since| since.timestamp_nanos_opt())
+            .unwrap_or(i64::MIN);
+        let copies_per_day = db
+            .prepare(
+                "SELECT day, SUM(is_new), SUM(is_last) FROM (
+                    SELECT date(first_copied_at / 1000000000, 'unixepoch') AS day, 1 AS is_new, 0 AS is_last
+                    FROM clipboard_history WHERE first_copied_at >= ?1
+                    UNION ALL
+                    SELECT date(last_copied_at / 1000000000, 'unixepoch'), 0, 1
+                    FROM clipboard_history WHERE last_copied_at >= ?1
+                 ) GROUP BY day ORDER BY day",
+            )?
+            .query_map(params![since_nanos], |row| {
+                Ok(DailyCopies {
+                    date: row.get(0)?,
+                    new_items: row.get(1)?,
+                    items_copied: row.get(2)?,
+                })
+            })?
+            .collect::<RusqliteResult<Vec<_>>>()?;
+
+        let mut storage_by_type = db
+            .prepare(
+                "SELECT content_type, COUNT(*), COALESCE(SUM(content_size_bytes), 0)
+                 FROM clipboard_history GROUP BY content_type ORDER BY content_type",
+            )?
+            .query_map([], |row| {
+                Ok(TypeStorage {
+                    content_type: ContentType::from_str(&row.get::<_, String>(0)?)
+                        .unwrap_or(ContentType::Text),
+                    items: row.get(1)?,
+                    bytes: row.get(2)?,
+                })
+            })?
+            .collect::<RusqliteResult<Vec<_>>>()?;
+        // The content of an image item is its file path, so measure the files instead.
+        if let Some(images) = storage_by_type
+            .iter_mut()
+            .find(|storage| storage.content_type == ContentType::Image)
+        {
+            let hashes = db
+                .prepare("SELECT hash FROM clipboard_history WHERE content_type = 'image'")?
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<RusqliteResult<Vec<_>>>()?;
+            images.bytes = hashes
+                .iter()
+                .filter_map(|hash| std::fs::metadata(self.image_path(hash)).ok())
+                .map(|metadata| metadata.len() as i64)
+                .sum();
         }
 
-        Ok(all_items)
+        let top_source_apps = db
+            .prepare(
+                "SELECT source_app_name, COUNT(*), SUM(times_copied) FROM clipboard_history
+                 WHERE source_app_name IS NOT NULL AND source_app_name != ''
+                 GROUP BY source_app_name COLLATE NOCASE
+                 ORDER BY SUM(times_copied) DESC, COUNT(*) DESC LIMIT ?",
+            )?
+            .query_map(params![limit], |row| {
+                Ok(SourceAppUsage {
+                    source_app_name: row.get(0)?,
+                    items: row.get(1)?,
+                    copies: row.get(2)?,
+                })
+            })?
+            .collect::<RusqliteResult<Vec<_>>>()?;
+
+        Ok(ClipboardStats {
+            total_items,
+            total_copies,
+            most_reused,
+            copies_per_day,
+            storage_by_type,
+            top_source_apps,
+        })
     }
 
-    pub fn get_content_by_offset(&self, offset: u32) -> Result<Option<String>, AppError> {
+    /// Returns the text of the item `offset` places back in the history of `selection`.
+    pub fn get_content_by_offset(
+        &self,
+        selection: Selection,
+        offset: u32,
+    ) -> Result<Option<String>, AppError> {
         let db = self.store.conn();
         let res: rusqlite::Result<String> = db.query_row(
-            "SELECT encrypted_content FROM clipboard_history ORDER BY last_copied_at DESC LIMIT 1 OFFSET ?",
-            params![offset],
+            "SELECT encrypted_content FROM clipboard_history WHERE is_primary = ? ORDER BY last_copied_at DESC LIMIT 1 OFFSET ?",
+            params![(selection == Selection::Primary) as i32, offset],
             |row| row.get(0),
         );
 
@@ -220,46 +825,1224 @@ impl ClipboardHistoryManager {
         decrypt(&encrypted_content, &self.key)
     }
 
+    /// Like `get_item_content`, but fails for images, whose content is only a file path.
+    pub fn get_text_content(&self, id: i64) -> Result<String, AppError> {
+        let db = self.store.conn();
+        let (content_type, encrypted_content): (String, String) = db.query_row(
+            "SELECT content_type, encrypted_content FROM clipboard_history WHERE id = ?",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        if content_type == ContentType::Image.as_str() {
+            return Err(AppError::ClipboardHistory(
+                "Images can't be pasted as text".into(),
+            ));
+        }
+        decrypt(&encrypted_content, &self.key)
+    }
+
+    pub fn get_item_formats(&self, id: i64) -> Result<ClipboardFormats, AppError> {
+        let db = self.store.conn();
+        let encrypted_formats: Option<String> = db.query_row(
+            "SELECT encrypted_formats FROM clipboard_history WHERE id = ?",
+            params![id],
+            |row| row.get(0),
+        )?;
+        match encrypted_formats {
+            Some(encrypted) => serde_json::from_str(&decrypt(&encrypted, &self.key)?)
+                .map_err(|e| AppError::Serialization(e.to_string())),
+            None => Ok(ClipboardFormats::default()),
+        }
+    }
+
+    /// The most recent image the OCR worker hasn't processed yet.
+    pub fn next_image_without_ocr(&self) -> Result<Option<(i64, String)>, AppError> {
+        let res = self.store.conn().query_row(
+            "SELECT id, hash FROM clipboard_history
+             WHERE content_type = 'image' AND encrypted_ocr_text IS NULL
+             ORDER BY last_copied_at DESC LIMIT 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        );
+        match res {
+            Ok(job) => Ok(Some(job)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn set_ocr_text(&self, id: i64, text: &str) -> Result<(), AppError> {
+        let db = self.store.conn();
+        db.execute(
+            "UPDATE clipboard_history SET encrypted_ocr_text = ? WHERE id = ?",
+            params![encrypt(text, &self.key)?, id],
+        )?;
+        search::index_item(&db, id, text, &self.search_key)?;
+        Ok(())
+    }
+
     pub fn item_was_copied(&self, id: i64) -> RusqliteResult<usize> {
-        self.store.conn().execute(
-            "UPDATE clipboard_history SET last_copied_at = ?, times_copied = times_copied + 1 WHERE id = ?",
-            params![Utc::now().timestamp_nanos_opt().unwrap_or_default(), id],
+        let db = self.store.conn();
+        db.execute(
+            "UPDATE clipboard_history SET last_copied_at = ?1, modified_at = ?1, sync_seq = ?2, times_copied = times_copied + 1 WHERE id = ?3",
+            params![
+                Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+                next_sync_seq(&db)?,
+                id
+            ],
         )
     }
 
-    pub fn delete_item(&self, id: i64) -> RusqliteResult<usize> {
-        self.store
-            .conn()
-            .execute("DELETE FROM clipboard_history WHERE id = ?", params![id])
+    pub fn delete_item(&self, id: i64) -> Result<usize, AppError> {
+        let db = self.store.conn();
+        search::remove_item(&db, id)?;
+        Ok(db.execute("DELETE FROM clipboard_history WHERE id = ?", params![id])?)
     }
 
     pub fn toggle_pin(&self, id: i64) -> RusqliteResult<usize> {
-        self.store.conn().execute(
-            "UPDATE clipboard_history SET is_pinned = 1 - is_pinned WHERE id = ?",
-            params![id],
+        let db = self.store.conn();
+        db.execute(
+            "UPDATE clipboard_history SET is_pinned = 1 - is_pinned, modified_at = ?, sync_seq = ? WHERE id = ?",
+            params![
+                Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+                next_sync_seq(&db)?,
+                id
+            ],
         )
     }
 
-    pub fn clear_all(&self) -> RusqliteResult<usize> {
+    pub fn clear_all(&self) -> Result<usize, AppError> {
+        let db = self.store.conn();
+        let deleted = db.execute("DELETE FROM clipboard_history WHERE is_pinned = 0", [])?;
+        search::remove_orphans(&db)?;
+        Ok(deleted)
+    }
+
+    /// Re-encrypts every item, the search index and all image files under a fresh key. The
+    /// items are committed together with the new key, encrypted with the old one, before the
+    /// key provider gets the new key; `finish_key_rotation` completes the rest.
+    pub fn rotate_key(&mut self) -> Result<(), AppError> {
+        let new_key: [u8; 32] = rand::random();
+        let new_search_key = search::derive_search_key(&new_key);
+        let db = self.store.conn();
+        let tx = db.unchecked_transaction()?;
+
+        let rows = {
+            let mut stmt = tx.prepare(
+                "SELECT id, hash, content_type, encrypted_content, encrypted_preview, encrypted_formats, encrypted_ocr_text
+                 FROM clipboard_history",
+            )?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, Option<String>>(4)?,
+                        row.get::<_, Option<String>>(5)?,
+                        row.get::<_, Option<String>>(6)?,
+                    ))
+                })?
+                .collect::<RusqliteResult<Vec<_>>>()?;
+            rows
+        };
+
+        let reencrypt = |encrypted: &str| encrypt(&decrypt(encrypted, &self.key)?, &new_key);
+        let sync_key: Option<String> = tx
+            .query_row(
+                "SELECT encrypted_key FROM clipboard_sync_key WHERE id = 1",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(sync_key) = sync_key {
+            tx.execute(
+                "UPDATE clipboard_sync_key SET encrypted_key = ? WHERE id = 1",
+                params![reencrypt(&sync_key)?],
+            )?;
+        }
+        tx.execute("DELETE FROM clipboard_search_index", [])?;
+        let mut image_hashes = Vec::new();
+        for (id, hash, content_type, content, preview, formats, ocr_text) in rows {
+            tx.execute(
+                "UPDATE clipboard_history SET encrypted_content = ?, encrypted_preview = ?, encrypted_formats = ?, encrypted_ocr_text = ? WHERE id = ?",
+                params![
+                    reencrypt(&content)?,
+                    preview.as_deref().map(reencrypt).transpose()?,
+                    formats.as_deref().map(reencrypt).transpose()?,
+                    ocr_text.as_deref().map(reencrypt).transpose()?,
+                    id
+                ],
+            )?;
+            if content_type == ContentType::Image.as_str() {
+                if let Some(ocr_text) = ocr_text {
+                    let plaintext = decrypt(&ocr_text, &self.key)?;
+                    search::index_item(&tx, id, &plaintext, &new_search_key)?;
+                }
+                image_hashes.push(hash);
+            } else {
+                let plaintext = decrypt(&content, &self.key)?;
+                search::index_item(&tx, id, &plaintext, &new_search_key)?;
+            }
+        }
+
+        // Stage the re-encrypted images next to the originals so a failure leaves both intact.
+        let mut staged = Vec::new();
+        let staging = image_hashes.iter().try_for_each(|hash| {
+            let path = self.image_path(hash);
+            if !path.exists() {
+                return Ok(());
+            }
+            let png = decrypt_bytes(&std::fs::read(&path)?, &self.key)?;
+            let staged_path = path.with_extension(STAGED_IMAGE_EXTENSION);
+            std::fs::write(&staged_path, encrypt_bytes(&png, &new_key)?)?;
+            staged.push((staged_path, path));
+            Ok::<(), AppError>(())
+        });
+        let discard_staged = |staged: &[(PathBuf, PathBuf)]| {
+            for (staged_path, _) in staged {
+                let _ = std::fs::remove_file(staged_path);
+            }
+        };
+        let committed = staging.and_then(|_| {
+            tx.execute(
+                "INSERT OR REPLACE INTO clipboard_key_rotation (id, encrypted_key) VALUES (1, ?)",
+                params![encrypt(&hex::encode(new_key), &self.key)?],
+            )?;
+            Ok(tx.commit()?)
+        });
+        if let Err(e) = committed {
+            discard_staged(&staged);
+            return Err(e);
+        }
+
+        drop(db);
+        self.key = new_key;
+        self.search_key = new_search_key;
+        self.finish_key_rotation()
+    }
+
+    /// Completes a committed key rotation: switches to its key if the key provider still
+    /// holds the old one, moves the staged images into place and stores the new key. Staged
+    /// images of a rotation that never committed are discarded. Runs on startup too, in case
+    /// the app stopped half way through.
+    fn finish_key_rotation(&mut self) -> Result<(), AppError> {
+        let pending: Option<String> = self
+            .store
+            .conn()
+            .query_row(
+                "SELECT encrypted_key FROM clipboard_key_rotation WHERE id = 1",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let mut staged = Vec::new();
+        for entry in std::fs::read_dir(&self.image_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) == Some(STAGED_IMAGE_EXTENSION) {
+                staged.push(path);
+            }
+        }
+
+        let Some(pending) = pending else {
+            for path in staged {
+                std::fs::remove_file(path)?;
+            }
+            return Ok(());
+        };
+        // Only the replaced key can read the pending one. If it doesn't, the current key is
+        // already the new one.
+        let new_key: Option<[u8; 32]> = decrypt(&pending, &self.key)
+            .ok()
+            .and_then(|key| hex::decode(key).ok())
+            .and_then(|key| key.try_into().ok());
+        if let Some(new_key) = new_key {
+            self.key = new_key;
+            self.search_key = search::derive_search_key(&new_key);
+        }
+        for path in staged {
+            std::fs::rename(&path, path.with_extension(IMAGE_EXTENSION))?;
+        }
+        self.key_provider.store_key(&self.key)?;
         self.store
             .conn()
-            .execute("DELETE FROM clipboard_history WHERE is_pinned = 0", [])
+            .execute("DELETE FROM clipboard_key_rotation", [])?;
+        Ok(())
+    }
+
+    pub fn export_items(&self) -> Result<Vec<ExportedItem>, AppError> {
+        Ok(self
+            .export_changes(i64::MIN, false, usize::MAX, usize::MAX)?
+            .0)
+    }
+
+    /// Exports up to `max_items` items changed on this device after the `sync_seq` `after`,
+    /// optionally only the pinned ones, along with the `sync_seq` to continue from. The page
+    /// stops before the item that would take its JSON past `max_bytes`; an item that doesn't
+    /// fit on a page of its own is left out. Highlighted PRIMARY text stays on this machine.
+    pub fn export_changes(
+        &self,
+        after: i64,
+        pinned_only: bool,
+        max_items: usize,
+        max_bytes: usize,
+    ) -> Result<(Vec<ExportedItem>, i64), AppError> {
+        let rows = {
+            let db = self.store.conn();
+            let mut stmt = db.prepare(
+                "SELECT hash, content_type, encrypted_content, encrypted_formats, source_app_name, first_copied_at, last_copied_at, times_copied, is_pinned, encrypted_ocr_text, modified_at, sync_seq
+                 FROM clipboard_history WHERE sync_seq > ? AND (is_pinned = 1 OR ? = 0) AND is_primary = 0
+                 ORDER BY sync_seq LIMIT ?",
+            )?;
+            let max_items = i64::try_from(max_items).unwrap_or(i64::MAX);
+            let rows = stmt
+                .query_map(params![after, pinned_only as i32, max_items], |row| {
+                    Ok((
+                        ExportedItem {
+                            hash: row.get(0)?,
+                            content_type: ContentType::from_str(&row.get::<_, String>(1)?)
+                                .unwrap_or(ContentType::Text),
+                            content: String::new(),
+                            formats: ClipboardFormats::default(),
+                            source_app_name: row.get(4)?,
+                            first_copied_at: row.get(5)?,
+                            last_copied_at: row.get(6)?,
+                            times_copied: row.get(7)?,
+                            is_pinned: row.get::<_, i32>(8)? == 1,
+                            modified_at: row.get(10)?,
+                            image: None,
+                            ocr_text: None,
+                        },
+                        row.get::<_, String>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                        row.get::<_, Option<String>>(9)?,
+                        row.get::<_, i64>(11)?,
+                    ))
+                })?
+                .collect::<RusqliteResult<Vec<_>>>()?;
+            rows
+        };
+
+        let mut items = Vec::with_capacity(rows.len());
+        let mut cursor = after;
+        let mut page_bytes = 0;
+        for (mut item, encrypted_content, encrypted_formats, encrypted_ocr_text, sync_seq) in rows {
+            item.content = decrypt(&encrypted_content, &self.key)?;
+            item.ocr_text = encrypted_ocr_text
+                .map(|encrypted| decrypt(&encrypted, &self.key))
+                .transpose()?;
+            if let Some(encrypted) = encrypted_formats {
+                item.formats = serde_json::from_str(&decrypt(&encrypted, &self.key)?)
+                    .map_err(|e| AppError::Serialization(e.to_string()))?;
+            }
+            if item.content_type == ContentType::Image {
+                match self.read_image(&item.hash) {
+                    Ok(png) => item.set_image_bytes(&png),
+                    Err(e) => {
+                        eprintln!("Skipping image {} in export: {:?}", item.hash, e);
+                        cursor = sync_seq;
+                        continue;
+                    }
+                }
+            }
+            if max_bytes != usize::MAX {
+                let item_bytes = serde_json::to_vec(&item)
+                    .map_err(|e| AppError::Serialization(e.to_string()))?
+                    .len();
+                if item_bytes > max_bytes {
+                    eprintln!(
+                        "Skipping item {} in export: {} bytes is more than a page holds",
+                        item.hash, item_bytes
+                    );
+                    cursor = sync_seq;
+                    continue;
+                }
+                if page_bytes + item_bytes > max_bytes {
+                    break;
+                }
+                page_bytes += item_bytes;
+            }
+            cursor = sync_seq;
+            items.push(item);
+        }
+        Ok((items, cursor))
+    }
+
+    /// Adds exported items that aren't in the history yet and merges the usage counters of
+    /// the ones that are. The pin is taken from whichever side changed the item last. Items
+    /// whose hash doesn't match their content are skipped. Returns the number of new items.
+    pub fn import_items(&self, items: Vec<ExportedItem>) -> Result<u32, AppError> {
+        let mut imported = 0;
+        for mut item in items {
+            let png = item.image_bytes()?;
+            if !hash_matches_content(&item, png.as_deref()) {
+                eprintln!("Skipping imported item {}: hash doesn't match", item.hash);
+                continue;
+            }
+            if let Some(png) = png {
+                item.content = self
+                    .save_image(&item.hash, &png)?
+                    .to_string_lossy()
+                    .to_string();
+            }
+
+            // Exports written before `modified_at` existed don't carry it.
+            let modified_at = item.modified_at.max(item.last_copied_at);
+            let db = self.store.conn();
+            let exists: bool = db.query_row(
+                "SELECT EXISTS(SELECT 1 FROM clipboard_history WHERE hash = ?)",
+                params![item.hash],
+                |row| row.get(0),
+            )?;
+            if exists {
+                // Only a change gets a new `sync_seq`, so an item doesn't bounce between
+                // peers forever.
+                db.execute(
+                    "UPDATE clipboard_history SET
+                        first_copied_at = MIN(first_copied_at, ?1),
+                        last_copied_at = MAX(last_copied_at, ?2),
+                        times_copied = MAX(times_copied, ?3),
+                        is_pinned = CASE WHEN ?5 > modified_at THEN ?4 ELSE is_pinned END,
+                        modified_at = MAX(modified_at, ?5),
+                        sync_seq = ?6
+                     WHERE hash = ?7 AND (first_copied_at > ?1 OR last_copied_at < ?2
+                        OR times_copied < ?3 OR modified_at < ?5)",
+                    params![
+                        item.first_copied_at,
+                        item.last_copied_at,
+                        item.times_copied,
+                        item.is_pinned as i32,
+                        modified_at,
+                        next_sync_seq(&db)?,
+                        item.hash
+                    ],
+                )?;
+                continue;
+            }
+
+            db.execute(
+                "INSERT INTO clipboard_history (hash, content_type, content_subtype, encrypted_content, encrypted_preview, encrypted_formats, content_size_bytes, source_app_name, first_copied_at, last_copied_at, times_copied, is_pinned, encrypted_ocr_text, modified_at, sync_seq)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    item.hash,
+                    item.content_type.as_str(),
+                    subtype_of(&item.content_type, &item.content),
+                    encrypt(&item.content, &self.key)?,
+                    encrypt(&preview_of(&item.content), &self.key)?,
+                    self.encrypt_formats(&item.formats)?,
+                    item.content.len() as i64,
+                    item.source_app_name,
+                    item.first_copied_at,
+                    item.last_copied_at,
+                    item.times_copied,
+                    item.is_pinned as i32,
+                    item.ocr_text
+                        .as_deref()
+                        .map(|text| encrypt(text, &self.key))
+                        .transpose()?,
+                    modified_at,
+                    next_sync_seq(&db)?
+                ],
+            )?;
+            let id = db.last_insert_rowid();
+            if item.content_type != ContentType::Image {
+                search::index_item(&db, id, &item.content, &self.search_key)?;
+            } else if let Some(ocr_text) = &item.ocr_text {
+                search::index_item(&db, id, ocr_text, &self.search_key)?;
+            }
+            imported += 1;
+        }
+        Ok(imported)
+    }
+
+    /// Identifies this device to sync peers. Kept across restarts, so peers carry on from
+    /// where they were.
+    pub fn sync_device_id(&self) -> Result<String, AppError> {
+        Ok(self.store.conn().query_row(
+            "SELECT device_id FROM clipboard_sync_state WHERE id = 1",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// The key shared with paired sync devices, if this device has been paired.
+    pub fn sync_key(&self) -> Result<Option<[u8; 32]>, AppError> {
+        let encrypted: Option<String> = self
+            .store
+            .conn()
+            .query_row(
+                "SELECT encrypted_key FROM clipboard_sync_key WHERE id = 1",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        encrypted
+            .map(|encrypted| {
+                hex::decode(decrypt(&encrypted, &self.key)?)
+                    .ok()
+                    .and_then(|key| key.try_into().ok())
+                    .ok_or_else(|| AppError::ClipboardHistory("Invalid sync key".into()))
+            })
+            .transpose()
+    }
+
+    pub fn set_sync_key(&self, sync_key: Option<&[u8; 32]>) -> Result<(), AppError> {
+        let db = self.store.conn();
+        match sync_key {
+            Some(sync_key) => db.execute(
+                "INSERT OR REPLACE INTO clipboard_sync_key (id, encrypted_key) VALUES (1, ?)",
+                params![encrypt(&hex::encode(sync_key), &self.key)?],
+            )?,
+            None => db.execute("DELETE FROM clipboard_sync_key", [])?,
+        };
+        Ok(())
+    }
+
+    pub fn export_to_file(&self, path: &Path, passphrase: &str) -> Result<u32, AppError> {
+        let items = self.export_items()?;
+        std::fs::write(path, backup::seal(&items, passphrase)?)?;
+        Ok(items.len() as u32)
+    }
+
+    pub fn import_from_file(&self, path: &Path, passphrase: &str) -> Result<u32, AppError> {
+        let items = backup::open(&std::fs::read(path)?, passphrase)?;
+        self.import_items(items)
+    }
+
+    /// Applies the retention settings to unpinned items, and the PRIMARY ones to highlighted
+    /// text, then deletes image files that no longer belong to any item.
+    pub fn prune(&self) -> Result<PruneReport, AppError> {
+        let db = self.store.conn();
+        let now_nanos = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        let mut report = PruneReport::default();
+
+        let candidates = {
+            let mut stmt = db.prepare(
+                "SELECT id, hash, content_type, last_copied_at, content_size_bytes, is_primary FROM clipboard_history
+                 WHERE is_pinned = 0 ORDER BY last_copied_at DESC",
+            )?;
+            let candidates = stmt
+                .query_map([], |row| {
+                    let hash: String = row.get(1)?;
+                    let content_type = ContentType::from_str(&row.get::<_, String>(2)?)
+                        .unwrap_or(ContentType::Text);
+                    // the stored size of an image is its path, so weigh it by the file instead
+                    let size_bytes = if content_type == ContentType::Image {
+                        std::fs::metadata(self.image_path(&hash)).map_or(0, |m| m.len())
+                    } else {
+                        row.get::<_, Option<i64>>(4)?.unwrap_or(0).max(0) as u64
+                    };
+                    Ok((
+                        PruneCandidate {
+                            id: row.get(0)?,
+                            content_type,
+                            last_copied_at: row.get(3)?,
+                            size_bytes,
+                        },
+                        row.get::<_, i32>(5)? == 1,
+                    ))
+                })?
+                .collect::<RusqliteResult<Vec<_>>>()?;
+            candidates
+        };
+
+        let (primary, candidates): (Vec<_>, Vec<_>) = candidates
+            .into_iter()
+            .partition(|(_, is_primary)| *is_primary);
+        let primary: Vec<PruneCandidate> = primary.into_iter().map(|(c, _)| c).collect();
+        let mut candidates: Vec<PruneCandidate> = candidates.into_iter().map(|(c, _)| c).collect();
+        let mut expired = self.settings.retention.expired_ids(&candidates, now_nanos);
+        expired.extend(
+            self.settings
+                .primary
+                .retention
+                .expired_ids(&primary, now_nanos),
+        );
+        candidates.extend(primary);
+        if !expired.is_empty() {
+            let tx = db.unchecked_transaction()?;
+            for candidate in candidates.iter().filter(|c| expired.contains(&c.id)) {
+                search::remove_item(&tx, candidate.id)?;
+                tx.execute(
+                    "DELETE FROM clipboard_history WHERE id = ?",
+                    params![candidate.id],
+                )?;
+                report.items_removed += 1;
+                // image files are reclaimed (and counted) by the orphan sweep below
+                if candidate.content_type != ContentType::Image {
+                    report.bytes_reclaimed += candidate.size_bytes;
+                }
+            }
+            tx.commit()?;
+        }
+
+        self.remove_orphaned_images(&db, &mut report)?;
+        Ok(report)
+    }
+
+    fn remove_orphaned_images(
+        &self,
+        db: &Connection,
+        report: &mut PruneReport,
+    ) -> Result<(), AppError> {
+        let referenced: HashSet<String> = {
+            let mut stmt =
+                db.prepare("SELECT hash FROM clipboard_history WHERE content_type = 'image'")?;
+            let hashes = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<RusqliteResult<HashSet<String>>>()?;
+            hashes
+        };
+
+        for entry in std::fs::read_dir(&self.image_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(IMAGE_EXTENSION) {
+                continue;
+            }
+            let is_orphan = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .is_some_and(|stem| !referenced.contains(stem));
+            if !is_orphan {
+                continue;
+            }
+            let Ok(metadata) = std::fs::metadata(&path) else {
+                continue;
+            };
+            let is_recent = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_none_or(|age| age < ORPHAN_GRACE_PERIOD);
+            if !is_recent {
+                let size = metadata.len();
+                match std::fs::remove_file(&path) {
+                    Ok(_) => {
+                        report.files_removed += 1;
+                        report.bytes_reclaimed += size;
+                    }
+                    Err(e) => eprintln!("Failed to remove orphaned image {:?}: {:?}", path, e),
+                }
+            }
+        }
+        Ok(())
     }
 }
 
 pub static MANAGER: Lazy<Mutex<Option<ClipboardHistoryManager>>> = Lazy::new(|| Mutex::new(None));
 pub static INTERNAL_CLIPBOARD_CHANGE: AtomicBool = AtomicBool::new(false);
 
-pub fn init(app_handle: AppHandle) {
+static APP_HANDLE: OnceCell<AppHandle> = OnceCell::new();
+static BACKGROUND_TASKS: Once = Once::new();
+static KEY_STATUS: Lazy<Mutex<KeyProviderStatus>> = Lazy::new(|| {
+    Mutex::new(KeyProviderStatus {
+        provider: KeyProviderKind::default(),
+        unlocked: false,
+        error: None,
+    })
+});
+
+fn data_dir(app_handle: &AppHandle) -> Result<PathBuf, AppError> {
+    app_handle
+        .path()
+        .app_local_data_dir()
+        .map_err(|_| AppError::DirectoryNotFound)
+}
+
+fn set_key_status(provider: KeyProviderKind, error: Option<String>) {
+    *KEY_STATUS.lock().unwrap() = KeyProviderStatus {
+        provider,
+        unlocked: error.is_none() && MANAGER.lock().unwrap().is_some(),
+        error,
+    };
+}
+
+pub fn key_status() -> KeyProviderStatus {
+    KEY_STATUS.lock().unwrap().clone()
+}
+
+/// Creates the manager with the given key provider and starts monitoring. Does nothing if
+/// the history is already running.
+pub fn start(kind: KeyProviderKind, passphrase: Option<String>) -> Result<(), AppError> {
+    let app_handle = APP_HANDLE
+        .get()
+        .ok_or_else(|| AppError::ClipboardHistory("Clipboard history not initialized".into()))?;
     let mut manager_guard = MANAGER.lock().unwrap();
-    if manager_guard.is_none() {
-        match ClipboardHistoryManager::new(&app_handle) {
-            Ok(manager) => {
-                *manager_guard = Some(manager);
-                drop(manager_guard);
-                start_monitoring(app_handle);
-            }
-            Err(e) => eprintln!("Failed to create ClipboardHistoryManager: {:?}", e),
+    if manager_guard.is_some() {
+        return Ok(());
+    }
+
+    let manager = create_provider(kind, &data_dir(app_handle)?, passphrase)
+        .and_then(|provider| ClipboardHistoryManager::new(app_handle, provider));
+    match manager {
+        Ok(manager) => {
+            *manager_guard = Some(manager);
+            drop(manager_guard);
+            set_key_status(kind, None);
+            BACKGROUND_TASKS.call_once(|| {
+                start_monitoring(app_handle.clone());
+                start_pruning();
+                start_ocr_worker();
+                start_sync();
+                start_primary_monitoring();
+            });
+            Ok(())
+        }
+        Err(e) => {
+            drop(manager_guard);
+            set_key_status(kind, Some(e.to_string()));
+            Err(e)
+        }
+    }
+}
+
+/// Switches to another key provider. A running history moves its key over; otherwise the
+/// history is started with the new provider, which creates a fresh key if it has none, so
+/// items encrypted with an unreachable key stay unreadable.
+pub fn switch_key_provider(
+    kind: KeyProviderKind,
+    passphrase: Option<String>,
+) -> Result<(), AppError> {
+    let mut manager_guard = MANAGER.lock().unwrap();
+    if let Some(manager) = manager_guard.as_mut() {
+        let app_handle = APP_HANDLE.get().ok_or_else(|| {
+            AppError::ClipboardHistory("Clipboard history not initialized".into())
+        })?;
+        manager.set_key_provider(create_provider(kind, &data_dir(app_handle)?, passphrase)?)?;
+        drop(manager_guard);
+        set_key_status(kind, None);
+        Ok(())
+    } else {
+        drop(manager_guard);
+        start(kind, passphrase)
+    }
+}
+
+pub fn init(app_handle: AppHandle) {
+    let kind = data_dir(&app_handle)
+        .and_then(|dir| read_settings(&dir.join(SETTINGS_FILENAME)))
+        .map(|settings| settings.key_provider)
+        .unwrap_or_default();
+    let _ = APP_HANDLE.set(app_handle);
+
+    // The passphrase has to come from the user, see `history_unlock`.
+    if kind == KeyProviderKind::PassphraseFile {
+        set_key_status(kind, None);
+        return;
+    }
+    if let Err(e) = start(kind, None) {
+        eprintln!("Failed to create ClipboardHistoryManager: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_items_filters_by_source_app() {
+        let manager = ClipboardHistoryManager::new_for_test().unwrap();
+        manager
+            .add_item(
+                "a".into(),
+                ContentType::Text,
+                "from the browser".into(),
+                Some("firefox".into()),
+            )
+            .unwrap();
+        manager
+            .add_item(
+                "b".into(),
+                ContentType::Text,
+                "from the terminal".into(),
+                Some("kitty".into()),
+            )
+            .unwrap();
+
+        let items = manager
+            .get_items("all".into(), None, Some("Firefox".into()), 10, 0)
+            .unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].hash, "a");
+        assert_eq!(items[0].source_app_name.as_deref(), Some("firefox"));
+
+        let all = manager.get_items("all".into(), None, None, 10, 0).unwrap();
+        assert_eq!(all.len(), 2);
+    }
+
+    #[test]
+    fn test_merge_items_orders_and_dedupes() {
+        let manager = ClipboardHistoryManager::new_for_test().unwrap();
+        let first = manager
+            .add_item("a".into(), ContentType::Text, "alpha".into(), None)
+            .unwrap();
+        let second = manager
+            .add_item("b".into(), ContentType::Text, "beta".into(), None)
+            .unwrap();
+
+        let merged = manager
+            .merge_items(&[second, first], ", ", MergeOrder::OldestFirst)
+            .unwrap();
+        assert_eq!(manager.get_item_content(merged).unwrap(), "alpha, beta");
+
+        let reversed = manager
+            .merge_items(&[first, second], ", ", MergeOrder::NewestFirst)
+            .unwrap();
+        assert_eq!(manager.get_item_content(reversed).unwrap(), "beta, alpha");
+
+        let again = manager
+            .merge_items(&[first, second], ", ", MergeOrder::Selection)
+            .unwrap();
+        assert_eq!(again, merged);
+        assert_eq!(manager.get_item(merged).unwrap().times_copied, 2);
+    }
+
+    #[test]
+    fn test_split_item_adds_each_line() {
+        let manager = ClipboardHistoryManager::new_for_test().unwrap();
+        let id = manager
+            .add_item(
+                "list".into(),
+                ContentType::Text,
+                "one\n\n  https://example.com  \nthree".into(),
+                None,
+            )
+            .unwrap();
+
+        let ids = manager.split_item(id).unwrap();
+        let contents: Vec<_> = ids
+            .iter()
+            .map(|id| manager.get_item_content(*id).unwrap())
+            .collect();
+        assert_eq!(contents, ["one", "https://example.com", "three"]);
+        assert_eq!(
+            manager.get_item(ids[1]).unwrap().content_type,
+            ContentType::Link
+        );
+        assert!(manager.split_item(ids[0]).is_err());
+    }
+
+    #[test]
+    fn test_get_stats_aggregates_usage() {
+        let manager = ClipboardHistoryManager::new_for_test().unwrap();
+        for (hash, content, app) in [
+            ("a", "alpha", Some("firefox")),
+            ("b", "beta!", Some("Firefox")),
+            ("c", "gamma", Some("kitty")),
+            ("d", "delta", None),
+        ] {
+            manager
+                .add_item(
+                    hash.into(),
+                    ContentType::Text,
+                    content.into(),
+                    app.map(Into::into),
+                )
+                .unwrap();
         }
+        manager
+            .add_item("a".into(), ContentType::Text, "alpha".into(), None)
+            .unwrap();
+        manager
+            .add_item("a".into(), ContentType::Text, "alpha".into(), None)
+            .unwrap();
+        manager
+            .add_item("c".into(), ContentType::Text, "gamma".into(), None)
+            .unwrap();
+        manager
+            .add_item("#fff".into(), ContentType::Color, "#fff".into(), None)
+            .unwrap();
+
+        let stats = manager.get_stats(30, 10).unwrap();
+        assert_eq!(stats.total_items, 5);
+        assert_eq!(
+            manager
+                .get_stats(u32::MAX, 10)
+                .unwrap()
+                .copies_per_day
+                .len(),
+            1
+        );
+        assert_eq!(stats.total_copies, 8);
+
+        let reused: Vec<_> = stats
+            .most_reused
+            .iter()
+            .map(|item| item.hash.as_str())
+            .collect();
+        assert_eq!(reused, ["a", "c"]);
+
+        assert_eq!(stats.copies_per_day.len(), 1);
+        assert_eq!(stats.copies_per_day[0].new_items, 5);
+
+        let text = stats
+            .storage_by_type
+            .iter()
+            .find(|storage| storage.content_type == ContentType::Text)
+            .unwrap();
+        assert_eq!((text.items, text.bytes), (4, 20));
+
+        assert_eq!(stats.top_source_apps.len(), 2);
+        assert!(stats.top_source_apps[0]
+            .source_app_name
+            .eq_ignore_ascii_case("firefox"));
+        assert_eq!(
+            (
+                stats.top_source_apps[0].items,
+                stats.top_source_apps[0].copies
+            ),
+            (2, 4)
+        );
+    }
+
+    #[test]
+    fn test_get_items_filters_by_detected_subtype() {
+        let manager = ClipboardHistoryManager::new_for_test().unwrap();
+        for (hash, content) in [
+            ("mail", "jane@example.com"),
+            ("rust", "fn main() {\n    let mut x = 1;\n}"),
+            ("sql", "SELECT * FROM users WHERE id = 1"),
+            ("plain", "just some words"),
+        ] {
+            manager
+                .add_item(hash.into(), ContentType::Text, content.into(), None)
+                .unwrap();
+        }
+
+        let emails = manager
+            .get_items("email".into(), None, None, 10, 0)
+            .unwrap();
+        assert_eq!(emails.len(), 1);
+        assert_eq!(emails[0].content_subtype.as_deref(), Some("email"));
+
+        let code = manager.get_items("code".into(), None, None, 10, 0).unwrap();
+        let mut hashes: Vec<_> = code.iter().map(|item| item.hash.as_str()).collect();
+        hashes.sort();
+        assert_eq!(hashes, ["rust", "sql"]);
+
+        let rust = manager
+            .get_items("code:rust".into(), None, None, 10, 0)
+            .unwrap();
+        assert_eq!(rust.len(), 1);
+
+        let all = manager.get_items("all".into(), None, None, 10, 0).unwrap();
+        let plain = all.iter().find(|item| item.hash == "plain").unwrap();
+        assert_eq!(plain.content_subtype, None);
+
+        assert!(manager
+            .get_items("code:cobol".into(), None, None, 10, 0)
+            .is_err());
+        assert!(manager
+            .get_items("emails".into(), None, None, 10, 0)
+            .is_err());
+    }
+
+    #[test]
+    fn test_unreadable_items_are_classified_without_subtype() {
+        let manager = ClipboardHistoryManager::new_for_test().unwrap();
+        manager
+            .add_item(
+                "mail".into(),
+                ContentType::Text,
+                "jane@example.com".into(),
+                None,
+            )
+            .unwrap();
+        manager
+            .store
+            .conn()
+            .execute(
+                "INSERT INTO clipboard_history (hash, content_type, encrypted_content, first_copied_at, last_copied_at)
+                 VALUES ('corrupt', 'text', 'not encrypted', 1, 1)",
+                [],
+            )
+            .unwrap();
+        manager
+            .store
+            .conn()
+            .execute("UPDATE clipboard_history SET content_subtype = NULL", [])
+            .unwrap();
+
+        manager.classify_unclassified_items().unwrap();
+        let subtypes: Vec<(String, String)> = {
+            let db = manager.store.conn();
+            let mut stmt = db
+                .prepare("SELECT hash, content_subtype FROM clipboard_history ORDER BY hash")
+                .unwrap();
+            let subtypes = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                .unwrap()
+                .collect::<RusqliteResult<_>>()
+                .unwrap();
+            subtypes
+        };
+        assert_eq!(
+            subtypes,
+            [
+                ("corrupt".to_string(), String::new()),
+                ("mail".to_string(), "email".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plaintext_images_are_migrated() {
+        let manager = ClipboardHistoryManager::new_for_test().unwrap();
+        let png = [0x89, b'P', b'N', b'G', 1, 2, 3];
+        let hash = text_hash("legacy image");
+        let legacy_path = manager.image_dir.join(format!("{}.png", hash));
+        std::fs::write(&legacy_path, png).unwrap();
+        manager
+            .add_item(
+                hash.clone(),
+                ContentType::Image,
+                legacy_path.to_string_lossy().to_string(),
+                None,
+            )
+            .unwrap();
+
+        manager.migrate_plaintext_images().unwrap();
+
+        assert!(!legacy_path.exists());
+        let stored = std::fs::read(manager.image_path(&hash)).unwrap();
+        assert_ne!(stored, png);
+        assert_eq!(manager.read_image(&hash).unwrap(), png);
+        let id = manager.get_items("image".into(), None, None, 1, 0).unwrap()[0].id;
+        assert_eq!(
+            manager.get_item_content(id).unwrap(),
+            manager.image_path(&hash).to_string_lossy()
+        );
+        assert!(manager.read_image(&format!("../{}", hash)).is_err());
+        assert!(manager.save_image("../../escaped", &png).is_err());
+        std::fs::remove_dir_all(&manager.image_dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotate_key_keeps_items_readable_and_searchable() {
+        let mut manager = ClipboardHistoryManager::new_for_test().unwrap();
+        manager
+            .add_item(
+                "t".into(),
+                ContentType::Text,
+                "quarterly report".into(),
+                None,
+            )
+            .unwrap();
+        let png = [0x89, b'P', b'N', b'G', 9];
+        let hash = text_hash("image");
+        let path = manager.save_image(&hash, &png).unwrap();
+        manager
+            .add_item(
+                hash.clone(),
+                ContentType::Image,
+                path.to_string_lossy().to_string(),
+                None,
+            )
+            .unwrap();
+        let old_key = manager.key;
+        let old_ciphertext = std::fs::read(&path).unwrap();
+
+        manager.rotate_key().unwrap();
+
+        assert_ne!(manager.key, old_key);
+        assert_ne!(std::fs::read(&path).unwrap(), old_ciphertext);
+        assert_eq!(manager.read_image(&hash).unwrap(), png);
+        let found = manager
+            .get_items("all".into(), Some("quarterly".into()), None, 10, 0)
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            manager.get_item_content(found[0].id).unwrap(),
+            "quarterly report"
+        );
+        std::fs::remove_dir_all(&manager.image_dir).unwrap();
+    }
+
+    #[test]
+    fn test_interrupted_key_rotation_is_finished_or_rolled_back() {
+        let mut manager = ClipboardHistoryManager::new_for_test().unwrap();
+        manager
+            .add_item(text_hash("notes"), ContentType::Text, "notes".into(), None)
+            .unwrap();
+        let png = [0x89, b'P', b'N', b'G', 7];
+        let hash = text_hash("image");
+        let path = manager.save_image(&hash, &png).unwrap();
+        manager
+            .add_item(
+                hash.clone(),
+                ContentType::Image,
+                path.to_string_lossy().to_string(),
+                None,
+            )
+            .unwrap();
+        let old_key = manager.key;
+        manager.key_provider.store_key(&old_key).unwrap();
+        manager.rotate_key().unwrap();
+        let new_key = manager.key;
+        assert_eq!(manager.key_provider.load_key().unwrap(), new_key);
+
+        // As if the app stopped after the commit, before anything else happened.
+        manager.key_provider.store_key(&old_key).unwrap();
+        manager
+            .store
+            .conn()
+            .execute(
+                "INSERT INTO clipboard_key_rotation (id, encrypted_key) VALUES (1, ?)",
+                params![encrypt(&hex::encode(new_key), &old_key).unwrap()],
+            )
+            .unwrap();
+        let image_path = manager.image_path(&hash);
+        std::fs::rename(
+            &image_path,
+            image_path.with_extension(STAGED_IMAGE_EXTENSION),
+        )
+        .unwrap();
+        manager.key = old_key;
+        manager.search_key = search::derive_search_key(&old_key);
+
+        manager.finish_key_rotation().unwrap();
+        assert_eq!(manager.key, new_key);
+        assert_eq!(manager.key_provider.load_key().unwrap(), new_key);
+        assert_eq!(manager.read_image(&hash).unwrap(), png);
+        let found = manager
+            .get_items("all".into(), Some("notes".into()), None, 10, 0)
+            .unwrap();
+        assert_eq!(manager.get_item_content(found[0].id).unwrap(), "notes");
+
+        // A rotation that never committed leaves only unreadable staged images behind.
+        let leftover = image_path.with_extension(STAGED_IMAGE_EXTENSION);
+        std::fs::write(&leftover, b"staged").unwrap();
+        manager.finish_key_rotation().unwrap();
+        assert!(!leftover.exists());
+        assert_eq!(manager.read_image(&hash).unwrap(), png);
+        std::fs::remove_dir_all(&manager.image_dir).unwrap();
+    }
+
+    #[test]
+    fn test_export_import_roundtrip_merges_existing_items() {
+        let target = ClipboardHistoryManager::new_for_test().unwrap();
+        target
+            .add_item(
+                text_hash("https://b.example"),
+                ContentType::Link,
+                "https://b.example".into(),
+                None,
+            )
+            .unwrap();
+
+        let source = ClipboardHistoryManager::new_for_test().unwrap();
+        source
+            .add_item(text_hash("alpha"), ContentType::Text, "alpha".into(), None)
+            .unwrap();
+        source
+            .add_item(
+                text_hash("https://b.example"),
+                ContentType::Link,
+                "https://b.example".into(),
+                None,
+            )
+            .unwrap();
+        let b_id = source.get_items("link".into(), None, None, 1, 0).unwrap()[0].id;
+        source.toggle_pin(b_id).unwrap();
+        let exported = source.export_items().unwrap();
+        assert_eq!(target.import_items(exported).unwrap(), 1);
+
+        let items = target.get_items("all".into(), None, None, 10, 0).unwrap();
+        assert_eq!(items.len(), 2);
+        assert!(
+            items
+                .iter()
+                .find(|i| i.hash == text_hash("https://b.example"))
+                .unwrap()
+                .is_pinned
+        );
+        let alpha = target
+            .get_items("all".into(), Some("alpha".into()), None, 10, 0)
+            .unwrap();
+        assert_eq!(alpha.len(), 1);
+    }
+
+    #[test]
+    fn test_import_skips_items_whose_hash_does_not_match() {
+        let manager = ClipboardHistoryManager::new_for_test().unwrap();
+        let item = |hash: String, content_type: ContentType, content: &str| ExportedItem {
+            hash,
+            content_type,
+            content: content.into(),
+            formats: ClipboardFormats::default(),
+            source_app_name: None,
+            first_copied_at: 1,
+            last_copied_at: 1,
+            times_copied: 1,
+            is_pinned: false,
+            modified_at: 1,
+            image: None,
+            ocr_text: None,
+        };
+        let mut png = Vec::new();
+        image::RgbaImage::from_pixel(2, 2, image::Rgba([1, 2, 3, 255]))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let pixels_hash = hex::encode(Sha256::digest([1, 2, 3, 255].repeat(4)));
+
+        let mut image_item = item(pixels_hash.clone(), ContentType::Image, "");
+        image_item.set_image_bytes(&png);
+        let mut escaping_image = item("../../escaped".into(), ContentType::Image, "");
+        escaping_image.set_image_bytes(&png);
+        let mut mismatched_image = item(text_hash("other"), ContentType::Image, "");
+        mismatched_image.set_image_bytes(&png);
+
+        let imported = manager
+            .import_items(vec![
+                item(text_hash("kept"), ContentType::Text, "kept"),
+                item(text_hash("kept"), ContentType::Text, "swapped"),
+                image_item,
+                escaping_image,
+                mismatched_image,
+            ])
+            .unwrap();
+        assert_eq!(imported, 2);
+        assert!(manager.read_image(&pixels_hash).is_ok());
+        assert!(!manager.image_dir.join("../../escaped.tmp").exists());
+        let items = manager.get_items("all".into(), None, None, 10, 0).unwrap();
+        assert_eq!(items.len(), 2);
+        std::fs::remove_dir_all(&manager.image_dir).unwrap();
+    }
+
+    #[test]
+    fn test_formats_are_stored_encrypted_and_kept_on_recopy() {
+        let manager = ClipboardHistoryManager::new_for_test().unwrap();
+        let formats = ClipboardFormats {
+            html: Some("<b>bold</b>".into()),
+            rtf: Some(r"{\rtf1 \b bold\b0}".into()),
+            uri_list: None,
+        };
+        manager
+            .add_item_with_formats(
+                "rich".into(),
+                ContentType::Text,
+                "bold".into(),
+                formats.clone(),
+                None,
+            )
+            .unwrap();
+        manager
+            .add_item("rich".into(), ContentType::Text, "bold".into(), None)
+            .unwrap();
+
+        let id = manager.get_items("all".into(), None, None, 1, 0).unwrap()[0].id;
+        assert_eq!(manager.get_item_formats(id).unwrap(), formats);
+
+        let stored: String = manager
+            .store
+            .conn()
+            .query_row(
+                "SELECT encrypted_formats FROM clipboard_history WHERE id = ?",
+                params![id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(!stored.contains("bold"));
     }
 }
@@ -1,22 +1,46 @@
+mod backup;
+mod detect;
 mod encryption;
+mod key_provider;
 pub mod manager;
 mod monitor;
+mod ocr;
+mod paste_queue;
+mod primary;
+mod retention;
+mod search;
+mod selection;
+pub mod settings;
+mod sync;
 pub mod types;
+mod watcher;
 
+use crate::clipboard::{clipboard_paste, ClipboardContent};
+use crate::snippets::engine::{apply_modifiers, MODIFIERS};
+use key_provider::{KeyProviderKind, KeyProviderStatus};
 pub use manager::init;
 use manager::MANAGER;
-use types::ClipboardItem;
+pub use monitor::conceal_text;
+use paste_queue::PasteQueueStatus;
+pub use primary::read_primary_text;
+use retention::PruneReport;
+pub use selection::{path_to_uri_list, write_clipboard_formats};
+use settings::ClipboardHistorySettings;
+use std::path::Path;
+use sync::SyncStatus;
+use types::{ClipboardFormats, ClipboardItem, ClipboardStats, MergeOrder};
 
 #[tauri::command]
 pub fn history_get_items(
     filter: String,
     search_term: Option<String>,
+    source_app_name: Option<String>,
     limit: u32,
     offset: u32,
 ) -> Result<Vec<ClipboardItem>, String> {
     if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
         manager
-            .get_items(filter, search_term, limit, offset)
+            .get_items(filter, search_term, source_app_name, limit, offset)
             .map_err(|e| e.to_string())
     } else {
         Err("Clipboard history manager not initialized".to_string())
@@ -32,6 +56,83 @@ pub fn history_get_item_content(id: i64) -> Result<String, String> {
     }
 }
 
+/// Pastes an item as plain text after running it through `transforms`, which use the
+/// snippet modifier names, e.g. `["percent-decode", "trim"]`.
+#[tauri::command]
+pub async fn history_paste_transformed(
+    app: tauri::AppHandle,
+    id: i64,
+    transforms: Vec<String>,
+) -> Result<(), String> {
+    if let Some(unknown) = transforms
+        .iter()
+        .find(|transform| !MODIFIERS.contains(&transform.as_str()))
+    {
+        return Err(format!("Unknown transform: {}", unknown));
+    }
+    let content = if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+        manager.get_text_content(id).map_err(|e| e.to_string())?
+    } else {
+        return Err("Clipboard history manager not initialized".to_string());
+    };
+
+    let transforms: Vec<&str> = transforms.iter().map(String::as_str).collect();
+    let transformed = apply_modifiers(content, &transforms);
+    clipboard_paste(app, ClipboardContent::text(transformed)).await
+}
+
+#[tauri::command]
+pub fn history_get_item_formats(id: i64) -> Result<ClipboardFormats, String> {
+    if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+        manager.get_item_formats(id).map_err(|e| e.to_string())
+    } else {
+        Err("Clipboard history manager not initialized".to_string())
+    }
+}
+
+/// Serves decrypted images to the webview as `clipboard-image://localhost/<hash>`.
+pub fn handle_image_request(
+    request: &tauri::http::Request<Vec<u8>>,
+) -> tauri::http::Response<Vec<u8>> {
+    let hash = request.uri().path().trim_start_matches('/');
+    let image = match MANAGER.lock().unwrap().as_ref() {
+        Some(manager) => manager.read_image(hash).map_err(|e| e.to_string()),
+        None => Err("Clipboard history manager not initialized".to_string()),
+    };
+    let response = tauri::http::Response::builder();
+    match image {
+        Ok(png) => response
+            .header(tauri::http::header::CONTENT_TYPE, "image/png")
+            .body(png),
+        Err(e) => response
+            .status(tauri::http::StatusCode::NOT_FOUND)
+            .body(e.into_bytes()),
+    }
+    .unwrap()
+}
+
+#[tauri::command]
+pub fn history_copy_image(hash: String) -> Result<(), String> {
+    let png = if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+        manager.read_image(&hash).map_err(|e| e.to_string())?
+    } else {
+        return Err("Clipboard history manager not initialized".to_string());
+    };
+    let image = image::load_from_memory(&png)
+        .map_err(|e| e.to_string())?
+        .to_rgba8();
+    let (width, height) = image.dimensions();
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| {
+            clipboard.set_image(arboard::ImageData {
+                width: width as usize,
+                height: height as usize,
+                bytes: image.into_raw().into(),
+            })
+        })
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub fn history_item_was_copied(id: i64) -> Result<(), String> {
     if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
@@ -71,3 +172,189 @@ pub fn history_clear_all() -> Result<(), String> {
         Err("Clipboard history manager not initialized".to_string())
     }
 }
+
+#[tauri::command]
+pub fn history_get_key_provider() -> KeyProviderStatus {
+    manager::key_status()
+}
+
+#[tauri::command]
+pub fn history_unlock(passphrase: String) -> Result<KeyProviderStatus, String> {
+    manager::start(KeyProviderKind::PassphraseFile, Some(passphrase)).map_err(|e| e.to_string())?;
+    Ok(manager::key_status())
+}
+
+#[tauri::command]
+pub fn history_set_key_provider(
+    provider: KeyProviderKind,
+    passphrase: Option<String>,
+) -> Result<KeyProviderStatus, String> {
+    manager::switch_key_provider(provider, passphrase).map_err(|e| e.to_string())?;
+    Ok(manager::key_status())
+}
+
+#[tauri::command]
+pub fn history_rotate_key() -> Result<(), String> {
+    if let Some(manager) = MANAGER.lock().unwrap().as_mut() {
+        manager.rotate_key().map_err(|e| e.to_string())
+    } else {
+        Err("Clipboard history manager not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+pub fn history_export(path: String, passphrase: String) -> Result<u32, String> {
+    if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+        manager
+            .export_to_file(Path::new(&path), &passphrase)
+            .map_err(|e| e.to_string())
+    } else {
+        Err("Clipboard history manager not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+pub fn history_import(path: String, passphrase: String) -> Result<u32, String> {
+    if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+        manager
+            .import_from_file(Path::new(&path), &passphrase)
+            .map_err(|e| e.to_string())
+    } else {
+        Err("Clipboard history manager not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+pub fn history_get_settings() -> Result<ClipboardHistorySettings, String> {
+    if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+        Ok(manager.settings().clone())
+    } else {
+        Err("Clipboard history manager not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+pub fn history_set_settings(settings: ClipboardHistorySettings) -> Result<(), String> {
+    if let Some(manager) = MANAGER.lock().unwrap().as_mut() {
+        manager.set_settings(settings).map_err(|e| e.to_string())?;
+        ocr::notify();
+        Ok(())
+    } else {
+        Err("Clipboard history manager not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+pub fn history_get_stats(days: Option<u32>, limit: Option<u32>) -> Result<ClipboardStats, String> {
+    if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+        manager
+            .get_stats(days.unwrap_or(30), limit.unwrap_or(10))
+            .map_err(|e| e.to_string())
+    } else {
+        Err("Clipboard history manager not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+pub fn history_prune() -> Result<PruneReport, String> {
+    if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+        manager.prune().map_err(|e| e.to_string())
+    } else {
+        Err("Clipboard history manager not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+pub fn history_queue_start(app: tauri::AppHandle, shortcut: Option<String>) -> Result<(), String> {
+    paste_queue::start(&app, shortcut).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn history_queue_stop(app: tauri::AppHandle) -> Result<(), String> {
+    paste_queue::stop(&app).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn history_queue_status() -> Result<PasteQueueStatus, String> {
+    paste_queue::status().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn history_queue_paste_next(app: tauri::AppHandle) -> Result<bool, String> {
+    paste_queue::paste_next(&app).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn history_queue_paste_all(
+    app: tauri::AppHandle,
+    separator: Option<String>,
+) -> Result<bool, String> {
+    paste_queue::paste_all(&app, separator).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn history_merge_items(
+    ids: Vec<i64>,
+    separator: Option<String>,
+    order: Option<MergeOrder>,
+) -> Result<i64, String> {
+    if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+        manager
+            .merge_items(
+                &ids,
+                separator.as_deref().unwrap_or("\n"),
+                order.unwrap_or_default(),
+            )
+            .map_err(|e| e.to_string())
+    } else {
+        Err("Clipboard history manager not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+pub fn history_split_item(id: i64) -> Result<Vec<i64>, String> {
+    if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+        manager.split_item(id).map_err(|e| e.to_string())
+    } else {
+        Err("Clipboard history manager not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+pub fn history_sync_status() -> Result<SyncStatus, String> {
+    if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+        sync::status(manager).map_err(|e| e.to_string())
+    } else {
+        Err("Clipboard history manager not initialized".to_string())
+    }
+}
+
+/// Pairs this device with a new code and returns it, to be entered on the other devices.
+#[tauri::command]
+pub fn history_sync_pair_new() -> Result<String, String> {
+    if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+        let code = sync::generate_pairing_code();
+        sync::pair(manager, &code).map_err(|e| e.to_string())?;
+        Ok(code)
+    } else {
+        Err("Clipboard history manager not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+pub fn history_sync_pair_join(code: String) -> Result<(), String> {
+    if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+        sync::pair(manager, &code).map_err(|e| e.to_string())
+    } else {
+        Err("Clipboard history manager not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+pub fn history_sync_unpair() -> Result<(), String> {
+    if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+        sync::unpair(manager).map_err(|e| e.to_string())
+    } else {
+        Err("Clipboard history manager not initialized".to_string())
+    }
+}
@@ -1,83 +1,204 @@
 use super::{
+    detect::detect,
     manager::MANAGER,
-    types::{ContentType, COLOR_REGEX, URL_REGEX},
+    selection::{clipboard_mime_types, has_concealed_hint, read_clipboard_formats, uri_list_paths},
+    types::{ContentType, Selection},
+    watcher::{create_watcher, PollingWatcher},
 };
+use crate::active_window::clipboard_owner_app_name;
+use image::codecs::png::PngEncoder;
+use image::{ExtendedColorType, ImageEncoder};
+use once_cell::sync::Lazy;
 use sha2::{Digest, Sha256};
-use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use tauri::AppHandle;
 
-pub fn start_monitoring(_app_handle: AppHandle) {
-    std::thread::spawn(move || {
-        let mut last_text_hash = String::new();
-        let mut last_image_hash = String::new();
-        let mut clipboard = arboard::Clipboard::new().unwrap();
+/// A concealed text that never reaches the clipboard, e.g. because the paste failed, stops
+/// being concealed after this long.
+const CONCEAL_DURATION: Duration = Duration::from_secs(10);
 
-        loop {
-            if super::manager::INTERNAL_CLIPBOARD_CHANGE.load(std::sync::atomic::Ordering::SeqCst) {
-                std::thread::sleep(Duration::from_millis(50));
-                continue;
+/// Hashes of concealed texts, with when they were concealed.
+static CONCEALED_HASHES: Lazy<Mutex<HashMap<String, Instant>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub(super) fn text_hash(text: &str) -> String {
+    hex::encode(Sha256::digest(text.trim().as_bytes()))
+}
+
+/// Keeps the next clipboard change carrying `text` out of the history.
+pub fn conceal_text(text: &str) {
+    CONCEALED_HASHES
+        .lock()
+        .unwrap()
+        .insert(text_hash(text), Instant::now());
+}
+
+/// Whether the clipboard change carrying `hash` was concealed, forgetting it either way so a
+/// later copy of the same text is recorded.
+fn take_concealed(hash: &str, now: Instant) -> bool {
+    let mut concealed = CONCEALED_HASHES.lock().unwrap();
+    concealed.retain(|_, concealed_at| now.duration_since(*concealed_at) < CONCEAL_DURATION);
+    concealed.remove(hash).is_some()
+}
+
+/// Password managers mark secrets with a MIME hint; honour it even for allowed apps.
+fn clipboard_is_concealed() -> bool {
+    clipboard_mime_types().is_some_and(|mime_types| has_concealed_hint(&mime_types))
+}
+
+fn encode_png(image: &arboard::ImageData) -> Result<Vec<u8>, image::ImageError> {
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png).write_image(
+        &image.bytes,
+        image.width as u32,
+        image.height as u32,
+        ExtendedColorType::Rgba8,
+    )?;
+    Ok(png)
+}
+
+#[derive(Default)]
+struct MonitorState {
+    last_text_hash: String,
+    last_image_hash: String,
+}
+
+impl MonitorState {
+    fn capture(&mut self, clipboard: &mut arboard::Clipboard) {
+        if let Ok(text) = clipboard.get_text() {
+            let text = text.trim();
+            if !text.is_empty() {
+                let current_hash = text_hash(text);
+                let concealed_copy = take_concealed(&current_hash, Instant::now());
+                if current_hash != self.last_text_hash {
+                    let mime_types = clipboard_mime_types();
+                    let concealed =
+                        concealed_copy || mime_types.as_ref().is_some_and(has_concealed_hint);
+                    let formats = match &mime_types {
+                        Some(mime_types) if !concealed => read_clipboard_formats(mime_types),
+                        _ => Default::default(),
+                    };
+                    let file_paths = formats
+                        .uri_list
+                        .as_deref()
+                        .map(uri_list_paths)
+                        .unwrap_or_default();
+                    let (content_type, content_value) = if !file_paths.is_empty() {
+                        (ContentType::File, file_paths.join("\n"))
+                    } else {
+                        (detect(text).content_type, text.to_string())
+                    };
+
+                    let source_app_name = clipboard_owner_app_name();
+                    if let Some(manager) =
+                        MANAGER.lock().unwrap().as_ref().filter(|m| {
+                            !concealed && !m.is_excluded_app(source_app_name.as_deref())
+                        })
+                    {
+                        match manager.add_item_with_formats(
+                            current_hash.clone(),
+                            content_type,
+                            content_value,
+                            formats,
+                            source_app_name,
+                        ) {
+                            Ok(id) => super::paste_queue::record_copy(id),
+                            Err(e) => eprintln!("Error adding clipboard text item: {:?}", e),
+                        }
+                    }
+                    self.last_text_hash = current_hash;
+                    self.last_image_hash.clear();
+                }
             }
+        }
 
-            if let Ok(text) = clipboard.get_text() {
-                let text = text.trim();
-                if !text.is_empty() {
-                    let current_hash = hex::encode(Sha256::digest(text.as_bytes()));
-                    if current_hash != last_text_hash {
-                        let (content_type, content_value) = if COLOR_REGEX.is_match(text) {
-                            (ContentType::Color, text.to_string())
-                        } else if URL_REGEX.is_match(text) {
-                            (ContentType::Link, text.to_string())
-                        } else {
-                            (ContentType::Text, text.to_string())
-                        };
-
-                        if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+        if let Ok(image_data) = clipboard.get_image() {
+            // Only a full hash tells a new screenshot of the same size apart from the last one.
+            let current_hash = hex::encode(Sha256::digest(&image_data.bytes));
+            if current_hash != self.last_image_hash {
+                let concealed = clipboard_is_concealed();
+                let source_app_name = clipboard_owner_app_name();
+                if let Some(manager) = MANAGER
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .filter(|m| !concealed && !m.is_excluded_app(source_app_name.as_deref()))
+                {
+                    let saved =
+                        encode_png(&image_data)
+                            .map_err(|e| e.to_string())
+                            .and_then(|png| {
+                                manager
+                                    .save_image(&current_hash, &png)
+                                    .map_err(|e| e.to_string())
+                            });
+                    match saved {
+                        Ok(image_path) => {
+                            let content_value = image_path.to_string_lossy().to_string();
                             if let Err(e) = manager.add_item(
                                 current_hash.clone(),
-                                content_type,
+                                ContentType::Image,
                                 content_value,
-                                None,
+                                source_app_name,
                             ) {
-                                eprintln!("Error adding clipboard text item: {:?}", e);
+                                eprintln!("Error adding clipboard image item: {:?}", e);
                             }
+                            super::ocr::notify();
                         }
-                        last_text_hash = current_hash;
-                        last_image_hash.clear();
+                        Err(e) => eprintln!("Failed to save image: {}", e),
                     }
                 }
+                self.last_image_hash = current_hash;
+                self.last_text_hash.clear();
             }
+        }
+    }
+}
 
-            if let Ok(image_data) = clipboard.get_image() {
-                let current_hash = hex::encode(Sha256::digest(&image_data.bytes));
-                if current_hash != last_image_hash {
-                    if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
-                        let image_path = manager.image_dir.join(format!("{}.png", current_hash));
-                        match image::save_buffer(
-                            &image_path,
-                            &image_data.bytes,
-                            image_data.width as u32,
-                            image_data.height as u32,
-                            image::ColorType::Rgba8,
-                        ) {
-                            Ok(_) => {
-                                let content_value = image_path.to_string_lossy().to_string();
-                                if let Err(e) = manager.add_item(
-                                    current_hash.clone(),
-                                    ContentType::Image,
-                                    content_value,
-                                    None,
-                                ) {
-                                    eprintln!("Error adding clipboard image item: {:?}", e);
-                                }
-                            }
-                            Err(e) => eprintln!("Failed to save image: {:?}", e),
-                        }
-                    }
-                    last_image_hash = current_hash;
-                    last_text_hash.clear();
-                }
+pub fn start_monitoring(_app_handle: AppHandle) {
+    std::thread::spawn(move || {
+        let mut state = MonitorState::default();
+        let mut clipboard = arboard::Clipboard::new().unwrap();
+        let mut watcher = create_watcher(Selection::Clipboard);
+        println!(
+            "[ClipboardHistory] Watching clipboard via {}",
+            watcher.name()
+        );
+
+        loop {
+            // Our own paste helpers swap the clipboard temporarily; the restore triggers
+            // another change notification, so skipping here loses nothing.
+            if !super::manager::INTERNAL_CLIPBOARD_CHANGE.load(Ordering::SeqCst) {
+                state.capture(&mut clipboard);
+            }
+
+            if !watcher.wait_for_change() {
+                eprintln!(
+                    "[ClipboardHistory] {} watcher stopped, falling back to polling",
+                    watcher.name()
+                );
+                watcher = Box::new(PollingWatcher);
             }
-            std::thread::sleep(Duration::from_millis(500));
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concealed_text_is_forgotten_once_seen_or_expired() {
+        conceal_text("hunter2");
+        let hash = text_hash("hunter2");
+        assert!(take_concealed(&hash, Instant::now()));
+        assert!(!take_concealed(&hash, Instant::now()));
+
+        conceal_text("hunter3");
+        let later = Instant::now() + CONCEAL_DURATION;
+        assert!(!take_concealed(&text_hash("hunter3"), later));
+    }
+}
@@ -0,0 +1,178 @@
+use super::manager::MANAGER;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+const TESSERACT_BINARY: &str = "tesseract";
+const RESCAN_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+static WAKE_WORKER: Lazy<Mutex<Option<Sender<()>>>> = Lazy::new(|| Mutex::new(None));
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OcrSettings {
+    pub enabled: bool,
+    /// Tesseract language codes, e.g. `["eng", "deu"]`.
+    pub languages: Vec<String>,
+}
+
+impl Default for OcrSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            languages: vec!["eng".to_string()],
+        }
+    }
+}
+
+/// Drops the empty lines Tesseract emits between blocks and the trailing form feed.
+fn clean_text(raw: &str) -> String {
+    raw.lines()
+        .map(|line| line.trim_end_matches('\u{c}').trim())
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Runs the local Tesseract binary on a PNG. The image is piped through stdin so the
+/// decrypted data never touches the disk. `NotFound` means Tesseract isn't installed.
+fn recognize(png: &[u8], languages: &[String]) -> io::Result<String> {
+    let mut command = Command::new(TESSERACT_BINARY);
+    command.args(["stdin", "stdout"]);
+    if !languages.is_empty() {
+        command.args(["-l", &languages.join("+")]);
+    }
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(png)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "tesseract exited with {}",
+            output.status
+        )));
+    }
+    Ok(clean_text(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Recognizes images that have no OCR text yet, one at a time so the history stays usable
+/// while Tesseract runs.
+fn process_pending() {
+    loop {
+        let job = {
+            let guard = MANAGER.lock().unwrap();
+            let Some(manager) = guard.as_ref() else {
+                return;
+            };
+            let settings = &manager.settings().ocr;
+            if !settings.enabled {
+                return;
+            }
+            match manager.next_image_without_ocr() {
+                Ok(Some((id, hash))) => (id, manager.read_image(&hash), settings.languages.clone()),
+                Ok(None) => return,
+                Err(e) => {
+                    eprintln!("Error looking up images for OCR: {:?}", e);
+                    return;
+                }
+            }
+        };
+
+        let (id, png, languages) = job;
+        let text = match png {
+            Ok(png) => match recognize(&png, &languages) {
+                Ok(text) => text,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    eprintln!("[ClipboardHistory] OCR is enabled but tesseract is not installed");
+                    return;
+                }
+                Err(e) => {
+                    eprintln!("OCR failed for clipboard item {}: {:?}", id, e);
+                    String::new()
+                }
+            },
+            Err(e) => {
+                eprintln!("Failed to read clipboard image {} for OCR: {:?}", id, e);
+                String::new()
+            }
+        };
+
+        // Failed items are stored with empty text so they aren't retried forever.
+        if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+            if let Err(e) = manager.set_ocr_text(id, &text) {
+                eprintln!("Error storing OCR text: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Wakes the OCR worker after a new image was saved or the settings changed.
+pub fn notify() {
+    if let Some(sender) = WAKE_WORKER.lock().unwrap().as_ref() {
+        let _ = sender.send(());
+    }
+}
+
+pub fn start_ocr_worker() {
+    let (sender, receiver) = mpsc::channel();
+    *WAKE_WORKER.lock().unwrap() = Some(sender);
+    thread::spawn(move || loop {
+        process_pending();
+        match receiver.recv_timeout(RESCAN_INTERVAL) {
+            Ok(()) | Err(RecvTimeoutError::Timeout) => while receiver.try_recv().is_ok() {},
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clipboard_history::manager::ClipboardHistoryManager;
+    use crate::clipboard_history::types::ContentType;
+
+    #[test]
+    fn test_clean_text_drops_blank_lines_and_form_feed() {
+        let raw = "error: connection refused  \n\n  at main.rs:42\n\u{c}";
+        assert_eq!(clean_text(raw), "error: connection refused\nat main.rs:42");
+    }
+
+    #[test]
+    fn test_ocr_text_makes_images_searchable() {
+        let manager = ClipboardHistoryManager::new_for_test().unwrap();
+        manager
+            .add_item(
+                "shot".into(),
+                ContentType::Image,
+                "/tmp/shot.enc".into(),
+                None,
+            )
+            .unwrap();
+        manager
+            .add_item("note".into(), ContentType::Text, "unrelated".into(), None)
+            .unwrap();
+
+        let (id, hash) = manager.next_image_without_ocr().unwrap().unwrap();
+        assert_eq!(hash, "shot");
+        manager
+            .set_ocr_text(id, "Segmentation fault (core dumped)")
+            .unwrap();
+
+        assert!(manager.next_image_without_ocr().unwrap().is_none());
+        let found = manager
+            .get_items("all".into(), Some("segmentation".into()), None, 10, 0)
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].hash, "shot");
+    }
+}
@@ -0,0 +1,174 @@
+use super::manager::MANAGER;
+use super::types::ClipboardItem;
+use crate::error::AppError;
+use crate::snippets::input_manager::InputManager;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, ShortcutState};
+
+pub const DEFAULT_SHORTCUT: &str = "CommandOrControl+Alt+V";
+pub const DEFAULT_SEPARATOR: &str = "\n";
+
+static PASTE_QUEUE: Lazy<Mutex<PasteQueue>> = Lazy::new(|| Mutex::new(PasteQueue::default()));
+
+/// Ids of the history items copied while queue mode is on, oldest first.
+#[derive(Default)]
+struct PasteQueue {
+    shortcut: Option<String>,
+    items: VecDeque<i64>,
+}
+
+impl PasteQueue {
+    fn is_active(&self) -> bool {
+        self.shortcut.is_some()
+    }
+
+    /// Re-copying something that is already queued doesn't queue it twice.
+    fn push(&mut self, id: i64) {
+        if self.is_active() && !self.items.contains(&id) {
+            self.items.push_back(id);
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PasteQueueStatus {
+    pub active: bool,
+    pub shortcut: Option<String>,
+    pub items: Vec<ClipboardItem>,
+}
+
+/// Called by the monitor for every captured text item.
+pub fn record_copy(id: i64) {
+    PASTE_QUEUE.lock().unwrap().push(id);
+}
+
+/// Starts queueing copies and registers `shortcut` to paste them one at a time.
+pub fn start(app: &AppHandle, shortcut: Option<String>) -> Result<(), AppError> {
+    let shortcut = shortcut.unwrap_or_else(|| DEFAULT_SHORTCUT.to_string());
+    stop(app)?;
+    app.global_shortcut()
+        .on_shortcut(shortcut.as_str(), |app, _shortcut, event| {
+            // Injecting while the shortcut is still held would turn the pasted text into
+            // Ctrl+Alt chords, so wait for it to be let go.
+            if event.state() == ShortcutState::Released {
+                if let Err(e) = paste_next(app) {
+                    eprintln!("[PasteQueue] Failed to paste next item: {}", e);
+                }
+            }
+        })
+        .map_err(|e| AppError::ClipboardHistory(e.to_string()))?;
+    let mut queue = PASTE_QUEUE.lock().unwrap();
+    queue.shortcut = Some(shortcut);
+    queue.items.clear();
+    Ok(())
+}
+
+/// Stops queueing and drops whatever is still queued.
+pub fn stop(app: &AppHandle) -> Result<(), AppError> {
+    let shortcut = {
+        let mut queue = PASTE_QUEUE.lock().unwrap();
+        queue.items.clear();
+        queue.shortcut.take()
+    };
+    if let Some(shortcut) = shortcut {
+        app.global_shortcut()
+            .unregister(shortcut.as_str())
+            .map_err(|e| AppError::ClipboardHistory(e.to_string()))?;
+    }
+    Ok(())
+}
+
+pub fn status() -> Result<PasteQueueStatus, AppError> {
+    let (shortcut, ids) = {
+        let queue = PASTE_QUEUE.lock().unwrap();
+        (queue.shortcut.clone(), queue.items.clone())
+    };
+    let items = match MANAGER.lock().unwrap().as_ref() {
+        Some(manager) => ids
+            .iter()
+            .filter_map(|id| manager.get_item(*id).ok())
+            .collect(),
+        None => Vec::new(),
+    };
+    Ok(PasteQueueStatus {
+        active: shortcut.is_some(),
+        shortcut,
+        items,
+    })
+}
+
+fn text_of(ids: &[i64]) -> Result<Vec<String>, AppError> {
+    let guard = MANAGER.lock().unwrap();
+    let manager = guard.as_ref().ok_or_else(|| {
+        AppError::ClipboardHistory("Clipboard history manager not initialized".into())
+    })?;
+    // Items deleted from the history while queued are skipped.
+    Ok(ids
+        .iter()
+        .filter_map(|id| manager.get_text_content(*id).ok())
+        .collect())
+}
+
+fn inject(app: &AppHandle, text: String) -> Result<(), AppError> {
+    let input_manager = app
+        .try_state::<Arc<dyn InputManager>>()
+        .ok_or_else(|| AppError::ClipboardHistory("Text injection is unavailable".into()))?
+        .inner()
+        .clone();
+    std::thread::spawn(move || {
+        if let Err(e) = input_manager.inject_text(&text) {
+            eprintln!("[PasteQueue] Failed to inject text: {}", e);
+        }
+    });
+    Ok(())
+}
+
+/// Pastes the oldest queued item and removes it from the queue. Returns whether there was
+/// anything to paste.
+pub fn paste_next(app: &AppHandle) -> Result<bool, AppError> {
+    loop {
+        let Some(id) = PASTE_QUEUE.lock().unwrap().items.pop_front() else {
+            return Ok(false);
+        };
+        if let Some(text) = text_of(&[id])?.pop() {
+            inject(app, text)?;
+            return Ok(true);
+        }
+    }
+}
+
+/// Pastes every queued item at once, joined by `separator`, and empties the queue.
+pub fn paste_all(app: &AppHandle, separator: Option<String>) -> Result<bool, AppError> {
+    let ids: Vec<i64> = PASTE_QUEUE.lock().unwrap().items.drain(..).collect();
+    let texts = text_of(&ids)?;
+    if texts.is_empty() {
+        return Ok(false);
+    }
+    let separator = separator.unwrap_or_else(|| DEFAULT_SEPARATOR.to_string());
+    inject(app, texts.join(&separator))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_only_records_while_active() {
+        let mut queue = PasteQueue::default();
+        queue.push(1);
+        assert!(queue.items.is_empty());
+
+        queue.shortcut = Some(DEFAULT_SHORTCUT.to_string());
+        queue.push(1);
+        queue.push(2);
+        queue.push(1);
+        assert_eq!(queue.items, [1, 2]);
+        assert_eq!(queue.items.pop_front(), Some(1));
+    }
+}
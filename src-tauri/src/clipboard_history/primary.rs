@@ -0,0 +1,152 @@
+use super::{
+    manager::MANAGER,
+    monitor::text_hash,
+    retention::RetentionLimits,
+    types::Selection,
+    watcher::{create_watcher, ClipboardWatcher, PollingWatcher},
+};
+use crate::active_window::clipboard_owner_app_name;
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::Duration;
+
+/// Highlighting text by dragging changes PRIMARY continuously, so wait for it to settle.
+const SETTLE_INTERVAL: Duration = Duration::from_millis(400);
+const MAX_SETTLE_READS: usize = 10;
+const DISABLED_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// PRIMARY items are stored under a prefixed hash so highlighting text that was also copied
+/// doesn't bump the regular history entry.
+pub const PRIMARY_HASH_PREFIX: &str = "primary:";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PrimarySelectionSettings {
+    pub enabled: bool,
+    /// Shorter highlights, such as a double-clicked digit, are ignored.
+    pub min_length: usize,
+    /// Applied to PRIMARY items instead of the regular retention settings.
+    pub retention: RetentionLimits,
+}
+
+impl Default for PrimarySelectionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_length: 3,
+            retention: RetentionLimits {
+                max_age_days: Some(1),
+                max_items: Some(50),
+                max_total_bytes: None,
+            },
+        }
+    }
+}
+
+/// Reads the text currently in PRIMARY. Always `None` outside Linux.
+#[cfg(target_os = "linux")]
+pub fn read_primary_text(clipboard: &mut arboard::Clipboard) -> Option<String> {
+    use arboard::{GetExtLinux, LinuxClipboardKind};
+    clipboard
+        .get()
+        .clipboard(LinuxClipboardKind::Primary)
+        .text()
+        .ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read_primary_text(_clipboard: &mut arboard::Clipboard) -> Option<String> {
+    None
+}
+
+/// Re-reads PRIMARY until two reads agree, so a drag selection is recorded once it's done.
+fn read_settled_text(clipboard: &mut arboard::Clipboard) -> Option<String> {
+    let mut text = read_primary_text(clipboard)?;
+    for _ in 0..MAX_SETTLE_READS {
+        thread::sleep(SETTLE_INTERVAL);
+        let next = read_primary_text(clipboard)?;
+        if next == text {
+            break;
+        }
+        text = next;
+    }
+    Some(text)
+}
+
+fn settings() -> Option<PrimarySelectionSettings> {
+    MANAGER
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|manager| manager.settings().primary.clone())
+        .filter(|settings| settings.enabled)
+}
+
+fn capture(clipboard: &mut arboard::Clipboard, min_length: usize, last_hash: &mut String) {
+    let Some(text) = read_settled_text(clipboard) else {
+        return;
+    };
+    let text = text.trim();
+    if text.chars().count() < min_length {
+        return;
+    }
+    let current_hash = text_hash(text);
+    // Highlighting text and then copying it is already recorded by the clipboard monitor.
+    let copied = clipboard
+        .get_text()
+        .is_ok_and(|copied| text_hash(&copied) == current_hash);
+    if copied || current_hash == *last_hash {
+        return;
+    }
+
+    let source_app_name = clipboard_owner_app_name();
+    if let Some(manager) = MANAGER
+        .lock()
+        .unwrap()
+        .as_ref()
+        .filter(|m| !m.is_excluded_app(source_app_name.as_deref()))
+    {
+        if let Err(e) = manager.add_primary_item(text, source_app_name) {
+            eprintln!("Error adding primary selection item: {:?}", e);
+        }
+    }
+    *last_hash = current_hash;
+}
+
+/// Records highlighted text while `primary.enabled` is set. Does nothing until then.
+pub fn start_primary_monitoring() {
+    thread::spawn(|| {
+        let mut clipboard = match arboard::Clipboard::new() {
+            Ok(clipboard) => clipboard,
+            Err(e) => {
+                eprintln!("[ClipboardHistory] PRIMARY monitoring unavailable: {}", e);
+                return;
+            }
+        };
+        let mut watcher: Option<Box<dyn ClipboardWatcher>> = None;
+        let mut last_hash = String::new();
+
+        loop {
+            if settings().is_none() {
+                watcher = None;
+                thread::sleep(DISABLED_CHECK_INTERVAL);
+                continue;
+            }
+            let active = watcher.get_or_insert_with(|| {
+                let watcher = create_watcher(Selection::Primary);
+                println!(
+                    "[ClipboardHistory] Watching primary selection via {}",
+                    watcher.name()
+                );
+                watcher
+            });
+            if !active.wait_for_change() {
+                *active = Box::new(PollingWatcher);
+            }
+            // The setting may have been turned off while waiting.
+            if let Some(settings) = settings() {
+                capture(&mut clipboard, settings.min_length, &mut last_hash);
+            }
+        }
+    });
+}
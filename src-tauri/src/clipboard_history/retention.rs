@@ -0,0 +1,335 @@
+use super::{manager::MANAGER, types::ContentType};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::thread;
+use std::time::Duration;
+
+const PRUNE_STARTUP_DELAY: Duration = Duration::from_secs(60);
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const NANOS_PER_DAY: i64 = 86_400 * 1_000_000_000;
+
+/// Limits that apply to unpinned items. A `None` limit is not enforced.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RetentionLimits {
+    pub max_age_days: Option<u32>,
+    pub max_items: Option<u32>,
+    pub max_total_bytes: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RetentionSettings {
+    #[serde(flatten)]
+    pub limits: RetentionLimits,
+    pub per_type: HashMap<ContentType, RetentionLimits>,
+}
+
+#[derive(Serialize, Default, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PruneReport {
+    pub items_removed: u32,
+    pub files_removed: u32,
+    pub bytes_reclaimed: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct PruneCandidate {
+    pub id: i64,
+    pub content_type: ContentType,
+    pub last_copied_at: i64,
+    pub size_bytes: u64,
+}
+
+/// Walks `candidates` from newest to oldest and returns the ids that fall outside `limits`.
+fn select_expired<'a>(
+    candidates: impl Iterator<Item = &'a PruneCandidate>,
+    limits: &RetentionLimits,
+    now_nanos: i64,
+) -> Vec<i64> {
+    let cutoff = limits
+        .max_age_days
+        .map(|days| now_nanos.saturating_sub(days as i64 * NANOS_PER_DAY));
+    let mut kept_items: u32 = 0;
+    let mut kept_bytes: u64 = 0;
+    let mut expired = Vec::new();
+
+    for candidate in candidates {
+        let too_old = cutoff.is_some_and(|cutoff| candidate.last_copied_at < cutoff);
+        let too_many = limits.max_items.is_some_and(|max| kept_items >= max);
+        let too_big = limits
+            .max_total_bytes
+            .is_some_and(|max| kept_bytes + candidate.size_bytes > max);
+
+        if too_old || too_many || too_big {
+            expired.push(candidate.id);
+        } else {
+            kept_items += 1;
+            kept_bytes += candidate.size_bytes;
+        }
+    }
+    expired
+}
+
+impl RetentionLimits {
+    /// `candidates` must be unpinned items ordered by `last_copied_at` descending.
+    pub fn expired_ids(&self, candidates: &[PruneCandidate], now_nanos: i64) -> HashSet<i64> {
+        select_expired(candidates.iter(), self, now_nanos)
+            .into_iter()
+            .collect()
+    }
+}
+
+impl RetentionSettings {
+    /// `candidates` must be unpinned items ordered by `last_copied_at` descending.
+    pub fn expired_ids(&self, candidates: &[PruneCandidate], now_nanos: i64) -> HashSet<i64> {
+        let mut expired: HashSet<i64> = select_expired(candidates.iter(), &self.limits, now_nanos)
+            .into_iter()
+            .collect();
+
+        for (content_type, limits) in &self.per_type {
+            expired.extend(select_expired(
+                candidates
+                    .iter()
+                    .filter(|c| &c.content_type == content_type),
+                limits,
+                now_nanos,
+            ));
+        }
+        expired
+    }
+}
+
+pub fn start_pruning() {
+    thread::spawn(|| {
+        thread::sleep(PRUNE_STARTUP_DELAY);
+        loop {
+            if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+                match manager.prune() {
+                    Ok(report) if report.items_removed > 0 || report.files_removed > 0 => {
+                        println!(
+                            "[ClipboardHistory] Pruned {} items and {} files ({} bytes)",
+                            report.items_removed, report.files_removed, report.bytes_reclaimed
+                        );
+                    }
+                    Ok(_) => {}
+                    Err(e) => eprintln!("Error pruning clipboard history: {:?}", e),
+                }
+            }
+            thread::sleep(PRUNE_INTERVAL);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clipboard_history::manager::ClipboardHistoryManager;
+    use chrono::Utc;
+
+    fn candidate(id: i64, content_type: ContentType, age_days: i64, size: u64) -> PruneCandidate {
+        let now = Utc::now().timestamp_nanos_opt().unwrap();
+        PruneCandidate {
+            id,
+            content_type,
+            last_copied_at: now - age_days * NANOS_PER_DAY,
+            size_bytes: size,
+        }
+    }
+
+    fn now() -> i64 {
+        Utc::now().timestamp_nanos_opt().unwrap()
+    }
+
+    #[test]
+    fn test_no_limits_expire_nothing() {
+        let candidates = vec![candidate(1, ContentType::Text, 1000, 1_000_000)];
+        let settings = RetentionSettings::default();
+        assert!(settings.expired_ids(&candidates, now()).is_empty());
+    }
+
+    #[test]
+    fn test_max_age_expires_old_items() {
+        let candidates = vec![
+            candidate(1, ContentType::Text, 1, 10),
+            candidate(2, ContentType::Text, 10, 10),
+        ];
+        let settings = RetentionSettings {
+            limits: RetentionLimits {
+                max_age_days: Some(7),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(settings.expired_ids(&candidates, now()), HashSet::from([2]));
+    }
+
+    #[test]
+    fn test_max_items_and_bytes_keep_newest() {
+        let candidates = vec![
+            candidate(1, ContentType::Text, 0, 40),
+            candidate(2, ContentType::Text, 1, 40),
+            candidate(3, ContentType::Text, 2, 40),
+            candidate(4, ContentType::Text, 3, 40),
+        ];
+        let by_count = RetentionSettings {
+            limits: RetentionLimits {
+                max_items: Some(3),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(by_count.expired_ids(&candidates, now()), HashSet::from([4]));
+
+        let by_bytes = RetentionSettings {
+            limits: RetentionLimits {
+                max_total_bytes: Some(100),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            by_bytes.expired_ids(&candidates, now()),
+            HashSet::from([3, 4])
+        );
+    }
+
+    #[test]
+    fn test_per_type_limits_only_touch_their_type() {
+        let candidates = vec![
+            candidate(1, ContentType::Image, 0, 10),
+            candidate(2, ContentType::Text, 0, 10),
+            candidate(3, ContentType::Image, 1, 10),
+        ];
+        let settings = RetentionSettings {
+            per_type: HashMap::from([(
+                ContentType::Image,
+                RetentionLimits {
+                    max_items: Some(1),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        assert_eq!(settings.expired_ids(&candidates, now()), HashSet::from([3]));
+    }
+
+    #[test]
+    fn test_settings_roundtrip_uses_flat_limits() {
+        let json = r#"{"maxItems":5,"perType":{"image":{"maxAgeDays":2}}}"#;
+        let settings: RetentionSettings = serde_json::from_str(json).unwrap();
+        assert_eq!(settings.limits.max_items, Some(5));
+        assert_eq!(settings.per_type[&ContentType::Image].max_age_days, Some(2));
+    }
+
+    #[test]
+    fn test_prune_keeps_pinned_items() {
+        let mut manager = ClipboardHistoryManager::new_for_test().unwrap();
+        for i in 0..3 {
+            manager
+                .add_item(
+                    format!("h{}", i),
+                    ContentType::Text,
+                    format!("item {}", i),
+                    None,
+                )
+                .unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let oldest = manager.get_items("all".into(), None, None, 10, 0).unwrap()[2].id;
+        manager.toggle_pin(oldest).unwrap();
+
+        let mut settings = manager.settings().clone();
+        settings.retention.limits.max_items = Some(1);
+        manager.set_settings(settings).unwrap();
+
+        let report = manager.prune().unwrap();
+        assert_eq!(report.items_removed, 1);
+        assert_eq!(report.bytes_reclaimed, "item 1".len() as u64);
+
+        let remaining: Vec<String> = manager
+            .get_items("all".into(), None, None, 10, 0)
+            .unwrap()
+            .into_iter()
+            .map(|item| item.hash)
+            .collect();
+        assert_eq!(remaining, vec!["h2".to_string(), "h0".to_string()]);
+    }
+
+    #[test]
+    fn test_prune_applies_primary_limits_to_primary_items_only() {
+        let mut manager = ClipboardHistoryManager::new_for_test().unwrap();
+        for i in 0..3 {
+            manager
+                .add_item(
+                    format!("h{}", i),
+                    ContentType::Text,
+                    format!("item {}", i),
+                    None,
+                )
+                .unwrap();
+            manager
+                .add_primary_item(&format!("highlight {}", i), None)
+                .unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        let mut settings = manager.settings().clone();
+        settings.primary.retention = RetentionLimits {
+            max_items: Some(1),
+            ..Default::default()
+        };
+        manager.set_settings(settings).unwrap();
+
+        assert_eq!(manager.prune().unwrap().items_removed, 2);
+        let primary = manager
+            .get_items("primary".into(), None, None, 10, 0)
+            .unwrap();
+        assert_eq!(primary.len(), 1);
+        assert_eq!(primary[0].content_value.as_deref(), Some("highlight 2"));
+        assert_eq!(
+            manager
+                .get_items("all".into(), None, None, 10, 0)
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_prune_removes_orphaned_images() {
+        let manager = ClipboardHistoryManager::new_for_test().unwrap();
+        let kept = manager.image_path("kept");
+        let orphan = manager.image_path("orphan");
+        let just_saved = manager.image_path("just-saved");
+        std::fs::write(&kept, [0u8; 8]).unwrap();
+        std::fs::write(&orphan, [0u8; 16]).unwrap();
+        std::fs::write(&just_saved, [0u8; 4]).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&orphan)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(3600))
+            .unwrap();
+        manager
+            .add_item(
+                "kept".into(),
+                ContentType::Image,
+                kept.to_string_lossy().to_string(),
+                None,
+            )
+            .unwrap();
+
+        let report = manager.prune().unwrap();
+
+        assert_eq!(report.files_removed, 1);
+        assert_eq!(report.bytes_reclaimed, 16);
+        assert!(kept.exists());
+        assert!(!orphan.exists());
+        assert!(
+            just_saved.exists(),
+            "images still waiting for their row are kept"
+        );
+        std::fs::remove_dir_all(&manager.image_dir).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+use crate::error::AppError;
+use hmac::{Hmac, Mac};
+use rusqlite::{params, Connection};
+use sha2::Sha256;
+use std::collections::HashMap;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// The index never stores plaintext: every token is replaced by a keyed hash, so the
+// database only reveals which items share a token, not what the token is.
+pub const SEARCH_INDEX_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS clipboard_search_index (
+    token TEXT NOT NULL,
+    item_id INTEGER NOT NULL,
+    weight INTEGER NOT NULL,
+    PRIMARY KEY (token, item_id)
+) WITHOUT ROWID";
+pub const SEARCH_INDEX_ITEM_SCHEMA: &str =
+    "CREATE INDEX IF NOT EXISTS idx_clipboard_search_index_item ON clipboard_search_index (item_id)";
+
+const SEARCH_KEY_CONTEXT: &[u8] = b"clipboard-search-index-v1";
+const MIN_PREFIX_CHARS: usize = 2;
+const MAX_TOKEN_CHARS: usize = 32;
+const MAX_INDEXED_WORDS: usize = 5_000;
+const BLINDED_TOKEN_BYTES: usize = 16;
+
+const WEIGHT_PREFIX: i64 = 1;
+const WEIGHT_WORD: i64 = 2;
+
+/// Derives the key used to blind index tokens from the history encryption key, so the
+/// index can never be used to recover ciphertext or vice versa.
+pub fn derive_search_key(key: &[u8; 32]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(SEARCH_KEY_CONTEXT);
+    mac.finalize().into_bytes().into()
+}
+
+fn blind(token: &str, search_key: &[u8; 32]) -> String {
+    let mut mac = HmacSha256::new_from_slice(search_key).expect("HMAC accepts keys of any length");
+    mac.update(token.as_bytes());
+    hex::encode(&mac.finalize().into_bytes()[..BLINDED_TOKEN_BYTES])
+}
+
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase().chars().take(MAX_TOKEN_CHARS).collect())
+}
+
+/// Splits `text` into lowercase words and expands each into its prefixes, so that a query
+/// for "recei" finds "receipt". Whole words outrank prefixes.
+pub fn tokenize(text: &str) -> HashMap<String, i64> {
+    let mut tokens: HashMap<String, i64> = HashMap::new();
+
+    for word in words(text).take(MAX_INDEXED_WORDS) {
+        let char_count = word.chars().count();
+        for (len, (idx, ch)) in word.char_indices().enumerate().map(|(i, c)| (i + 1, c)) {
+            if len < MIN_PREFIX_CHARS && len < char_count {
+                continue;
+            }
+            let prefix = &word[..idx + ch.len_utf8()];
+            let weight = if len == char_count {
+                WEIGHT_WORD
+            } else {
+                WEIGHT_PREFIX
+            };
+            let entry = tokens.entry(prefix.to_string()).or_insert(weight);
+            *entry = (*entry).max(weight);
+        }
+    }
+
+    tokens
+}
+
+/// Turns a free-text search term into the blinded tokens every matching item must contain.
+pub fn query_tokens(term: &str, search_key: &[u8; 32]) -> Vec<String> {
+    let mut blinded: Vec<String> = words(term).map(|w| blind(&w, search_key)).collect();
+    blinded.sort();
+    blinded.dedup();
+    blinded
+}
+
+pub fn index_item(
+    db: &Connection,
+    item_id: i64,
+    text: &str,
+    search_key: &[u8; 32],
+) -> Result<(), AppError> {
+    let mut stmt = db.prepare_cached(
+        "INSERT INTO clipboard_search_index (token, item_id, weight) VALUES (?, ?, ?)
+         ON CONFLICT(token, item_id) DO UPDATE SET weight = MAX(weight, excluded.weight)",
+    )?;
+    for (token, weight) in tokenize(text) {
+        stmt.execute(params![blind(&token, search_key), item_id, weight])?;
+    }
+    Ok(())
+}
+
+pub fn remove_item(db: &Connection, item_id: i64) -> Result<(), AppError> {
+    db.execute(
+        "DELETE FROM clipboard_search_index WHERE item_id = ?",
+        params![item_id],
+    )?;
+    Ok(())
+}
+
+pub fn remove_orphans(db: &Connection) -> Result<(), AppError> {
+    db.execute(
+        "DELETE FROM clipboard_search_index WHERE item_id NOT IN (SELECT id FROM clipboard_history)",
+        [],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clipboard_history::manager::ClipboardHistoryManager;
+    use crate::clipboard_history::types::ContentType;
+
+    #[test]
+    fn test_tokenize_emits_words_and_prefixes() {
+        let tokens = tokenize("Hello, World");
+        assert_eq!(tokens.get("hello"), Some(&WEIGHT_WORD));
+        assert_eq!(tokens.get("hel"), Some(&WEIGHT_PREFIX));
+        assert_eq!(tokens.get("wo"), Some(&WEIGHT_PREFIX));
+        assert!(!tokens.contains_key("h"));
+        assert!(!tokens.contains_key("ello"));
+    }
+
+    #[test]
+    fn test_tokenize_keeps_single_char_words() {
+        let tokens = tokenize("a b");
+        assert_eq!(tokens.get("a"), Some(&WEIGHT_WORD));
+        assert_eq!(tokens.get("b"), Some(&WEIGHT_WORD));
+    }
+
+    #[test]
+    fn test_tokenize_prefers_word_weight_over_prefix() {
+        let tokens = tokenize("cat category");
+        assert_eq!(tokens.get("cat"), Some(&WEIGHT_WORD));
+    }
+
+    #[test]
+    fn test_tokenize_handles_unicode() {
+        let tokens = tokenize("Ünïcode façade");
+        assert_eq!(tokens.get("ünïcode"), Some(&WEIGHT_WORD));
+        assert_eq!(tokens.get("fa"), Some(&WEIGHT_PREFIX));
+    }
+
+    #[test]
+    fn test_blinding_depends_on_key() {
+        let key_a = derive_search_key(&[1; 32]);
+        let key_b = derive_search_key(&[2; 32]);
+        assert_eq!(blind("secret", &key_a), blind("secret", &key_a));
+        assert_ne!(blind("secret", &key_a), blind("secret", &key_b));
+        assert!(!blind("secret", &key_a).contains("secret"));
+    }
+
+    #[test]
+    fn test_query_tokens_are_deduplicated() {
+        let key = derive_search_key(&[0; 32]);
+        assert_eq!(query_tokens("foo FOO foo", &key).len(), 1);
+        assert!(query_tokens("  ...  ", &key).is_empty());
+    }
+
+    #[test]
+    fn test_search_finds_items_outside_first_page() {
+        let manager = ClipboardHistoryManager::new_for_test().unwrap();
+        manager
+            .add_item(
+                "old".into(),
+                ContentType::Text,
+                "invoice number 4711".into(),
+                None,
+            )
+            .unwrap();
+        for i in 0..50 {
+            manager
+                .add_item(
+                    format!("filler{}", i),
+                    ContentType::Text,
+                    format!("filler text {}", i),
+                    None,
+                )
+                .unwrap();
+        }
+
+        let results = manager
+            .get_items("all".into(), Some("invoice".into()), None, 10, 0)
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].hash, "old");
+    }
+
+    #[test]
+    fn test_search_requires_every_term_and_pages() {
+        let manager = ClipboardHistoryManager::new_for_test().unwrap();
+        for i in 0..5 {
+            manager
+                .add_item(
+                    format!("match{}", i),
+                    ContentType::Text,
+                    format!("deploy script {}", i),
+                    None,
+                )
+                .unwrap();
+        }
+        manager
+            .add_item(
+                "partial".into(),
+                ContentType::Text,
+                "deploy notes".into(),
+                None,
+            )
+            .unwrap();
+
+        let page1 = manager
+            .get_items("all".into(), Some("dep scr".into()), None, 3, 0)
+            .unwrap();
+        let page2 = manager
+            .get_items("all".into(), Some("dep scr".into()), None, 3, 3)
+            .unwrap();
+        assert_eq!(page1.len(), 3);
+        assert_eq!(page2.len(), 2);
+        assert!(page1
+            .iter()
+            .chain(page2.iter())
+            .all(|item| item.hash.starts_with("match")));
+    }
+
+    #[test]
+    fn test_search_ranks_whole_words_first() {
+        let manager = ClipboardHistoryManager::new_for_test().unwrap();
+        manager
+            .add_item("exact".into(), ContentType::Text, "cat".into(), None)
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        manager
+            .add_item("prefix".into(), ContentType::Text, "category".into(), None)
+            .unwrap();
+
+        let results = manager
+            .get_items("all".into(), Some("cat".into()), None, 10, 0)
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].hash, "exact");
+    }
+
+    #[test]
+    fn test_deleted_items_leave_no_index_entries() {
+        let manager = ClipboardHistoryManager::new_for_test().unwrap();
+        manager
+            .add_item("gone".into(), ContentType::Text, "ephemeral".into(), None)
+            .unwrap();
+        let id = manager.get_items("all".into(), None, None, 1, 0).unwrap()[0].id;
+
+        manager.delete_item(id).unwrap();
+
+        let results = manager
+            .get_items("all".into(), Some("ephemeral".into()), None, 10, 0)
+            .unwrap();
+        assert!(results.is_empty());
+    }
+}
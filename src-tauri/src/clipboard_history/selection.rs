@@ -0,0 +1,470 @@
+use super::types::ClipboardFormats;
+use std::collections::HashSet;
+
+/// Targets that password managers attach to secrets so clipboard managers skip them.
+const CONCEALED_MIME_TYPES: &[&str] = &[
+    "x-kde-passwordManagerHint",
+    "CLIPBOARD_CONCEALED",
+    "org.nspasteboard.ConcealedType",
+    "ExcludeClipboardContentFromMonitorProcessing",
+];
+
+const HTML_MIME_TYPE: &str = "text/html";
+const RTF_MIME_TYPES: &[&str] = &["text/rtf", "application/rtf"];
+const URI_LIST_MIME_TYPE: &str = "text/uri-list";
+/// Nautilus only pastes files that are offered under its own target.
+const GNOME_COPIED_FILES_MIME_TYPE: &str = "x-special/gnome-copied-files";
+const TEXT_MIME_TYPES: &[&str] = &[
+    "text/plain;charset=utf-8",
+    "UTF8_STRING",
+    "text/plain",
+    "STRING",
+    "TEXT",
+];
+
+fn is_wayland() -> bool {
+    std::env::var("WAYLAND_DISPLAY").is_ok()
+}
+
+/// Lists the MIME types (X11 targets) currently offered on the clipboard, or `None` if the
+/// clipboard owner can't be queried.
+pub fn clipboard_mime_types() -> Option<HashSet<String>> {
+    if is_wayland() {
+        wayland::mime_types()
+    } else {
+        x11::mime_types()
+    }
+}
+
+pub fn has_concealed_hint(mime_types: &HashSet<String>) -> bool {
+    CONCEALED_MIME_TYPES
+        .iter()
+        .any(|hint| mime_types.contains(*hint))
+}
+
+fn read_clipboard_text(mime_type: &str) -> Option<String> {
+    let bytes = if is_wayland() {
+        wayland::read(mime_type)
+    } else {
+        x11::read(mime_type)
+    }?;
+    let text = String::from_utf8_lossy(&bytes)
+        .trim_end_matches('\0')
+        .to_string();
+    Some(text).filter(|text| !text.trim().is_empty())
+}
+
+/// Reads the rich representations among `mime_types` that the history keeps next to plain text.
+pub fn read_clipboard_formats(mime_types: &HashSet<String>) -> ClipboardFormats {
+    let read = |mime_type: &&str| {
+        mime_types
+            .contains(*mime_type)
+            .then(|| read_clipboard_text(mime_type))
+            .flatten()
+    };
+    ClipboardFormats {
+        html: read(&HTML_MIME_TYPE),
+        rtf: RTF_MIME_TYPES.iter().find_map(read),
+        uri_list: read(&URI_LIST_MIME_TYPE).filter(|list| !uri_list_paths(list).is_empty()),
+    }
+}
+
+/// Decodes the local files in a `text/uri-list`, skipping comments and remote URIs.
+pub fn uri_list_paths(uri_list: &str) -> Vec<String> {
+    uri_list
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| url::Url::parse(line).ok()?.to_file_path().ok())
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Builds a `text/uri-list` for a single local path or `file://` URI.
+pub fn path_to_uri_list(path: &str) -> Option<String> {
+    if path.starts_with("file://") {
+        return Some(format!("{}\r\n", path));
+    }
+    url::Url::from_file_path(path)
+        .ok()
+        .map(|uri| format!("{}\r\n", uri))
+}
+
+fn format_targets(text: Option<&str>, formats: &ClipboardFormats) -> Vec<(String, Vec<u8>)> {
+    let mut targets = Vec::new();
+    if let Some(html) = &formats.html {
+        targets.push((HTML_MIME_TYPE.to_string(), html.as_bytes().to_vec()));
+    }
+    if let Some(rtf) = &formats.rtf {
+        for mime_type in RTF_MIME_TYPES {
+            targets.push((mime_type.to_string(), rtf.as_bytes().to_vec()));
+        }
+    }
+    if let Some(uri_list) = &formats.uri_list {
+        let uris: Vec<&str> = uri_list
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        targets.push((URI_LIST_MIME_TYPE.to_string(), uri_list.as_bytes().to_vec()));
+        targets.push((
+            GNOME_COPIED_FILES_MIME_TYPE.to_string(),
+            format!("copy\n{}", uris.join("\n")).into_bytes(),
+        ));
+    }
+    if let Some(text) = text {
+        for mime_type in TEXT_MIME_TYPES {
+            targets.push((mime_type.to_string(), text.as_bytes().to_vec()));
+        }
+    }
+    targets
+}
+
+/// Takes ownership of the clipboard and offers `text` together with every rich format at once,
+/// which neither arboard nor the clipboard plugin can do.
+pub fn write_clipboard_formats(
+    text: Option<&str>,
+    formats: &ClipboardFormats,
+) -> Result<(), String> {
+    let targets = format_targets(text, formats);
+    if is_wayland() {
+        wayland::write(targets)
+    } else {
+        x11::write(targets)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod x11 {
+    use std::collections::HashSet;
+    use std::time::{Duration, Instant};
+    use x11rb::connection::{Connection, RequestConnection};
+    use x11rb::protocol::xproto::{
+        Atom, AtomEnum, ConnectionExt, CreateWindowAux, EventMask, PropMode, SelectionNotifyEvent,
+        Window, WindowClass, SELECTION_NOTIFY_EVENT,
+    };
+    use x11rb::protocol::Event;
+    use x11rb::rust_connection::RustConnection;
+    use x11rb::wrapper::ConnectionExt as _;
+    use x11rb::{COPY_DEPTH_FROM_PARENT, CURRENT_TIME};
+
+    const CONVERT_TIMEOUT: Duration = Duration::from_millis(250);
+    const PROPERTY_NAME: &str = "FLARE_CLIPBOARD_HISTORY";
+
+    fn intern(conn: &RustConnection, name: &str) -> Option<Atom> {
+        Some(
+            conn.intern_atom(false, name.as_bytes())
+                .ok()?
+                .reply()
+                .ok()?
+                .atom,
+        )
+    }
+
+    /// A hidden window to receive selection conversions, destroyed on drop.
+    pub struct Requestor {
+        conn: RustConnection,
+        window: Window,
+    }
+
+    impl Requestor {
+        pub fn new() -> Option<Self> {
+            let (conn, screen_num) = x11rb::connect(None).ok()?;
+            let screen = conn.setup().roots.get(screen_num)?;
+            let (root, visual) = (screen.root, screen.root_visual);
+            let window = conn.generate_id().ok()?;
+            conn.create_window(
+                COPY_DEPTH_FROM_PARENT,
+                window,
+                root,
+                0,
+                0,
+                1,
+                1,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                visual,
+                &CreateWindowAux::new(),
+            )
+            .ok()?;
+            Some(Self { conn, window })
+        }
+
+        /// Asks the owner of `selection` to convert it to `target` and returns the raw bytes
+        /// along with the type the owner answered with. Incremental transfers aren't supported.
+        pub fn convert(&self, selection: &str, target: &str) -> Option<(Atom, Vec<u8>)> {
+            let conn = &self.conn;
+            let property = intern(conn, PROPERTY_NAME)?;
+            conn.convert_selection(
+                self.window,
+                intern(conn, selection)?,
+                intern(conn, target)?,
+                property,
+                CURRENT_TIME,
+            )
+            .ok()?;
+            conn.flush().ok()?;
+
+            let deadline = Instant::now() + CONVERT_TIMEOUT;
+            loop {
+                match conn.poll_for_event().ok()? {
+                    Some(Event::SelectionNotify(event)) if event.requestor == self.window => {
+                        if event.property == x11rb::NONE {
+                            return None;
+                        }
+                        break;
+                    }
+                    Some(_) => continue,
+                    None if Instant::now() >= deadline => return None,
+                    None => std::thread::sleep(Duration::from_millis(5)),
+                }
+            }
+
+            let reply = conn
+                .get_property(true, self.window, property, AtomEnum::ANY, 0, u32::MAX / 4)
+                .ok()?
+                .reply()
+                .ok()?;
+            if Some(reply.type_) == intern(conn, "INCR") {
+                return None;
+            }
+            Some((reply.type_, reply.value))
+        }
+
+        pub fn targets(&self, selection: &str) -> Option<HashSet<String>> {
+            let (_, value) = self.convert(selection, "TARGETS")?;
+            let atoms = value
+                .chunks_exact(4)
+                .map(|chunk| u32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+            Some(
+                atoms
+                    .filter_map(|atom| self.conn.get_atom_name(atom).ok()?.reply().ok())
+                    .map(|reply| String::from_utf8_lossy(&reply.name).into_owned())
+                    .collect(),
+            )
+        }
+    }
+
+    impl Drop for Requestor {
+        fn drop(&mut self) {
+            let _ = self.conn.destroy_window(self.window);
+            let _ = self.conn.flush();
+        }
+    }
+
+    pub fn mime_types() -> Option<HashSet<String>> {
+        Requestor::new()?.targets("CLIPBOARD")
+    }
+
+    pub fn read(mime_type: &str) -> Option<Vec<u8>> {
+        Requestor::new()?
+            .convert("CLIPBOARD", mime_type)
+            .map(|(_, value)| value)
+    }
+
+    /// Owns CLIPBOARD from a background thread until another client takes it over. Payloads
+    /// are sent in one piece, so anything beyond the server's maximum request size is refused.
+    pub fn write(targets: Vec<(String, Vec<u8>)>) -> Result<(), String> {
+        let owner = Requestor::new().ok_or("Failed to connect to the X server")?;
+        let conn = &owner.conn;
+        let atom = |name: &str| intern(conn, name).ok_or(format!("Failed to intern {}", name));
+        let clipboard = atom("CLIPBOARD")?;
+        let targets_atom = atom("TARGETS")?;
+        let mut offered = Vec::with_capacity(targets.len());
+        for (mime_type, data) in targets {
+            offered.push((atom(&mime_type)?, data));
+        }
+        let max_bytes = conn.maximum_request_bytes().saturating_sub(64);
+
+        conn.set_selection_owner(owner.window, clipboard, CURRENT_TIME)
+            .map_err(|e| e.to_string())?;
+        let current_owner = conn
+            .get_selection_owner(clipboard)
+            .map_err(|e| e.to_string())?
+            .reply()
+            .map_err(|e| e.to_string())?
+            .owner;
+        if current_owner != owner.window {
+            return Err("Failed to take ownership of the clipboard".to_string());
+        }
+
+        std::thread::spawn(move || {
+            let conn = &owner.conn;
+            while let Ok(event) = conn.wait_for_event() {
+                match event {
+                    Event::SelectionRequest(request) => {
+                        let property = if request.property == x11rb::NONE {
+                            request.target
+                        } else {
+                            request.property
+                        };
+                        let served = if request.target == targets_atom {
+                            let mut atoms: Vec<Atom> =
+                                offered.iter().map(|(atom, _)| *atom).collect();
+                            atoms.push(targets_atom);
+                            conn.change_property32(
+                                PropMode::REPLACE,
+                                request.requestor,
+                                property,
+                                AtomEnum::ATOM,
+                                &atoms,
+                            )
+                            .is_ok()
+                        } else {
+                            offered
+                                .iter()
+                                .find(|(atom, data)| {
+                                    *atom == request.target && data.len() <= max_bytes
+                                })
+                                .is_some_and(|(atom, data)| {
+                                    conn.change_property8(
+                                        PropMode::REPLACE,
+                                        request.requestor,
+                                        property,
+                                        *atom,
+                                        data,
+                                    )
+                                    .is_ok()
+                                })
+                        };
+                        let notify = SelectionNotifyEvent {
+                            response_type: SELECTION_NOTIFY_EVENT,
+                            sequence: 0,
+                            time: request.time,
+                            requestor: request.requestor,
+                            selection: request.selection,
+                            target: request.target,
+                            property: if served { property } else { x11rb::NONE },
+                        };
+                        let _ =
+                            conn.send_event(false, request.requestor, EventMask::NO_EVENT, notify);
+                        let _ = conn.flush();
+                    }
+                    Event::SelectionClear(_) => break,
+                    _ => {}
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod x11 {
+    use std::collections::HashSet;
+
+    pub fn mime_types() -> Option<HashSet<String>> {
+        None
+    }
+
+    pub fn read(_mime_type: &str) -> Option<Vec<u8>> {
+        None
+    }
+
+    pub fn write(_targets: Vec<(String, Vec<u8>)>) -> Result<(), String> {
+        Err("Rich clipboard formats are not supported on this platform".to_string())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod wayland {
+    use std::collections::HashSet;
+    use std::io::Read;
+    use wl_clipboard_rs::copy::{self, MimeSource, Source};
+    use wl_clipboard_rs::paste::{get_contents, get_mime_types, ClipboardType, MimeType, Seat};
+
+    pub fn mime_types() -> Option<HashSet<String>> {
+        get_mime_types(ClipboardType::Regular, Seat::Unspecified).ok()
+    }
+
+    pub fn read(mime_type: &str) -> Option<Vec<u8>> {
+        let (mut pipe, _) = get_contents(
+            ClipboardType::Regular,
+            Seat::Unspecified,
+            MimeType::Specific(mime_type),
+        )
+        .ok()?;
+        let mut bytes = Vec::new();
+        pipe.read_to_end(&mut bytes).ok()?;
+        Some(bytes)
+    }
+
+    pub fn write(targets: Vec<(String, Vec<u8>)>) -> Result<(), String> {
+        let sources = targets
+            .into_iter()
+            .map(|(mime_type, data)| MimeSource {
+                source: Source::Bytes(data.into_boxed_slice()),
+                mime_type: copy::MimeType::Specific(mime_type),
+            })
+            .collect();
+        let mut options = copy::Options::new();
+        options.omit_additional_text_mime_types(true);
+        options.copy_multi(sources).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod wayland {
+    use std::collections::HashSet;
+
+    pub fn mime_types() -> Option<HashSet<String>> {
+        None
+    }
+
+    pub fn read(_mime_type: &str) -> Option<Vec<u8>> {
+        None
+    }
+
+    pub fn write(_targets: Vec<(String, Vec<u8>)>) -> Result<(), String> {
+        Err("Rich clipboard formats are not supported on this platform".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_has_concealed_hint() {
+        let secret: HashSet<String> = ["UTF8_STRING", "x-kde-passwordManagerHint"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let plain: HashSet<String> = ["UTF8_STRING", "text/plain"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(has_concealed_hint(&secret));
+        assert!(!has_concealed_hint(&plain));
+    }
+
+    #[test]
+    fn test_uri_list_paths_decode_local_files() {
+        let list = "# copied from Dolphin\r\nfile:///home/me/My%20Notes.txt\r\nhttps://example.com/a\r\nfile:///tmp/b\r\n";
+        assert_eq!(
+            uri_list_paths(list),
+            vec!["/home/me/My Notes.txt".to_string(), "/tmp/b".to_string()]
+        );
+        assert_eq!(
+            path_to_uri_list("/home/me/My Notes.txt").as_deref(),
+            Some("file:///home/me/My%20Notes.txt\r\n")
+        );
+    }
+
+    #[test]
+    fn test_format_targets_offer_files_to_nautilus() {
+        let formats = ClipboardFormats {
+            uri_list: Some("file:///tmp/a\r\nfile:///tmp/b\r\n".into()),
+            ..Default::default()
+        };
+        let targets = format_targets(Some("/tmp/a\n/tmp/b"), &formats);
+        let gnome = targets
+            .iter()
+            .find(|(mime_type, _)| mime_type == GNOME_COPIED_FILES_MIME_TYPE)
+            .unwrap();
+        assert_eq!(gnome.1, b"copy\nfile:///tmp/a\nfile:///tmp/b".to_vec());
+        assert!(targets
+            .iter()
+            .any(|(mime_type, _)| mime_type == "UTF8_STRING"));
+    }
+}
@@ -0,0 +1,104 @@
+use super::key_provider::KeyProviderKind;
+use super::ocr::OcrSettings;
+use super::primary::PrimarySelectionSettings;
+use super::retention::RetentionSettings;
+use super::sync::SyncSettings;
+use crate::error::AppError;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+pub const SETTINGS_FILENAME: &str = "clipboard_history_settings.json";
+
+/// Window classes of common password managers, which are excluded until the user says otherwise.
+pub(crate) const DEFAULT_EXCLUDED_APPS: &[&str] = &[
+    "KeePassXC",
+    "org.keepassxc.KeePassXC",
+    "1Password",
+    "Bitwarden",
+    "Enpass",
+    "gnome-keyring",
+    "seahorse",
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ClipboardHistorySettings {
+    pub retention: RetentionSettings,
+    /// Window classes whose copies are never recorded, compared case-insensitively.
+    pub excluded_apps: Vec<String>,
+    pub key_provider: KeyProviderKind,
+    pub ocr: OcrSettings,
+    pub sync: SyncSettings,
+    /// Recording of highlighted text, Linux only.
+    pub primary: PrimarySelectionSettings,
+}
+
+impl Default for ClipboardHistorySettings {
+    fn default() -> Self {
+        Self {
+            retention: RetentionSettings::default(),
+            excluded_apps: DEFAULT_EXCLUDED_APPS
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            key_provider: KeyProviderKind::default(),
+            ocr: OcrSettings::default(),
+            sync: SyncSettings::default(),
+            primary: PrimarySelectionSettings::default(),
+        }
+    }
+}
+
+impl ClipboardHistorySettings {
+    pub fn is_excluded_app(&self, app_name: Option<&str>) -> bool {
+        app_name.is_some_and(|name| {
+            self.excluded_apps
+                .iter()
+                .any(|excluded| excluded.eq_ignore_ascii_case(name))
+        })
+    }
+}
+
+pub fn read_settings(path: &Path) -> Result<ClipboardHistorySettings, AppError> {
+    if !path.exists() {
+        return Ok(ClipboardHistorySettings::default());
+    }
+    let content = fs::read_to_string(path)?;
+    if content.trim().is_empty() {
+        return Ok(ClipboardHistorySettings::default());
+    }
+    serde_json::from_str(&content).map_err(|e| AppError::Serialization(e.to_string()))
+}
+
+pub fn write_settings(path: &Path, settings: &ClipboardHistorySettings) -> Result<(), AppError> {
+    let content = serde_json::to_string_pretty(settings)
+        .map_err(|e| AppError::Serialization(e.to_string()))?;
+    fs::write(path, content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_excluded_app_ignores_case() {
+        let settings = ClipboardHistorySettings::default();
+        assert!(settings.is_excluded_app(Some("keepassxc")));
+        assert!(!settings.is_excluded_app(Some("firefox")));
+        assert!(!settings.is_excluded_app(None));
+    }
+
+    #[test]
+    fn test_missing_fields_use_defaults() {
+        let settings: ClipboardHistorySettings =
+            serde_json::from_str(r#"{"retention":{"maxItems":10}}"#).unwrap();
+        assert_eq!(settings.retention.limits.max_items, Some(10));
+        assert!(settings.is_excluded_app(Some("Bitwarden")));
+
+        let settings: ClipboardHistorySettings =
+            serde_json::from_str(r#"{"excludedApps":[]}"#).unwrap();
+        assert!(!settings.is_excluded_app(Some("Bitwarden")));
+    }
+}
@@ -0,0 +1,845 @@
+//! Opt-in sync of history items between paired devices on the local network.
+//!
+//! Devices are paired by entering the same pairing code, from which the shared key is
+//! derived. Every device advertises itself over mDNS/DNS-SD with a TXT record carrying a tag
+//! of the shared key, so only devices of the same pairing group talk to each other. Peers
+//! periodically pull the items that changed on the other side since the last pull over TCP.
+//! Both ends of a connection first prove they hold the shared key by sealing the other's
+//! random nonce, and every frame after that is encrypted with it. A pull goes page by page;
+//! every page comes with a cursor into the other side's own order of changes, `sync_seq`,
+//! where the next page continues, and a page that doesn't move the cursor ends the pull.
+//! Items are merged by `hash` through `import_items`, which keeps the earliest first copy,
+//! the latest `last_copied_at`, and the pin of whichever side changed the item last.
+//! (Un)pinning an item marks it modified, so it is sent again. Deletions aren't synced.
+
+use super::backup::{derive_key, ExportedItem};
+use super::encryption::{decrypt_bytes, encrypt_bytes};
+use super::manager::{ClipboardHistoryManager, MANAGER};
+use crate::error::AppError;
+use mdns_sd::{Receiver, ResolvedService, ServiceDaemon, ServiceEvent, ServiceInfo};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SERVICE_TYPE: &str = "_flare-clipsync._tcp.local.";
+const PROTOCOL_VERSION: u32 = 1;
+pub const DEFAULT_PORT: u16 = 47822;
+
+const PAIRING_SALT: &[u8] = b"flare-clipboard-sync";
+const PAIRING_ITERATIONS: u32 = 100_000;
+const PAIRING_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const PAIRING_CODE_GROUPS: usize = 4;
+const PAIRING_GROUP_LENGTH: usize = 5;
+
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+const PULL_INTERVAL: Duration = Duration::from_secs(15);
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Most connections served at once.
+const MAX_CONNECTIONS: usize = 4;
+
+const NONCE_BYTES: usize = 32;
+const SERVER_ROLE: &[u8; 6] = b"server";
+const CLIENT_ROLE: &[u8; 6] = b"client";
+/// A role and a nonce sealed with the shared key, with the AES-GCM nonce and tag around them.
+const PROOF_BYTES: usize = 12 + SERVER_ROLE.len() + NONCE_BYTES + 16;
+
+/// Most items asked for in one page.
+const PAGE_ITEMS: usize = 100;
+/// Most JSON bytes of the items in one page. Bigger items aren't synced.
+const MAX_PAGE_BYTES: usize = 16 * 1024 * 1024;
+/// A page plus room for the message around its items and the encryption overhead.
+const MAX_FRAME_BYTES: usize = MAX_PAGE_BYTES + 64 * 1024;
+
+static PEERS: Lazy<Mutex<HashMap<String, Peer>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static ACTIVE_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SyncScope {
+    #[default]
+    Pinned,
+    All,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SyncSettings {
+    pub enabled: bool,
+    /// Which of this device's items are offered to peers.
+    pub scope: SyncScope,
+    /// TCP port for peers to pull from. Falls back to a free port if it's taken, e.g. by a
+    /// second instance on the same machine.
+    pub port: u16,
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            scope: SyncScope::default(),
+            port: DEFAULT_PORT,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Peer {
+    pub device_id: String,
+    pub address: SocketAddr,
+    /// The cursor the peer sent with its last answer; the next pull starts there.
+    pub synced_until: i64,
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncStatus {
+    pub paired: bool,
+    pub enabled: bool,
+    pub device_id: String,
+    pub peers: Vec<Peer>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum Message {
+    Pull {
+        since: i64,
+        limit: usize,
+    },
+    Items {
+        items: Vec<ExportedItem>,
+        /// Where the next pull continues, in the sender's own order of changes.
+        cursor: i64,
+    },
+}
+
+fn normalize_pairing_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// A random code like `K7QX2-M9RTA-4HWZP-C3NEV` (100 bits) to enter on the other devices.
+pub fn generate_pairing_code() -> String {
+    (0..PAIRING_CODE_GROUPS)
+        .map(|_| {
+            (0..PAIRING_GROUP_LENGTH)
+                .map(|_| PAIRING_ALPHABET[rand::random_range(0..PAIRING_ALPHABET.len())] as char)
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+pub fn key_from_pairing_code(code: &str) -> Result<[u8; 32], AppError> {
+    let normalized = normalize_pairing_code(code);
+    if normalized.len() != PAIRING_CODE_GROUPS * PAIRING_GROUP_LENGTH {
+        return Err(AppError::ClipboardHistory("Invalid pairing code".into()));
+    }
+    Ok(derive_key(&normalized, PAIRING_SALT, PAIRING_ITERATIONS))
+}
+
+/// Identifies the pairing group in advertisements without revealing the key.
+fn group_tag(key: &[u8; 32]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"group");
+    hasher.update(key);
+    hex::encode(&hasher.finalize()[..8])
+}
+
+/// Proves holding the shared key by sealing the other side's `nonce`. The role keeps a proof
+/// from being sent back as the answer to itself.
+fn seal_proof(
+    role: &[u8; 6],
+    nonce: &[u8; NONCE_BYTES],
+    key: &[u8; 32],
+) -> Result<Vec<u8>, AppError> {
+    encrypt_bytes(&[&role[..], nonce].concat(), key)
+}
+
+fn check_proof(
+    proof: &[u8; PROOF_BYTES],
+    role: &[u8; 6],
+    nonce: &[u8; NONCE_BYTES],
+    key: &[u8; 32],
+) -> Result<(), AppError> {
+    match decrypt_bytes(proof, key) {
+        Ok(opened) if opened == [&role[..], nonce].concat() => Ok(()),
+        _ => Err(AppError::ClipboardHistory(
+            "Peer isn't paired with this device".into(),
+        )),
+    }
+}
+
+/// The serving side of the handshake: answers the client's nonce with its own and a proof,
+/// then checks the client's proof. Reads nothing but fixed-size fields.
+fn accept_handshake(stream: &mut TcpStream, key: &[u8; 32]) -> Result<(), AppError> {
+    let mut client_nonce = [0u8; NONCE_BYTES];
+    stream.read_exact(&mut client_nonce)?;
+    let server_nonce: [u8; NONCE_BYTES] = rand::random();
+    stream.write_all(&server_nonce)?;
+    stream.write_all(&seal_proof(SERVER_ROLE, &client_nonce, key)?)?;
+    stream.flush()?;
+    let mut proof = [0u8; PROOF_BYTES];
+    stream.read_exact(&mut proof)?;
+    check_proof(&proof, CLIENT_ROLE, &server_nonce, key)
+}
+
+/// The pulling side of the handshake, see `accept_handshake`.
+fn open_handshake(stream: &mut TcpStream, key: &[u8; 32]) -> Result<(), AppError> {
+    let client_nonce: [u8; NONCE_BYTES] = rand::random();
+    stream.write_all(&client_nonce)?;
+    stream.flush()?;
+    let mut server_nonce = [0u8; NONCE_BYTES];
+    stream.read_exact(&mut server_nonce)?;
+    let mut proof = [0u8; PROOF_BYTES];
+    stream.read_exact(&mut proof)?;
+    check_proof(&proof, SERVER_ROLE, &client_nonce, key)?;
+    stream.write_all(&seal_proof(CLIENT_ROLE, &server_nonce, key)?)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn write_message(
+    stream: &mut TcpStream,
+    key: &[u8; 32],
+    message: &Message,
+) -> Result<(), AppError> {
+    let json = serde_json::to_vec(message).map_err(|e| AppError::Serialization(e.to_string()))?;
+    let sealed = encrypt_bytes(&json, key)?;
+    stream.write_all(&(sealed.len() as u32).to_be_bytes())?;
+    stream.write_all(&sealed)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn read_message(stream: &mut TcpStream, key: &[u8; 32]) -> Result<Message, AppError> {
+    let mut length = [0u8; 4];
+    stream.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME_BYTES {
+        return Err(AppError::ClipboardHistory("Sync message too large".into()));
+    }
+    let mut sealed = vec![0u8; length];
+    stream.read_exact(&mut sealed)?;
+    let json = decrypt_bytes(&sealed, key)
+        .map_err(|_| AppError::ClipboardHistory("Peer isn't paired with this device".into()))?;
+    serde_json::from_slice(&json).map_err(|e| AppError::Serialization(e.to_string()))
+}
+
+/// Answers the page requests of one pull with the items and cursor `export` returns for the
+/// requested `since` and `limit`, until a page doesn't move the cursor.
+fn serve_connection(
+    mut stream: TcpStream,
+    key: &[u8; 32],
+    mut export: impl FnMut(i64, usize) -> Result<(Vec<ExportedItem>, i64), AppError>,
+) -> Result<(), AppError> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    accept_handshake(&mut stream, key)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    loop {
+        match read_message(&mut stream, key)? {
+            Message::Pull { since, limit } => {
+                let (items, cursor) = export(since, limit.min(PAGE_ITEMS))?;
+                write_message(&mut stream, key, &Message::Items { items, cursor })?;
+                if cursor <= since {
+                    return Ok(());
+                }
+            }
+            Message::Items { .. } => {
+                return Err(AppError::ClipboardHistory("Unexpected sync message".into()))
+            }
+        }
+    }
+}
+
+fn export_for_peer(
+    manager: &ClipboardHistoryManager,
+    scope: SyncScope,
+    since: i64,
+    limit: usize,
+) -> Result<(Vec<ExportedItem>, i64), AppError> {
+    manager.export_changes(since, scope == SyncScope::Pinned, limit, MAX_PAGE_BYTES)
+}
+
+/// Fetches the items that changed on `address` after its cursor `since`, page by page, and
+/// hands each page to `import` along with the cursor the next page starts at.
+fn pull(
+    address: SocketAddr,
+    key: &[u8; 32],
+    mut since: i64,
+    mut import: impl FnMut(Vec<ExportedItem>, i64) -> Result<(), AppError>,
+) -> Result<(), AppError> {
+    let mut stream = TcpStream::connect_timeout(&address, IO_TIMEOUT)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    open_handshake(&mut stream, key)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    loop {
+        let limit = PAGE_ITEMS;
+        write_message(&mut stream, key, &Message::Pull { since, limit })?;
+        match read_message(&mut stream, key)? {
+            Message::Items { items, cursor } => {
+                if cursor <= since {
+                    return Ok(());
+                }
+                import(items, cursor)?;
+                since = cursor;
+            }
+            Message::Pull { .. } => {
+                return Err(AppError::ClipboardHistory("Unexpected sync message".into()))
+            }
+        }
+    }
+}
+
+fn mdns_error(e: mdns_sd::Error) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+/// The device id of an instance of `SERVICE_TYPE`, which is the instance name.
+fn device_id_of(fullname: &str) -> Option<&str> {
+    fullname.strip_suffix(SERVICE_TYPE)?.strip_suffix('.')
+}
+
+/// The device and address to pull from if `service` is another device of `group`.
+fn peer_of(
+    service: &ResolvedService,
+    group: &str,
+    own_device_id: &str,
+) -> Option<(String, SocketAddr)> {
+    let device_id = device_id_of(service.get_fullname())?;
+    if service.get_property_val_str("version") != Some(&PROTOCOL_VERSION.to_string())
+        || service.get_property_val_str("group") != Some(group)
+        || device_id == own_device_id
+    {
+        return None;
+    }
+    let ip = service.get_addresses_v4().into_iter().next()?;
+    Some((
+        device_id.to_string(),
+        SocketAddr::new(ip.into(), service.get_port()),
+    ))
+}
+
+/// One of the `MAX_CONNECTIONS` served at once, given back when dropped.
+struct ConnectionSlot;
+
+impl ConnectionSlot {
+    fn take() -> Option<Self> {
+        ACTIVE_CONNECTIONS
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |active| {
+                (active < MAX_CONNECTIONS).then_some(active + 1)
+            })
+            .ok()
+            .map(|_| Self)
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The listener and mDNS registration of a running sync session. Dropping it stops the
+/// session and withdraws the advertisement.
+struct SyncNode {
+    listener: TcpListener,
+    daemon: ServiceDaemon,
+    browser: Receiver<ServiceEvent>,
+    device_id: String,
+    group: String,
+    last_pull: Option<Instant>,
+}
+
+impl SyncNode {
+    fn bind(port: u16, key: &[u8; 32], device_id: String) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))
+            .or_else(|_| TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0)))?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+
+        let group = group_tag(key);
+        let daemon = ServiceDaemon::new().map_err(mdns_error)?;
+        let host_name = format!("flare-{}.local.", &device_id[..8]);
+        let version = PROTOCOL_VERSION.to_string();
+        let service = ServiceInfo::new(
+            SERVICE_TYPE,
+            &device_id,
+            &host_name,
+            "",
+            port,
+            &[("version", version.as_str()), ("group", group.as_str())][..],
+        )
+        .map_err(mdns_error)?
+        .enable_addr_auto();
+        daemon.register(service).map_err(mdns_error)?;
+        let browser = daemon.browse(SERVICE_TYPE).map_err(mdns_error)?;
+        Ok(Self {
+            listener,
+            daemon,
+            browser,
+            device_id,
+            group,
+            last_pull: None,
+        })
+    }
+
+    fn tick(&mut self, key: &[u8; 32], scope: SyncScope) {
+        self.accept_connections(key, scope);
+        self.discover_peers();
+        if self
+            .last_pull
+            .is_none_or(|pulled| pulled.elapsed() >= PULL_INTERVAL)
+        {
+            pull_from_peers(key);
+            self.last_pull = Some(Instant::now());
+        }
+    }
+
+    fn accept_connections(&self, key: &[u8; 32], scope: SyncScope) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, address)) => {
+                    let Some(slot) = ConnectionSlot::take() else {
+                        eprintln!(
+                            "[ClipboardSync] Turned away {}: too many connections",
+                            address
+                        );
+                        continue;
+                    };
+                    let key = *key;
+                    thread::spawn(move || {
+                        let _slot = slot;
+                        let result = stream
+                            .set_nonblocking(false)
+                            .map_err(AppError::from)
+                            .and_then(|_| {
+                                serve_connection(stream, &key, |since, limit| {
+                                    match MANAGER.lock().unwrap().as_ref() {
+                                        Some(manager) => {
+                                            export_for_peer(manager, scope, since, limit)
+                                        }
+                                        None => Ok((Vec::new(), since)),
+                                    }
+                                })
+                            });
+                        if let Err(e) = result {
+                            eprintln!("[ClipboardSync] Request from {} failed: {}", address, e);
+                        }
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    eprintln!("[ClipboardSync] Accept failed: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Adds the devices of our group that were resolved since the last tick and forgets the
+    /// ones that went away.
+    fn discover_peers(&self) {
+        while let Ok(event) = self.browser.try_recv() {
+            match event {
+                ServiceEvent::ServiceResolved(service) => {
+                    let Some((device_id, address)) =
+                        peer_of(&service, &self.group, &self.device_id)
+                    else {
+                        continue;
+                    };
+                    let mut peers = PEERS.lock().unwrap();
+                    let peer = peers.entry(device_id.clone()).or_insert(Peer {
+                        device_id,
+                        address,
+                        synced_until: i64::MIN,
+                        last_error: None,
+                    });
+                    peer.address = address;
+                }
+                ServiceEvent::ServiceRemoved(_, fullname) => {
+                    if let Some(device_id) = device_id_of(&fullname) {
+                        PEERS.lock().unwrap().remove(device_id);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Drop for SyncNode {
+    fn drop(&mut self) {
+        if let Err(e) = self.daemon.shutdown() {
+            eprintln!("[ClipboardSync] Failed to stop mDNS: {}", e);
+        }
+    }
+}
+
+fn pull_from_peers(key: &[u8; 32]) {
+    let peers: Vec<(String, SocketAddr, i64)> = {
+        PEERS
+            .lock()
+            .unwrap()
+            .values()
+            .map(|peer| (peer.device_id.clone(), peer.address, peer.synced_until))
+            .collect()
+    };
+
+    for (device_id, address, since) in peers {
+        // Every page is kept as it arrives, so a broken pull resumes after the last one.
+        let result = pull(address, key, since, |items, cursor| {
+            if let Some(manager) = MANAGER.lock().unwrap().as_ref() {
+                manager.import_items(items)?;
+            }
+            if let Some(peer) = PEERS.lock().unwrap().get_mut(&device_id) {
+                peer.synced_until = cursor;
+            }
+            Ok(())
+        });
+        if let Some(peer) = PEERS.lock().unwrap().get_mut(&device_id) {
+            peer.last_error = result.err().map(|e| e.to_string());
+        }
+    }
+}
+
+/// What a running sync session needs, once sync is switched on and this device is paired.
+struct SyncConfig {
+    key: [u8; 32],
+    scope: SyncScope,
+    port: u16,
+    device_id: String,
+}
+
+fn active_config() -> Option<SyncConfig> {
+    let guard = MANAGER.lock().unwrap();
+    let manager = guard.as_ref()?;
+    let settings = &manager.settings().sync;
+    if !settings.enabled {
+        return None;
+    }
+    Some(SyncConfig {
+        key: manager.sync_key().ok().flatten()?,
+        scope: settings.scope,
+        port: settings.port,
+        device_id: manager.sync_device_id().ok()?,
+    })
+}
+
+pub fn start_sync() {
+    thread::spawn(|| {
+        let mut node: Option<(u16, SyncNode)> = None;
+        loop {
+            match active_config() {
+                Some(SyncConfig {
+                    key,
+                    scope,
+                    port,
+                    device_id,
+                }) => {
+                    // Re-pairing changes the group the advertisement carries.
+                    if node.as_ref().is_none_or(|(bound_port, bound)| {
+                        *bound_port != port || bound.group != group_tag(&key)
+                    }) {
+                        // Frees the port before binding it again.
+                        drop(node.take());
+                        node = match SyncNode::bind(port, &key, device_id) {
+                            Ok(bound) => Some((port, bound)),
+                            Err(e) => {
+                                eprintln!("[ClipboardSync] Failed to start: {}", e);
+                                None
+                            }
+                        };
+                    }
+                    if let Some((_, node)) = &mut node {
+                        node.tick(&key, scope);
+                    }
+                }
+                None => {
+                    if node.take().is_some() {
+                        PEERS.lock().unwrap().clear();
+                    }
+                }
+            }
+            thread::sleep(TICK_INTERVAL);
+        }
+    });
+}
+
+pub fn status(manager: &ClipboardHistoryManager) -> Result<SyncStatus, AppError> {
+    Ok(SyncStatus {
+        paired: manager.sync_key()?.is_some(),
+        enabled: manager.settings().sync.enabled,
+        device_id: manager.sync_device_id()?,
+        peers: PEERS.lock().unwrap().values().cloned().collect(),
+    })
+}
+
+/// Replaces the pairing of this device. Peers of the old group are forgotten.
+pub fn pair(manager: &ClipboardHistoryManager, code: &str) -> Result<(), AppError> {
+    manager.set_sync_key(Some(&key_from_pairing_code(code)?))?;
+    PEERS.lock().unwrap().clear();
+    Ok(())
+}
+
+pub fn unpair(manager: &ClipboardHistoryManager) -> Result<(), AppError> {
+    manager.set_sync_key(None)?;
+    PEERS.lock().unwrap().clear();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clipboard_history::monitor::text_hash;
+    use crate::clipboard_history::types::ContentType;
+
+    fn test_key() -> [u8; 32] {
+        derive_key("ABCDEFGHJKLMNPQRSTUV", PAIRING_SALT, 10)
+    }
+
+    fn copy(manager: &ClipboardHistoryManager, text: &str) -> i64 {
+        manager
+            .add_item(text_hash(text), ContentType::Text, text.into(), None)
+            .unwrap()
+    }
+
+    /// Serves one pull from `manager` on a free local port.
+    fn serve_once(manager: ClipboardHistoryManager, key: [u8; 32], scope: SyncScope) -> SocketAddr {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let _ = serve_connection(stream, &key, |since, limit| {
+                export_for_peer(&manager, scope, since, limit)
+            });
+        });
+        address
+    }
+
+    /// Pulls every page from `address`, returning the items and the last cursor.
+    fn pull_all(
+        address: SocketAddr,
+        key: &[u8; 32],
+        since: i64,
+    ) -> Result<(Vec<ExportedItem>, i64), AppError> {
+        let mut pulled = Vec::new();
+        let mut last_cursor = since;
+        pull(address, key, since, |items, cursor| {
+            pulled.extend(items);
+            last_cursor = cursor;
+            Ok(())
+        })?;
+        Ok((pulled, last_cursor))
+    }
+
+    #[test]
+    fn test_pairing_code_roundtrip() {
+        let code = generate_pairing_code();
+        assert_eq!(code.len(), 23);
+        assert_eq!(
+            normalize_pairing_code(&code.to_lowercase().replace('-', " ")),
+            normalize_pairing_code(&code)
+        );
+        assert!(key_from_pairing_code("too-short").is_err());
+    }
+
+    #[test]
+    fn test_device_id_of_service_instance() {
+        assert_eq!(
+            device_id_of("1b4e28ba-2fa1-11d2-883f-0016d3cca427._flare-clipsync._tcp.local."),
+            Some("1b4e28ba-2fa1-11d2-883f-0016d3cca427")
+        );
+        assert_eq!(device_id_of("printer._ipp._tcp.local."), None);
+    }
+
+    #[test]
+    fn test_pull_merges_peer_items_by_hash() {
+        let key = test_key();
+        let sender = ClipboardHistoryManager::new_for_test().unwrap();
+        copy(&sender, "on both");
+        let pinned = copy(&sender, "only there");
+        sender.toggle_pin(pinned).unwrap();
+        copy(&sender, "not pinned");
+
+        let receiver = ClipboardHistoryManager::new_for_test().unwrap();
+        copy(&receiver, "on both");
+
+        let (items, cursor) =
+            pull_all(serve_once(sender, key, SyncScope::All), &key, i64::MIN).unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(cursor, 4);
+        assert_eq!(receiver.import_items(items).unwrap(), 2);
+
+        let all = receiver.get_items("all".into(), None, None, 10, 0).unwrap();
+        assert_eq!(all.len(), 3);
+        let shared = all
+            .iter()
+            .find(|item| item.hash == text_hash("on both"))
+            .unwrap();
+        assert_eq!(shared.times_copied, 1);
+        assert!(all
+            .iter()
+            .any(|item| item.hash == text_hash("only there") && item.is_pinned));
+    }
+
+    #[test]
+    fn test_pull_goes_page_by_page() {
+        let key = test_key();
+        let sender = ClipboardHistoryManager::new_for_test().unwrap();
+        for i in 0..PAGE_ITEMS * 2 + 1 {
+            copy(&sender, &format!("item {}", i));
+        }
+
+        let mut pages = Vec::new();
+        let address = serve_once(sender, key, SyncScope::All);
+        pull(address, &key, i64::MIN, |items, cursor| {
+            pages.push((items.len(), cursor));
+            Ok(())
+        })
+        .unwrap();
+        let last = PAGE_ITEMS as i64 * 2 + 1;
+        assert_eq!(
+            pages,
+            [
+                (PAGE_ITEMS, PAGE_ITEMS as i64),
+                (PAGE_ITEMS, PAGE_ITEMS as i64 * 2),
+                (1, last)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pages_stop_at_their_byte_limit() {
+        let manager = ClipboardHistoryManager::new_for_test().unwrap();
+        copy(&manager, &"a".repeat(600));
+        copy(&manager, &"b".repeat(2000));
+        copy(&manager, &"c".repeat(600));
+
+        // The second item doesn't fit next to the first, and on its own it doesn't fit at all.
+        let (items, cursor) = manager.export_changes(i64::MIN, false, 10, 1000).unwrap();
+        assert_eq!(items.len(), 1);
+        let (items, cursor) = manager.export_changes(cursor, false, 10, 1000).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].hash, text_hash(&"c".repeat(600)));
+        assert_eq!(cursor, 3);
+    }
+
+    #[test]
+    fn test_relayed_items_are_pulled_whatever_their_timestamps() {
+        let relay = ClipboardHistoryManager::new_for_test().unwrap();
+        copy(&relay, "recent");
+        let (_, cursor) = relay
+            .export_changes(i64::MIN, false, usize::MAX, usize::MAX)
+            .unwrap();
+
+        // An item another device copied long ago reaches the relay after that pull.
+        let origin = ClipboardHistoryManager::new_for_test().unwrap();
+        copy(&origin, "old");
+        let old = ExportedItem {
+            modified_at: 0,
+            ..origin.export_items().unwrap().remove(0)
+        };
+        relay.import_items(vec![old]).unwrap();
+
+        let (items, _) = relay
+            .export_changes(cursor, false, usize::MAX, usize::MAX)
+            .unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].hash, text_hash("old"));
+    }
+
+    #[test]
+    fn test_pin_changes_are_pulled_again_and_unpins_propagate() {
+        let sender = ClipboardHistoryManager::new_for_test().unwrap();
+        let id = copy(&sender, "copied");
+        let receiver = ClipboardHistoryManager::new_for_test().unwrap();
+        let (items, cursor) = sender
+            .export_changes(i64::MIN, false, usize::MAX, usize::MAX)
+            .unwrap();
+        receiver.import_items(items).unwrap();
+
+        sender.toggle_pin(id).unwrap();
+        let (items, cursor) = sender
+            .export_changes(cursor, false, usize::MAX, usize::MAX)
+            .unwrap();
+        assert_eq!(items.len(), 1);
+        let pinned = items[0].clone();
+        receiver.import_items(items).unwrap();
+        let items = receiver.get_items("all".into(), None, None, 10, 0).unwrap();
+        assert!(items[0].is_pinned);
+
+        sender.toggle_pin(id).unwrap();
+        let (items, _) = sender
+            .export_changes(cursor, false, usize::MAX, usize::MAX)
+            .unwrap();
+        receiver.import_items(items).unwrap();
+        let items = receiver.get_items("all".into(), None, None, 10, 0).unwrap();
+        assert!(!items[0].is_pinned);
+
+        // A copy from before the unpin doesn't pin it again.
+        receiver.import_items(vec![pinned]).unwrap();
+        let items = receiver.get_items("all".into(), None, None, 10, 0).unwrap();
+        assert!(!items[0].is_pinned);
+    }
+
+    #[test]
+    fn test_pinned_scope_and_wrong_key() {
+        let key = test_key();
+        let sender = ClipboardHistoryManager::new_for_test().unwrap();
+        let pinned = copy(&sender, "keep");
+        sender.toggle_pin(pinned).unwrap();
+        copy(&sender, "private");
+
+        let (items, _) =
+            pull_all(serve_once(sender, key, SyncScope::Pinned), &key, i64::MIN).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].hash, text_hash("keep"));
+
+        let other = ClipboardHistoryManager::new_for_test().unwrap();
+        let address = serve_once(other, key, SyncScope::All);
+        let wrong_key = derive_key("ZZZZZZZZZZZZZZZZZZZZ", PAIRING_SALT, 10);
+        assert!(pull_all(address, &wrong_key, i64::MIN).is_err());
+    }
+
+    #[test]
+    fn test_handshake_turns_away_unpaired_devices() {
+        let key = test_key();
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve_connection(stream, &key, |_, _| panic!("served an unpaired device"))
+        });
+
+        // A client that skips checking the server and answers with a proof of another key.
+        let wrong_key = derive_key("ZZZZZZZZZZZZZZZZZZZZ", PAIRING_SALT, 10);
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(&[0u8; NONCE_BYTES]).unwrap();
+        let mut server_nonce = [0u8; NONCE_BYTES];
+        stream.read_exact(&mut server_nonce).unwrap();
+        stream.read_exact(&mut [0u8; PROOF_BYTES]).unwrap();
+        let proof = seal_proof(CLIENT_ROLE, &server_nonce, &wrong_key).unwrap();
+        assert_eq!(proof.len(), PROOF_BYTES);
+        stream.write_all(&proof).unwrap();
+
+        let err = server.join().unwrap().unwrap_err();
+        assert!(err.to_string().contains("isn't paired"));
+    }
+}
@@ -26,9 +26,86 @@ pub struct ClipboardItem {
     pub last_copied_at: DateTime<Utc>,
     pub times_copied: i32,
     pub is_pinned: bool,
+    /// Finer classification such as `email`, `rgb` or `code:rust`, see `detect.rs`.
+    pub content_subtype: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+/// The X11/Wayland selection an item was copied from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    /// Explicit copy and paste.
+    Clipboard,
+    /// Whatever text was last highlighted, pasted with a middle click.
+    Primary,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyCopies {
+    /// UTC date, `YYYY-MM-DD`.
+    pub date: String,
+    /// Entries copied for the first time that day.
+    pub new_items: i64,
+    /// Entries whose most recent copy was that day. Earlier copies of an entry aren't kept,
+    /// so this undercounts days on which something was copied again later.
+    pub items_copied: i64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeStorage {
+    pub content_type: ContentType,
+    pub items: i64,
+    pub bytes: i64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceAppUsage {
+    pub source_app_name: String,
+    pub items: i64,
+    pub copies: i64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ClipboardStats {
+    pub total_items: i64,
+    pub total_copies: i64,
+    /// Unpinned entries copied more than once, most copied first. Good candidates to pin.
+    pub most_reused: Vec<ClipboardItem>,
+    pub copies_per_day: Vec<DailyCopies>,
+    pub storage_by_type: Vec<TypeStorage>,
+    pub top_source_apps: Vec<SourceAppUsage>,
+}
+
+/// The order in which `merge_items` concatenates entries.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum MergeOrder {
+    /// The order the ids were given in.
+    #[default]
+    Selection,
+    OldestFirst,
+    NewestFirst,
+}
+
+/// Representations captured alongside the plain-text content of an item.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ClipboardFormats {
+    pub html: Option<String>,
+    pub rtf: Option<String>,
+    pub uri_list: Option<String>,
+}
+
+impl ClipboardFormats {
+    pub fn is_empty(&self) -> bool {
+        self.html.is_none() && self.rtf.is_none() && self.uri_list.is_none()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
 #[serde(rename_all = "camelCase")]
 pub enum ContentType {
     Text,
@@ -0,0 +1,359 @@
+use super::types::Selection;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Blocks the monitor thread until the clipboard may have changed.
+pub trait ClipboardWatcher: Send {
+    fn name(&self) -> &'static str;
+
+    /// Returns `false` once the watcher can no longer deliver notifications.
+    fn wait_for_change(&mut self) -> bool;
+}
+
+/// Used when neither XFixes nor a data-control protocol is available.
+pub struct PollingWatcher;
+
+impl ClipboardWatcher for PollingWatcher {
+    fn name(&self) -> &'static str {
+        "polling"
+    }
+
+    fn wait_for_change(&mut self) -> bool {
+        std::thread::sleep(POLL_INTERVAL);
+        true
+    }
+}
+
+/// Picks the first notification backend the session supports, falling back to polling.
+pub fn create_watcher(selection: Selection) -> Box<dyn ClipboardWatcher> {
+    let watcher: Option<Box<dyn ClipboardWatcher>> = if std::env::var("WAYLAND_DISPLAY").is_ok() {
+        wayland::DataControlWatcher::new(selection)
+            .map(|w| Box::new(w) as Box<dyn ClipboardWatcher>)
+    } else {
+        x11::XFixesWatcher::new(selection).map(|w| Box::new(w) as Box<dyn ClipboardWatcher>)
+    };
+    watcher.unwrap_or_else(|| Box::new(PollingWatcher))
+}
+
+#[cfg(target_os = "linux")]
+mod x11 {
+    use super::{ClipboardWatcher, Selection};
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xfixes::{ConnectionExt as _, SelectionEventMask};
+    use x11rb::protocol::xproto::{ConnectionExt as _, CreateWindowAux, WindowClass};
+    use x11rb::protocol::Event;
+    use x11rb::rust_connection::RustConnection;
+    use x11rb::COPY_DEPTH_FROM_PARENT;
+
+    pub struct XFixesWatcher {
+        conn: RustConnection,
+    }
+
+    impl XFixesWatcher {
+        pub fn new(selection: Selection) -> Option<Self> {
+            let (conn, screen_num) = x11rb::connect(None).ok()?;
+            conn.xfixes_query_version(5, 0).ok()?.reply().ok()?;
+            let screen = conn.setup().roots.get(screen_num)?;
+            let (root, visual) = (screen.root, screen.root_visual);
+            let window = conn.generate_id().ok()?;
+            conn.create_window(
+                COPY_DEPTH_FROM_PARENT,
+                window,
+                root,
+                0,
+                0,
+                1,
+                1,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                visual,
+                &CreateWindowAux::new(),
+            )
+            .ok()?;
+            let atom_name: &[u8] = match selection {
+                Selection::Clipboard => b"CLIPBOARD",
+                Selection::Primary => b"PRIMARY",
+            };
+            let atom = conn.intern_atom(false, atom_name).ok()?.reply().ok()?.atom;
+            conn.xfixes_select_selection_input(
+                window,
+                atom,
+                SelectionEventMask::SET_SELECTION_OWNER
+                    | SelectionEventMask::SELECTION_WINDOW_DESTROY
+                    | SelectionEventMask::SELECTION_CLIENT_CLOSE,
+            )
+            .ok()?;
+            conn.flush().ok()?;
+            Some(Self { conn })
+        }
+    }
+
+    impl ClipboardWatcher for XFixesWatcher {
+        fn name(&self) -> &'static str {
+            "xfixes"
+        }
+
+        fn wait_for_change(&mut self) -> bool {
+            loop {
+                match self.conn.wait_for_event() {
+                    Ok(Event::XfixesSelectionNotify(_)) => return true,
+                    Ok(_) => continue,
+                    Err(_) => return false,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod x11 {
+    pub struct XFixesWatcher;
+
+    impl XFixesWatcher {
+        pub fn new(_selection: super::Selection) -> Option<Self> {
+            None
+        }
+    }
+
+    impl super::ClipboardWatcher for XFixesWatcher {
+        fn name(&self) -> &'static str {
+            "xfixes"
+        }
+
+        fn wait_for_change(&mut self) -> bool {
+            false
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod wayland {
+    use super::{ClipboardWatcher, Selection};
+    use wayland_client::protocol::{wl_registry, wl_seat::WlSeat};
+    use wayland_client::{event_created_child, Connection, Dispatch, EventQueue, QueueHandle};
+    use wayland_protocols::ext::data_control::v1::client::{
+        ext_data_control_device_v1::{self, ExtDataControlDeviceV1},
+        ext_data_control_manager_v1::ExtDataControlManagerV1,
+        ext_data_control_offer_v1::ExtDataControlOfferV1,
+    };
+    use wayland_protocols_wlr::data_control::v1::client::{
+        zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1},
+        zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+        zwlr_data_control_offer_v1::ZwlrDataControlOfferV1,
+    };
+
+    /// Offers stay alive until the client destroys them, so keep only the current ones.
+    enum Offer {
+        Ext(ExtDataControlOfferV1),
+        Wlr(ZwlrDataControlOfferV1),
+    }
+
+    impl Drop for Offer {
+        fn drop(&mut self) {
+            match self {
+                Offer::Ext(offer) => offer.destroy(),
+                Offer::Wlr(offer) => offer.destroy(),
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct State {
+        seat: Option<WlSeat>,
+        ext_manager: Option<ExtDataControlManagerV1>,
+        wlr_manager: Option<ZwlrDataControlManagerV1>,
+        selection: Option<Offer>,
+        primary_selection: Option<Offer>,
+        changes: u64,
+        primary_changes: u64,
+        finished: bool,
+    }
+
+    impl Dispatch<wl_registry::WlRegistry, ()> for State {
+        fn event(
+            state: &mut Self,
+            registry: &wl_registry::WlRegistry,
+            event: wl_registry::Event,
+            _data: &(),
+            _conn: &Connection,
+            qh: &QueueHandle<Self>,
+        ) {
+            if let wl_registry::Event::Global {
+                name,
+                interface,
+                version,
+            } = event
+            {
+                match interface.as_str() {
+                    "wl_seat" if state.seat.is_none() => {
+                        state.seat = Some(registry.bind(name, version.min(2), qh, ()));
+                    }
+                    "ext_data_control_manager_v1" => {
+                        state.ext_manager = Some(registry.bind(name, 1, qh, ()));
+                    }
+                    "zwlr_data_control_manager_v1" => {
+                        state.wlr_manager = Some(registry.bind(name, version.min(2), qh, ()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    macro_rules! ignore_events {
+        ($($iface:ty),*) => {
+            $(
+                impl Dispatch<$iface, ()> for State {
+                    fn event(
+                        _state: &mut Self,
+                        _proxy: &$iface,
+                        _event: <$iface as wayland_client::Proxy>::Event,
+                        _data: &(),
+                        _conn: &Connection,
+                        _qh: &QueueHandle<Self>,
+                    ) {
+                    }
+                }
+            )*
+        };
+    }
+
+    ignore_events!(
+        WlSeat,
+        ExtDataControlManagerV1,
+        ZwlrDataControlManagerV1,
+        ExtDataControlOfferV1,
+        ZwlrDataControlOfferV1
+    );
+
+    macro_rules! dispatch_device {
+        ($device:ty, $module:ident, $offer:ty, $variant:ident) => {
+            impl Dispatch<$device, ()> for State {
+                fn event(
+                    state: &mut Self,
+                    _proxy: &$device,
+                    event: $module::Event,
+                    _data: &(),
+                    _conn: &Connection,
+                    _qh: &QueueHandle<Self>,
+                ) {
+                    match event {
+                        $module::Event::Selection { id } => {
+                            state.selection = id.map(Offer::$variant);
+                            state.changes += 1;
+                        }
+                        $module::Event::PrimarySelection { id } => {
+                            state.primary_selection = id.map(Offer::$variant);
+                            state.primary_changes += 1;
+                        }
+                        $module::Event::Finished => state.finished = true,
+                        _ => {}
+                    }
+                }
+
+                event_created_child!(State, $device, [
+                    $module::EVT_DATA_OFFER_OPCODE => ($offer, ()),
+                ]);
+            }
+        };
+    }
+
+    dispatch_device!(
+        ExtDataControlDeviceV1,
+        ext_data_control_device_v1,
+        ExtDataControlOfferV1,
+        Ext
+    );
+    dispatch_device!(
+        ZwlrDataControlDeviceV1,
+        zwlr_data_control_device_v1,
+        ZwlrDataControlOfferV1,
+        Wlr
+    );
+
+    /// Receives a `selection` (or `primary_selection`) event from the compositor every time
+    /// the owner changes. Prefers the standardised ext protocol over the wlroots one.
+    pub struct DataControlWatcher {
+        queue: EventQueue<State>,
+        state: State,
+        selection: Selection,
+        seen: u64,
+    }
+
+    impl State {
+        fn changes_of(&self, selection: Selection) -> u64 {
+            match selection {
+                Selection::Clipboard => self.changes,
+                Selection::Primary => self.primary_changes,
+            }
+        }
+    }
+
+    impl DataControlWatcher {
+        pub fn new(selection: Selection) -> Option<Self> {
+            let conn = Connection::connect_to_env().ok()?;
+            let mut queue = conn.new_event_queue();
+            let qh = queue.handle();
+            conn.display().get_registry(&qh, ());
+
+            let mut state = State::default();
+            queue.roundtrip(&mut state).ok()?;
+            let seat = state.seat.clone()?;
+            if let Some(manager) = &state.ext_manager {
+                manager.get_data_device(&seat, &qh, ());
+            } else if let Some(manager) = &state.wlr_manager {
+                manager.get_data_device(&seat, &qh, ());
+            } else {
+                return None;
+            }
+            // The compositor announces the current selection straight away; that one has
+            // already been seen by the monitor's initial read.
+            queue.roundtrip(&mut state).ok()?;
+            let seen = state.changes_of(selection);
+            Some(Self {
+                queue,
+                state,
+                selection,
+                seen,
+            })
+        }
+    }
+
+    impl ClipboardWatcher for DataControlWatcher {
+        fn name(&self) -> &'static str {
+            "data-control"
+        }
+
+        fn wait_for_change(&mut self) -> bool {
+            while self.state.changes_of(self.selection) == self.seen {
+                if self.state.finished || self.queue.blocking_dispatch(&mut self.state).is_err() {
+                    return false;
+                }
+            }
+            self.seen = self.state.changes_of(self.selection);
+            true
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod wayland {
+    pub struct DataControlWatcher;
+
+    impl DataControlWatcher {
+        pub fn new(_selection: super::Selection) -> Option<Self> {
+            None
+        }
+    }
+
+    impl super::ClipboardWatcher for DataControlWatcher {
+        fn name(&self) -> &'static str {
+            "data-control"
+        }
+
+        fn wait_for_change(&mut self) -> bool {
+            false
+        }
+    }
+}
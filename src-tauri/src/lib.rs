@@ -1,3 +1,4 @@
+mod active_window;
 mod ai;
 mod app;
 mod browser_extension;
@@ -205,7 +206,7 @@ fn setup_input_listener(app: &tauri::AppHandle) {
         Ok(input_manager) => {
             app.manage(input_manager.clone());
 
-            let engine = ExpansionEngine::new(snippet_manager_arc, input_manager);
+            let engine = ExpansionEngine::new(app.clone(), snippet_manager_arc, input_manager);
             thread::spawn(move || {
                 if let Err(e) = engine.start_listening() {
                     eprintln!("[ExpansionEngine] Failed to start: {}", e);
@@ -253,6 +254,12 @@ pub fn run() {
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_clipboard_manager::init())
         .plugin(tauri_plugin_opener::init())
+        .register_asynchronous_uri_scheme_protocol("clipboard-image", |_ctx, request, responder| {
+            // Decrypting waits on the history lock, which mustn't block the webview.
+            thread::spawn(move || {
+                responder.respond(clipboard_history::handle_image_request(&request))
+            });
+        })
         .invoke_handler(tauri::generate_handler![
             get_installed_apps,
             launch_app,
@@ -273,10 +280,34 @@ pub fn run() {
             oauth::oauth_remove_tokens,
             clipboard_history::history_get_items,
             clipboard_history::history_get_item_content,
+            clipboard_history::history_get_item_formats,
+            clipboard_history::history_paste_transformed,
+            clipboard_history::history_queue_start,
+            clipboard_history::history_queue_stop,
+            clipboard_history::history_queue_status,
+            clipboard_history::history_queue_paste_next,
+            clipboard_history::history_queue_paste_all,
+            clipboard_history::history_merge_items,
+            clipboard_history::history_split_item,
+            clipboard_history::history_sync_status,
+            clipboard_history::history_sync_pair_new,
+            clipboard_history::history_sync_pair_join,
+            clipboard_history::history_sync_unpair,
+            clipboard_history::history_copy_image,
             clipboard_history::history_delete_item,
             clipboard_history::history_toggle_pin,
             clipboard_history::history_clear_all,
             clipboard_history::history_item_was_copied,
+            clipboard_history::history_get_settings,
+            clipboard_history::history_set_settings,
+            clipboard_history::history_prune,
+            clipboard_history::history_get_stats,
+            clipboard_history::history_get_key_provider,
+            clipboard_history::history_unlock,
+            clipboard_history::history_set_key_provider,
+            clipboard_history::history_rotate_key,
+            clipboard_history::history_export,
+            clipboard_history::history_import,
             quicklinks::create_quicklink,
             quicklinks::list_quicklinks,
             quicklinks::update_quicklink,
@@ -297,6 +328,14 @@ pub fn run() {
             snippets::update_snippet,
             snippets::delete_snippet,
             snippets::import_snippets,
+            snippets::submit_snippet_arguments,
+            snippets::get_snippet_app_filter,
+            snippets::set_snippet_app_filter,
+            snippets::set_snippet_app_override,
+            snippets::export_snippets,
+            snippets::list_snippet_folders,
+            snippets::list_snippet_tags,
+            snippets::set_snippet_folder_enabled,
             snippets::paste_snippet_content,
             snippets::snippet_was_used,
             file_search::search_files,
@@ -351,7 +390,13 @@ pub fn run() {
                             let _ = window.hide();
                         }
                     }
-                    tauri::WindowEvent::Focused(false) => {
+                    tauri::WindowEvent::Focused(focused) => {
+                        snippets::engine::forget_last_expansion();
+                        if focused {
+                            return;
+                        }
+                        // The arguments form is gone once the window is left.
+                        snippets::engine::cancel_pending_expansion();
                         if let Some(window) = app.get_webview_window("main") {
                             if !cfg!(debug_assertions) {
                                 let _ = window.hide();
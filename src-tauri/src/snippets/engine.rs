@@ -1,37 +1,153 @@
+use crate::active_window::focused_app_name;
 use crate::clipboard_history::manager::{
     ClipboardHistoryManager, MANAGER as CLIPBOARD_MANAGER_STATIC,
 };
+use crate::clipboard_history::read_primary_text;
+use crate::clipboard_history::types::Selection;
 use crate::error::AppError;
 use crate::snippets::input_manager::{InputEvent, InputManager};
 use crate::snippets::manager::SnippetManager;
+use crate::snippets::trigger::{is_delimiter, Trigger};
+use crate::snippets::types::Snippet;
 use arboard::Clipboard;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use chrono::{DateTime, Duration, Local, Months};
 use enigo::Key as EnigoKey;
 use once_cell::sync::Lazy;
-use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
 use regex::Regex;
+use serde::Serialize;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Instant;
+use tauri::{AppHandle, Emitter, Manager};
 use uuid::Uuid;
 
-const BUFFER_SIZE: usize = 30;
 const FRAGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'<').add(b'>').add(b'`');
 
-static PLACEHOLDER_REGEX: Lazy<Regex> = Lazy::new(|| {
+pub(super) static PLACEHOLDER_REGEX: Lazy<Regex> = Lazy::new(|| {
     Regex::new(r#"\{(?P<name>\w+)(?P<attributes>(?:\s+\w+=(?:"[^"]*"|\S+))*)?(?P<modifiers>(?:\s*\|\s*[\w%-]+)*)\}"#).unwrap()
 });
 static ATTRIBUTE_REGEX: Lazy<Regex> = Lazy::new(|| {
     Regex::new(r#"\s*(?P<key>\w+)=(?:"(?P<q_value>[^"]*)"|(?P<uq_value>\S+))"#).unwrap()
 });
-static OFFSET_REGEX: Lazy<Regex> =
+pub(super) static OFFSET_REGEX: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"(?P<sign>[+-])(?P<num>\d+)(?P<unit>[ymhMd])").unwrap());
 
+/// How deep `{snippet}` placeholders may nest inside each other.
+const MAX_SNIPPET_DEPTH: usize = 8;
+
+/// Arguments without a `name` attribute share this one.
+const DEFAULT_ARGUMENT_NAME: &str = "argument";
+pub const ARGUMENTS_REQUESTED_EVENT: &str = "snippet-arguments-requested";
+/// Carries the message of an expansion that was given up, which is also shown in the HUD.
+pub const EXPANSION_FAILED_EVENT: &str = "snippet-expansion-failed";
+
 pub struct ResolvedSnippet {
     pub content: String,
     pub cursor_pos: Option<usize>,
 }
 
+/// A value asked from the user by `{argument name="..." default="..." options="a,b"}`.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SnippetArgument {
+    pub name: String,
+    pub default_value: Option<String>,
+    /// When not empty, the value is picked from these.
+    pub options: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ArgumentsRequest {
+    /// A `u32` so it survives the trip through a JavaScript number.
+    pub request_id: u32,
+    pub snippet_name: String,
+    pub arguments: Vec<SnippetArgument>,
+}
+
+/// An expansion waiting for the frontend to send the values of its arguments.
+struct PendingExpansion {
+    request_id: u32,
+    trigger: Trigger,
+    content: String,
+    /// The app the keyword was typed in.
+    app: Option<String>,
+}
+
+static PENDING_EXPANSION: Lazy<Mutex<Option<PendingExpansion>>> = Lazy::new(|| Mutex::new(None));
+static NEXT_REQUEST_ID: AtomicU32 = AtomicU32::new(1);
+
+/// Drops the expansion waiting for arguments, e.g. once its form was closed.
+pub fn cancel_pending_expansion() {
+    PENDING_EXPANSION.lock().unwrap().take();
+}
+
+fn report_expansion_error(app_handle: &AppHandle, error: &AppError) {
+    eprintln!("[ExpansionEngine] {}", error);
+    let message = error.to_string();
+    if let Err(e) = app_handle.emit(EXPANSION_FAILED_EVENT, &message) {
+        eprintln!("[ExpansionEngine] Failed to report expansion error: {}", e);
+    }
+    tauri::async_runtime::spawn(crate::show_hud(app_handle.clone(), message));
+}
+
+/// How long after an expansion a backspace still reverts it.
+const UNDO_WINDOW: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// The expansion typed last, which a backspace pressed right after it reverts.
+#[derive(Debug, Clone, PartialEq)]
+struct LastExpansion {
+    /// What the expansion replaced: the keyword as typed, followed by its delimiter.
+    replaced: String,
+    /// Characters typed by the expansion.
+    inserted: usize,
+    /// How far left of the end of the expansion the caret was put by `{cursor}`.
+    cursor_offset: usize,
+    /// The app the expansion was typed in.
+    app: Option<String>,
+    expanded_at: Instant,
+}
+
+impl LastExpansion {
+    /// Whether a backspace at `now` is still right after the expansion. A backspace typed
+    /// later, or in another app, deletes something else.
+    fn is_undoable(&self, now: Instant, focused_app: impl FnOnce() -> Option<String>) -> bool {
+        now.duration_since(self.expanded_at) < UNDO_WINDOW && focused_app() == self.app
+    }
+
+    /// The presses of right arrow and backspace that delete the expansion once the user's
+    /// backspace has already deleted the character before the caret.
+    fn undo_keys(&self) -> Option<(usize, usize)> {
+        let remaining = self.inserted.checked_sub(1)?;
+        Some((self.cursor_offset, remaining))
+    }
+}
+
+static LAST_EXPANSION: Lazy<Mutex<Option<LastExpansion>>> = Lazy::new(|| Mutex::new(None));
+
+/// Stops a backspace from reverting the last expansion, e.g. once the focus moved away.
+pub fn forget_last_expansion() {
+    LAST_EXPANSION.lock().unwrap().take();
+}
+
+/// Set while keys are injected, so the listener doesn't take them for the user's typing.
+static INJECTING: AtomicBool = AtomicBool::new(false);
+
+/// Runs `inject` on its own thread, ignoring the keys it presses.
+fn inject_in_background(inject: impl FnOnce() + Send + 'static) {
+    INJECTING.store(true, Ordering::SeqCst);
+    thread::spawn(move || {
+        inject();
+        // The listener may only see the injected keys after a short delay.
+        thread::sleep(std::time::Duration::from_millis(50));
+        INJECTING.store(false, Ordering::SeqCst);
+    });
+}
+
 #[derive(Debug)]
 struct ParsedPlaceholder<'a> {
     name: &'a str,
@@ -43,14 +159,20 @@ pub struct ExpansionEngine {
     buffer: Arc<Mutex<String>>,
     snippet_manager: Arc<SnippetManager>,
     input_manager: Arc<dyn InputManager>,
+    app_handle: AppHandle,
 }
 
 impl ExpansionEngine {
-    pub fn new(snippet_manager: Arc<SnippetManager>, input_manager: Arc<dyn InputManager>) -> Self {
+    pub fn new(
+        app_handle: AppHandle,
+        snippet_manager: Arc<SnippetManager>,
+        input_manager: Arc<dyn InputManager>,
+    ) -> Self {
         Self {
-            buffer: Arc::new(Mutex::new(String::with_capacity(BUFFER_SIZE))),
+            buffer: Arc::new(Mutex::new(String::new())),
             snippet_manager,
             input_manager,
+            app_handle,
         }
     }
 
@@ -67,13 +189,41 @@ impl ExpansionEngine {
             buffer: self.buffer.clone(),
             snippet_manager: self.snippet_manager.clone(),
             input_manager: self.input_manager.clone(),
+            app_handle: self.app_handle.clone(),
         }
     }
 
     fn handle_key_press(&self, event: InputEvent) {
-        let InputEvent::KeyPress(ch) = event;
+        // Keys typed into the arguments form aren't typed into the app.
+        if INJECTING.load(Ordering::SeqCst) || PENDING_EXPANSION.lock().unwrap().is_some() {
+            return;
+        }
+        // Only the key right after an expansion can undo it.
+        let last_expansion = LAST_EXPANSION.lock().unwrap().take();
+        let ch = match event {
+            InputEvent::KeyPress(ch) => ch,
+            InputEvent::OtherKey | InputEvent::Click => {
+                self.buffer.lock().unwrap().clear();
+                return;
+            }
+        };
+        if ch == '\u{8}' {
+            if let Some(last_expansion) = last_expansion
+                .filter(|expansion| expansion.is_undoable(Instant::now(), focused_app_name))
+            {
+                self.undo_expansion(last_expansion);
+                return;
+            }
+        }
+
+        let Ok(matcher) = self.snippet_manager.keyword_matcher() else {
+            return;
+        };
         let mut buffer = self.buffer.lock().unwrap();
+        // Snippets waiting for a delimiter look at the text typed before it.
+        let before_delimiter = is_delimiter(ch).then(|| buffer.clone());
 
+        let mut typed = false;
         match ch {
             '\u{8}' => {
                 buffer.pop();
@@ -84,88 +234,246 @@ impl ExpansionEngine {
             ch if ch.is_control() => (),
             _ => {
                 buffer.push(ch);
-                if buffer.len() > BUFFER_SIZE {
+                while buffer.chars().count() > matcher.buffer_size() {
                     buffer.remove(0);
                 }
+                typed = true;
             }
         }
 
-        if let Ok(snippets) = self.snippet_manager.list_snippets(None) {
-            for snippet in snippets {
-                if buffer.ends_with(&snippet.keyword) {
-                    let (keyword, content, id) =
-                        (snippet.keyword.clone(), snippet.content.clone(), snippet.id);
-                    let manager = self.snippet_manager.clone();
-                    drop(buffer);
-                    self.expand_snippet(&keyword, &content);
-                    thread::spawn(move || {
-                        let _ = manager.snippet_was_used(id);
-                    });
-                    break;
-                }
-            }
-        }
-    }
-
-    fn expand_snippet(&self, keyword: &str, content: &str) {
-        let mut backspaces = String::new();
-        for _ in 0..keyword.len() {
-            backspaces.push('\u{8}');
-        }
-
-        let clipboard_manager_lock = CLIPBOARD_MANAGER_STATIC.lock().unwrap();
-        let resolved_result = parse_and_resolve_placeholders(
-            content,
-            &self.snippet_manager,
-            clipboard_manager_lock.as_ref(),
-        );
-
-        let resolved = match resolved_result {
-            Ok(res) => res,
-            Err(e) => {
-                eprintln!("[ExpansionEngine] Error resolving placeholders: {}", e);
-                ResolvedSnippet {
-                    content: content.to_string(),
-                    cursor_pos: None,
-                }
-            }
+        let found = before_delimiter
+            .and_then(|before| matcher.find(&before, Some(ch)))
+            .or_else(|| typed.then(|| matcher.find(&buffer, None)).flatten());
+        let Some((snippet, trigger)) = found else {
+            return;
         };
 
-        let content_to_paste = resolved.content;
+        let snippet = snippet.clone();
+        drop(buffer);
+        // Only looked up on a match, since it can take a compositor round-trip.
+        let app = focused_app_name();
+        if !self
+            .snippet_manager
+            .expands_in(&snippet, app.as_deref())
+            .unwrap_or(true)
+        {
+            return;
+        }
 
-        let chars_to_move_left = if let Some(pos) = resolved.cursor_pos {
-            content_to_paste.chars().count() - pos
+        let id = snippet.id;
+        let manager = self.snippet_manager.clone();
+        // A nesting error is reported when the snippet is expanded.
+        let arguments =
+            collect_arguments(&snippet.content, &self.snippet_manager).unwrap_or_default();
+        if arguments.is_empty() {
+            self.expand_snippet(&trigger, &snippet.content, app);
         } else {
-            0
+            self.request_arguments(snippet, arguments, trigger, app);
+        }
+        thread::spawn(move || {
+            let _ = manager.snippet_was_used(id);
+        });
+    }
+
+    /// Deletes what is left of `last_expansion` after the user's backspace and types the
+    /// keyword it replaced again.
+    fn undo_expansion(&self, last_expansion: LastExpansion) {
+        let Some((right, backspaces)) = last_expansion.undo_keys() else {
+            return;
         };
+        *self.buffer.lock().unwrap() = last_expansion.replaced.clone();
 
         let input_manager = self.input_manager.clone();
-
-        thread::spawn(move || {
-            if let Err(e) = input_manager.inject_text(&backspaces) {
-                eprintln!("Failed to inject backspaces: {}", e);
-            }
-            thread::sleep(std::time::Duration::from_millis(50));
-            if let Err(e) = input_manager.inject_text(&content_to_paste) {
-                eprintln!("Failed to inject snippet content: {}", e);
-            }
-
-            if chars_to_move_left > 0 {
-                thread::sleep(std::time::Duration::from_millis(50));
-                if let Err(e) =
-                    input_manager.inject_key_clicks(EnigoKey::LeftArrow, chars_to_move_left)
-                {
+        inject_in_background(move || {
+            if right > 0 {
+                if let Err(e) = input_manager.inject_key_clicks(EnigoKey::RightArrow, right) {
                     eprintln!("Failed to inject cursor movement: {}", e);
                 }
             }
+            if backspaces > 0 {
+                if let Err(e) = input_manager.inject_key_clicks(EnigoKey::Backspace, backspaces) {
+                    eprintln!("Failed to inject backspaces: {}", e);
+                }
+                thread::sleep(std::time::Duration::from_millis(50));
+            }
+            if let Err(e) = input_manager.inject_text(&last_expansion.replaced) {
+                eprintln!("Failed to inject keyword: {}", e);
+            }
         });
+    }
+
+    fn expand_snippet(&self, trigger: &Trigger, content: &str, app: Option<String>) {
+        if let Err(e) = replace_keyword(
+            trigger,
+            content,
+            app,
+            &self.snippet_manager,
+            self.input_manager.clone(),
+            &HashMap::new(),
+        ) {
+            report_expansion_error(&self.app_handle, &e);
+        }
 
         let mut buffer = self.buffer.lock().unwrap();
         buffer.clear();
     }
+
+    /// Leaves the keyword in place and asks the frontend for the snippet's arguments. The
+    /// expansion finishes in `submit_arguments`.
+    fn request_arguments(
+        &self,
+        snippet: Snippet,
+        arguments: Vec<SnippetArgument>,
+        trigger: Trigger,
+        app: Option<String>,
+    ) {
+        self.buffer.lock().unwrap().clear();
+        let request = ArgumentsRequest {
+            request_id: NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed),
+            snippet_name: snippet.name,
+            arguments,
+        };
+        *PENDING_EXPANSION.lock().unwrap() = Some(PendingExpansion {
+            request_id: request.request_id,
+            trigger,
+            content: snippet.content,
+            app,
+        });
+
+        if let Some(window) = self.app_handle.get_webview_window("main") {
+            let _ = window.show();
+            let _ = window.set_focus();
+        }
+        if let Err(e) = self.app_handle.emit(ARGUMENTS_REQUESTED_EVENT, &request) {
+            eprintln!("[ExpansionEngine] Failed to request arguments: {}", e);
+        }
+    }
 }
 
-fn parse_attributes(attr_str: &str) -> HashMap<&str, &str> {
+/// Finishes the expansion of `request_id` with `values`, or drops it when `values` is
+/// `None`. The window is hidden first so the keyword is replaced in the app it was typed in.
+pub fn submit_arguments(
+    app: &AppHandle,
+    request_id: u32,
+    values: Option<HashMap<String, String>>,
+) -> Result<(), AppError> {
+    let pending = {
+        let mut pending = PENDING_EXPANSION.lock().unwrap();
+        match pending.as_ref() {
+            Some(expansion) if expansion.request_id == request_id => pending.take().unwrap(),
+            _ => {
+                return Err(AppError::Snippet(
+                    "The snippet expansion has expired".into(),
+                ))
+            }
+        }
+    };
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.hide();
+    }
+    let Some(values) = values else {
+        return Ok(());
+    };
+
+    let snippet_manager = app.state::<SnippetManager>().inner().clone();
+    let input_manager = app
+        .try_state::<Arc<dyn InputManager>>()
+        .ok_or_else(|| AppError::Snippet("Text injection is unavailable".into()))?
+        .inner()
+        .clone();
+    let app = app.clone();
+    thread::spawn(move || {
+        // Give the window manager a moment to focus the previous app again.
+        thread::sleep(std::time::Duration::from_millis(150));
+        // Not every window manager does, and the backspaces must not land in another app.
+        if focused_app_name() != pending.app {
+            let error = AppError::Snippet(format!(
+                "Snippet not expanded: {} is no longer focused",
+                pending.app.as_deref().unwrap_or("the app it was typed in")
+            ));
+            report_expansion_error(&app, &error);
+            return;
+        }
+        if let Err(e) = replace_keyword(
+            &pending.trigger,
+            &pending.content,
+            pending.app,
+            &snippet_manager,
+            input_manager,
+            &values,
+        ) {
+            report_expansion_error(&app, &e);
+        }
+    });
+    Ok(())
+}
+
+/// Deletes what was typed to trigger the snippet and types the resolved `content` in its
+/// place, followed by the delimiter that completed the keyword, if any. `app` is where the
+/// keyword was typed. If the content can't be resolved, the keyword is left as typed.
+fn replace_keyword(
+    trigger: &Trigger,
+    content: &str,
+    app: Option<String>,
+    snippet_manager: &SnippetManager,
+    input_manager: Arc<dyn InputManager>,
+    arguments: &HashMap<String, String>,
+) -> Result<(), AppError> {
+    let mut backspaces = String::new();
+    for _ in 0..trigger.backspaces() {
+        backspaces.push('\u{8}');
+    }
+
+    let clipboard_manager_lock = CLIPBOARD_MANAGER_STATIC.lock().unwrap();
+    let resolved_result = resolve_placeholders_with_arguments(
+        content,
+        snippet_manager,
+        clipboard_manager_lock.as_ref(),
+        arguments,
+    );
+    drop(clipboard_manager_lock);
+
+    let resolved = resolved_result?;
+
+    let (mut content_to_paste, cursor_pos) =
+        trigger.case.apply(&resolved.content, resolved.cursor_pos);
+    content_to_paste.extend(trigger.delimiter);
+
+    let chars_to_move_left = if let Some(pos) = cursor_pos {
+        content_to_paste.chars().count() - pos
+    } else {
+        0
+    };
+
+    *LAST_EXPANSION.lock().unwrap() = Some(LastExpansion {
+        replaced: trigger.typed.chars().chain(trigger.delimiter).collect(),
+        inserted: content_to_paste.chars().count(),
+        cursor_offset: chars_to_move_left,
+        app,
+        expanded_at: Instant::now(),
+    });
+
+    inject_in_background(move || {
+        if let Err(e) = input_manager.inject_text(&backspaces) {
+            eprintln!("Failed to inject backspaces: {}", e);
+        }
+        thread::sleep(std::time::Duration::from_millis(50));
+        if let Err(e) = input_manager.inject_text(&content_to_paste) {
+            eprintln!("Failed to inject snippet content: {}", e);
+        }
+
+        if chars_to_move_left > 0 {
+            thread::sleep(std::time::Duration::from_millis(50));
+            if let Err(e) = input_manager.inject_key_clicks(EnigoKey::LeftArrow, chars_to_move_left)
+            {
+                eprintln!("Failed to inject cursor movement: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+pub(super) fn parse_attributes(attr_str: &str) -> HashMap<&str, &str> {
     ATTRIBUTE_REGEX
         .captures_iter(attr_str)
         .filter_map(|cap| {
@@ -187,21 +495,53 @@ fn parse_modifiers(mod_str: &str) -> Vec<&str> {
         .collect()
 }
 
-fn apply_modifiers(mut value: String, modifiers: &[&str]) -> String {
+/// Modifiers understood by `apply_modifiers`, in placeholders (`{clipboard | trim}`) and
+/// when pasting from clipboard history.
+pub const MODIFIERS: &[&str] = &[
+    "uppercase",
+    "lowercase",
+    "trim",
+    "strip-whitespace",
+    "percent-encode",
+    "percent-decode",
+    "json-stringify",
+    "json-pretty",
+    "base64-encode",
+    "base64-decode",
+];
+
+/// Applies `modifiers` left to right. Unknown modifiers, and decoders given input they
+/// can't decode, leave the value unchanged.
+pub fn apply_modifiers(mut value: String, modifiers: &[&str]) -> String {
     for &modifier in modifiers {
         value = match modifier {
             "uppercase" => value.to_uppercase(),
             "lowercase" => value.to_lowercase(),
             "trim" => value.trim().to_string(),
+            "strip-whitespace" => value.chars().filter(|c| !c.is_whitespace()).collect(),
             "percent-encode" => utf8_percent_encode(&value, FRAGMENT).to_string(),
+            "percent-decode" => percent_decode_str(&value.replace('+', " "))
+                .decode_utf8()
+                .map(|decoded| decoded.into_owned())
+                .unwrap_or(value),
             "json-stringify" => serde_json::to_string(&value).unwrap_or(value),
+            "json-pretty" => serde_json::from_str::<serde_json::Value>(&value)
+                .and_then(|json| serde_json::to_string_pretty(&json))
+                .unwrap_or(value),
+            "base64-encode" => BASE64.encode(value.as_bytes()),
+            "base64-decode" => BASE64
+                .decode(value.trim())
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .unwrap_or(value),
             _ => value,
         };
     }
     value
 }
 
-fn translate_date_format(format_str: &str) -> String {
+/// Turns a `yyyy-MM-dd` style format, as written in placeholders, into a strftime one.
+pub(super) fn translate_date_format(format_str: &str) -> String {
     let mut result = String::with_capacity(format_str.len());
     let mut in_literal = false;
     let mut chars = format_str.chars().peekable();
@@ -272,15 +612,188 @@ fn translate_date_format(format_str: &str) -> String {
     result
 }
 
+/// The strftime format of a date placeholder without a `format` attribute.
+pub(super) fn default_date_format(name: &str) -> &'static str {
+    match name {
+        "date" => "%-d %b %Y",
+        "time" => "%-I:%M %p",
+        "datetime" => "%-d %b %Y at %-I:%M %p",
+        "day" => "%A",
+        _ => "",
+    }
+}
+
+fn argument_name<'a>(placeholder: &ParsedPlaceholder<'a>) -> &'a str {
+    placeholder
+        .attributes
+        .get("name")
+        .copied()
+        .unwrap_or(DEFAULT_ARGUMENT_NAME)
+}
+
+/// The arguments `content` and the snippets it includes ask for, once per name. Repeated
+/// arguments take the first default and options given for their name.
+pub fn collect_arguments(
+    content: &str,
+    snippet_manager: &SnippetManager,
+) -> Result<Vec<SnippetArgument>, AppError> {
+    let mut arguments = Vec::new();
+    collect_nested_arguments(content, snippet_manager, &mut arguments, &mut Vec::new())?;
+    Ok(arguments)
+}
+
+fn collect_nested_arguments(
+    content: &str,
+    snippet_manager: &SnippetManager,
+    arguments: &mut Vec<SnippetArgument>,
+    nested: &mut Vec<String>,
+) -> Result<(), AppError> {
+    for cap in PLACEHOLDER_REGEX.captures_iter(content) {
+        let attributes = parse_attributes(cap.name("attributes").map_or("", |m| m.as_str()));
+        match cap.name("name").unwrap().as_str() {
+            "argument" => merge_argument(arguments, attributes),
+            "snippet" => {
+                let Some(name) = attributes.get("name").copied() else {
+                    continue;
+                };
+                let Some(snippet) = enter_nested_snippet(name, snippet_manager, nested)? else {
+                    continue;
+                };
+                let collected =
+                    collect_nested_arguments(&snippet.content, snippet_manager, arguments, nested);
+                nested.pop();
+                collected?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The arguments `content` itself asks for, leaving out included snippets.
+fn content_arguments(content: &str) -> Vec<SnippetArgument> {
+    let mut arguments = Vec::new();
+    for cap in PLACEHOLDER_REGEX.captures_iter(content) {
+        if cap.name("name").unwrap().as_str() == "argument" {
+            merge_argument(
+                &mut arguments,
+                parse_attributes(cap.name("attributes").map_or("", |m| m.as_str())),
+            );
+        }
+    }
+    arguments
+}
+
+fn merge_argument(arguments: &mut Vec<SnippetArgument>, attributes: HashMap<&str, &str>) {
+    let placeholder = ParsedPlaceholder {
+        name: "argument",
+        attributes,
+        modifiers: Vec::new(),
+    };
+    let name = argument_name(&placeholder);
+    let options: Vec<String> = placeholder
+        .attributes
+        .get("options")
+        .map(|options| {
+            options
+                .split(',')
+                .map(str::trim)
+                .filter(|option| !option.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    let default_value = placeholder.attributes.get("default").map(|d| d.to_string());
+
+    match arguments.iter_mut().find(|argument| argument.name == name) {
+        Some(argument) => {
+            if argument.default_value.is_none() {
+                argument.default_value = default_value;
+            }
+            if argument.options.is_empty() {
+                argument.options = options;
+            }
+        }
+        None => arguments.push(SnippetArgument {
+            name: name.to_string(),
+            default_value,
+            options,
+        }),
+    }
+}
+
+/// Looks up the snippet a `{snippet name="..."}` placeholder includes and pushes its name
+/// onto `nested`, the names of the snippets being resolved, outermost first. Fails if the
+/// snippet includes itself or is nested too deep.
+fn enter_nested_snippet(
+    name: &str,
+    snippet_manager: &SnippetManager,
+    nested: &mut Vec<String>,
+) -> Result<Option<Snippet>, AppError> {
+    let Some(snippet) = snippet_manager.find_snippet_by_name(name)? else {
+        return Ok(None);
+    };
+    if nested.iter().any(|outer| outer == name) {
+        let chain: Vec<&str> = nested
+            .iter()
+            .map(String::as_str)
+            .chain(std::iter::once(name))
+            .collect();
+        return Err(AppError::Snippet(format!(
+            "Snippet \"{}\" includes itself: {}",
+            name,
+            chain.join(" → ")
+        )));
+    }
+    if nested.len() >= MAX_SNIPPET_DEPTH {
+        return Err(AppError::Snippet(format!(
+            "Snippets are nested more than {} levels deep",
+            MAX_SNIPPET_DEPTH
+        )));
+    }
+    nested.push(name.to_string());
+    Ok(Some(snippet))
+}
+
+/// Resolves a `{snippet name="..."}` placeholder with the placeholders of the snippet it
+/// names. `nested` holds the names of the snippets being resolved, outermost first.
+fn resolve_nested_snippet(
+    name: &str,
+    snippet_manager: &SnippetManager,
+    clipboard_manager: Option<&ClipboardHistoryManager>,
+    arguments: &HashMap<String, String>,
+    nested: &mut Vec<String>,
+) -> Result<String, AppError> {
+    let Some(snippet) = enter_nested_snippet(name, snippet_manager, nested)? else {
+        return Ok(String::new());
+    };
+    let resolved = resolve_content(
+        &snippet.content,
+        snippet_manager,
+        clipboard_manager,
+        arguments,
+        nested,
+    );
+    nested.pop();
+    // The caret can only be placed by the outermost snippet.
+    Ok(resolved?.content)
+}
+
 fn resolve_value<'a>(
     placeholder: &ParsedPlaceholder,
     snippet_manager: &SnippetManager,
     clipboard_manager: Option<&ClipboardHistoryManager>,
+    arguments: &HashMap<String, String>,
+    nested: &mut Vec<String>,
 ) -> Result<String, AppError> {
     let now = Local::now();
 
     match placeholder.name {
         "cursor" => Ok(String::new()),
+        "argument" => Ok(arguments
+            .get(argument_name(placeholder))
+            .cloned()
+            .unwrap_or_default()),
         "uuid" => Ok(Uuid::new_v4().to_string().to_uppercase()),
         "clipboard" => {
             let offset: u32 = placeholder
@@ -289,31 +802,37 @@ fn resolve_value<'a>(
                 .and_then(|s| s.parse().ok())
                 .unwrap_or(0);
 
+            // `selection="primary"` reads highlighted text instead of the clipboard.
+            let selection = match placeholder.attributes.get("selection") {
+                Some(&"primary") => Selection::Primary,
+                _ => Selection::Clipboard,
+            };
+
             if offset > 0 {
                 if let Some(cm) = clipboard_manager {
-                    if let Some(content) = cm.get_content_by_offset(offset)? {
+                    if let Some(content) = cm.get_content_by_offset(selection, offset)? {
                         return Ok(content);
                     }
                 }
                 return Ok(String::new());
             }
 
-            Ok(Clipboard::new()
-                .ok()
-                .and_then(|mut c| c.get_text().ok())
-                .unwrap_or_default())
-        }
-        "snippet" => {
-            if let Some(name) = placeholder.attributes.get("name") {
-                if let Some(snippet) = snippet_manager.find_snippet_by_name(name)? {
-                    if PLACEHOLDER_REGEX.is_match(&snippet.content) {
-                        return Ok(String::new());
-                    }
-                    return Ok(snippet.content);
-                }
+            let mut clipboard = match Clipboard::new() {
+                Ok(clipboard) => clipboard,
+                Err(_) => return Ok(String::new()),
+            };
+            Ok(match selection {
+                Selection::Primary => read_primary_text(&mut clipboard),
+                Selection::Clipboard => clipboard.get_text().ok(),
             }
-            Ok(String::new())
+            .unwrap_or_default())
         }
+        "snippet" => match placeholder.attributes.get("name") {
+            Some(name) => {
+                resolve_nested_snippet(name, snippet_manager, clipboard_manager, arguments, nested)
+            }
+            None => Ok(String::new()),
+        },
         "date" | "time" | "datetime" | "day" => {
             let mut date_time: DateTime<Local> = now;
             if let Some(offset_str) = placeholder.attributes.get("offset") {
@@ -366,16 +885,9 @@ fn resolve_value<'a>(
                 }
             }
 
-            let format_str = if let Some(fmt) = placeholder.attributes.get("format") {
-                translate_date_format(fmt)
-            } else {
-                match placeholder.name {
-                    "date" => "%-d %b %Y".to_string(),
-                    "time" => "%-I:%M %p".to_string(),
-                    "datetime" => "%-d %b %Y at %-I:%M %p".to_string(),
-                    "day" => "%A".to_string(),
-                    _ => "".to_string(),
-                }
+            let format_str = match placeholder.attributes.get("format") {
+                Some(fmt) => translate_date_format(fmt),
+                None => default_date_format(placeholder.name).to_string(),
             };
 
             Ok(date_time
@@ -388,11 +900,52 @@ fn resolve_value<'a>(
     }
 }
 
+/// Resolves the placeholders of `raw_content`, with arguments taking their default values.
 pub fn parse_and_resolve_placeholders(
     raw_content: &str,
     snippet_manager: &SnippetManager,
     clipboard_manager: Option<&ClipboardHistoryManager>,
 ) -> Result<ResolvedSnippet, AppError> {
+    resolve_placeholders_with_arguments(
+        raw_content,
+        snippet_manager,
+        clipboard_manager,
+        &HashMap::new(),
+    )
+}
+
+/// Like `parse_and_resolve_placeholders`, with `arguments` holding the values entered for
+/// `{argument}` placeholders by name.
+pub fn resolve_placeholders_with_arguments(
+    raw_content: &str,
+    snippet_manager: &SnippetManager,
+    clipboard_manager: Option<&ClipboardHistoryManager>,
+    arguments: &HashMap<String, String>,
+) -> Result<ResolvedSnippet, AppError> {
+    resolve_content(
+        raw_content,
+        snippet_manager,
+        clipboard_manager,
+        arguments,
+        &mut Vec::new(),
+    )
+}
+
+fn resolve_content(
+    raw_content: &str,
+    snippet_manager: &SnippetManager,
+    clipboard_manager: Option<&ClipboardHistoryManager>,
+    arguments: &HashMap<String, String>,
+    nested: &mut Vec<String>,
+) -> Result<ResolvedSnippet, AppError> {
+    // Arguments left unanswered fall back to their default, wherever it was given.
+    let mut arguments_with_defaults: HashMap<String, String> = content_arguments(raw_content)
+        .into_iter()
+        .filter_map(|argument| Some((argument.name, argument.default_value?)))
+        .collect();
+    arguments_with_defaults.extend(arguments.iter().map(|(k, v)| (k.clone(), v.clone())));
+    let arguments = &arguments_with_defaults;
+
     let mut resolved_content = String::with_capacity(raw_content.len());
     let mut cursor_pos: Option<usize> = None;
     let mut last_end = 0;
@@ -416,7 +969,13 @@ pub fn parse_and_resolve_placeholders(
                 cursor_pos = Some(resolved_content.chars().count());
             }
         } else {
-            let value = resolve_value(&placeholder, snippet_manager, clipboard_manager)?;
+            let value = resolve_value(
+                &placeholder,
+                snippet_manager,
+                clipboard_manager,
+                arguments,
+                nested,
+            )?;
             let modified_value = apply_modifiers(value, &placeholder.modifiers);
             resolved_content.push_str(&modified_value);
         }
@@ -434,6 +993,7 @@ pub fn parse_and_resolve_placeholders(
 mod tests {
     use super::*;
     use crate::clipboard_history::types::ContentType;
+    use crate::snippets::trigger::CaseStyle;
 
     #[test]
     fn test_plain_text() {
@@ -490,6 +1050,150 @@ mod tests {
         assert_eq!(result.content, "Clipboard item at offset 1 is: Item 1");
     }
 
+    #[test]
+    fn test_clipboard_offset_placeholder_targets_primary() {
+        let snippet_manager = SnippetManager::new_for_test().unwrap();
+        let clipboard_manager = ClipboardHistoryManager::new_for_test().unwrap();
+        clipboard_manager
+            .add_primary_item("Highlight 1", None)
+            .unwrap();
+        thread::sleep(std::time::Duration::from_millis(1));
+        clipboard_manager
+            .add_item("hash1".into(), ContentType::Text, "Copy 1".into(), None)
+            .unwrap();
+        thread::sleep(std::time::Duration::from_millis(1));
+        clipboard_manager
+            .add_primary_item("Highlight 0", None)
+            .unwrap();
+        thread::sleep(std::time::Duration::from_millis(1));
+        clipboard_manager
+            .add_item("hash0".into(), ContentType::Text, "Copy 0".into(), None)
+            .unwrap();
+
+        let content = "{clipboard offset=1} / {clipboard selection=primary offset=1}";
+        let result =
+            parse_and_resolve_placeholders(content, &snippet_manager, Some(&clipboard_manager))
+                .unwrap();
+
+        assert_eq!(result.content, "Copy 1 / Highlight 1");
+    }
+
+    #[test]
+    fn test_collect_arguments() {
+        let snippet_manager = SnippetManager::new_for_test().unwrap();
+        let content = "Hi {argument name=\"who\"}, {argument name=\"tone\" options=\"warm, dry\"} \
+                       {argument name=\"who\" default=\"there\"} {argument}";
+        assert_eq!(
+            collect_arguments(content, &snippet_manager).unwrap(),
+            vec![
+                SnippetArgument {
+                    name: "who".into(),
+                    default_value: Some("there".into()),
+                    options: vec![],
+                },
+                SnippetArgument {
+                    name: "tone".into(),
+                    default_value: None,
+                    options: vec!["warm".into(), "dry".into()],
+                },
+                SnippetArgument {
+                    name: "argument".into(),
+                    default_value: None,
+                    options: vec![],
+                },
+            ]
+        );
+        assert!(collect_arguments("{clipboard} {date}", &snippet_manager)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_collect_arguments_of_nested_snippets() {
+        let snippet_manager = SnippetManager::new_for_test().unwrap();
+        snippet_manager
+            .create_snippet(
+                "Greeting".into(),
+                "gr".into(),
+                "Hi {argument name=\"who\" default=\"there\"}".into(),
+            )
+            .unwrap();
+        snippet_manager
+            .create_snippet("Loop".into(), "lp".into(), "{snippet name=\"Loop\"}".into())
+            .unwrap();
+
+        let content =
+            "{argument name=\"who\"} {snippet name=\"Greeting\"} {snippet name=\"Greeting\"}";
+        assert_eq!(
+            collect_arguments(content, &snippet_manager).unwrap(),
+            vec![SnippetArgument {
+                name: "who".into(),
+                default_value: Some("there".into()),
+                options: vec![],
+            }]
+        );
+        assert!(matches!(
+            collect_arguments("{snippet name=\"Loop\"}", &snippet_manager),
+            Err(AppError::Snippet(_))
+        ));
+    }
+
+    #[test]
+    fn test_arguments_request_id_survives_javascript_numbers() {
+        let request = ArgumentsRequest {
+            request_id: u32::MAX,
+            snippet_name: "greeting".into(),
+            arguments: Vec::new(),
+        };
+        let json = serde_json::to_value(&request).unwrap();
+        let as_javascript = json["requestId"].as_f64().unwrap();
+        let submitted: u32 = serde_json::from_str(&as_javascript.to_string()).unwrap();
+        assert_eq!(submitted, request.request_id);
+    }
+
+    #[test]
+    fn test_argument_placeholder() {
+        let snippet_manager = SnippetManager::new_for_test().unwrap();
+        let content = "{argument name=\"who\" default=\"there\"}! Bye {argument name=\"who\"}.";
+
+        let result = parse_and_resolve_placeholders(content, &snippet_manager, None).unwrap();
+        assert_eq!(result.content, "there! Bye there.");
+
+        let values = HashMap::from([("who".to_string(), "Ada".to_string())]);
+        let result =
+            resolve_placeholders_with_arguments(content, &snippet_manager, None, &values).unwrap();
+        assert_eq!(result.content, "Ada! Bye Ada.");
+    }
+
+    fn last_expansion(inserted: usize, cursor_offset: usize) -> LastExpansion {
+        LastExpansion {
+            replaced: "sig ".into(),
+            inserted,
+            cursor_offset,
+            app: Some("gedit".into()),
+            expanded_at: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_undo_keys_of_last_expansion() {
+        assert_eq!(last_expansion(13, 5).undo_keys(), Some((5, 12)));
+        assert_eq!(last_expansion(0, 0).undo_keys(), None);
+    }
+
+    #[test]
+    fn test_last_expansion_is_only_undoable_right_after() {
+        let expansion = last_expansion(13, 0);
+        let gedit = || Some("gedit".to_string());
+        assert!(expansion.is_undoable(expansion.expanded_at, gedit));
+        assert!(!expansion.is_undoable(expansion.expanded_at, || Some("kate".to_string())));
+        assert!(!expansion.is_undoable(expansion.expanded_at + UNDO_WINDOW, gedit));
+
+        *LAST_EXPANSION.lock().unwrap() = Some(expansion);
+        forget_last_expansion();
+        assert!(LAST_EXPANSION.lock().unwrap().is_none());
+    }
+
     #[test]
     fn test_snippet_placeholder() {
         let snippet_manager = SnippetManager::new_for_test().unwrap();
@@ -515,9 +1219,107 @@ mod tests {
             .unwrap();
 
         let content = "{snippet name=\"recursion\"}";
+        let result = parse_and_resolve_placeholders(content, &snippet_manager, None);
+
+        match result {
+            Err(AppError::Snippet(message)) => {
+                assert!(message.contains("recursion → recursion"), "{}", message)
+            }
+            _ => panic!("Expected a snippet cycle error"),
+        }
+    }
+
+    #[test]
+    fn test_nested_snippet_placeholders() {
+        let snippet_manager = SnippetManager::new_for_test().unwrap();
+        snippet_manager
+            .create_snippet("Name".into(), "nm".into(), "Ada {cursor}".into())
+            .unwrap();
+        snippet_manager
+            .create_snippet(
+                "Signature".into(),
+                "sig".into(),
+                "{snippet name=\"Name\" | uppercase}, {date format=\"yyyy\"}".into(),
+            )
+            .unwrap();
+
+        let content = "Thanks,{cursor}\n{snippet name=\"Signature\"}";
         let result = parse_and_resolve_placeholders(content, &snippet_manager, None).unwrap();
+        let year = Local::now().format("%Y").to_string();
+        assert_eq!(result.content, format!("Thanks,\nADA , {}", year));
+        assert_eq!(result.cursor_pos, Some(7));
+    }
+
+    #[test]
+    fn test_snippet_nesting_depth_is_limited() {
+        let snippet_manager = SnippetManager::new_for_test().unwrap();
+        for level in 0..=MAX_SNIPPET_DEPTH {
+            snippet_manager
+                .create_snippet(
+                    format!("level{}", level),
+                    format!("l{}", level),
+                    format!("{{snippet name=\"level{}\"}}", level + 1),
+                )
+                .unwrap();
+        }
+
+        let result =
+            parse_and_resolve_placeholders("{snippet name=\"level0\"}", &snippet_manager, None);
+        assert!(matches!(result, Err(AppError::Snippet(_))));
+        assert!(parse_and_resolve_placeholders(
+            &format!("{{snippet name=\"level{}\"}}", MAX_SNIPPET_DEPTH - 1),
+            &snippet_manager,
+            None
+        )
+        .is_ok());
+    }
+
+    /// Records what would have been typed.
+    #[derive(Default)]
+    struct RecordingInputManager(Mutex<Vec<String>>);
+
+    impl InputManager for RecordingInputManager {
+        fn start_listening(
+            &self,
+            _callback: Box<dyn Fn(InputEvent) + Send + Sync>,
+        ) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn inject_text(&self, text: &str) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(text.to_string());
+            Ok(())
+        }
+
+        fn inject_key_clicks(&self, _key: EnigoKey, _count: usize) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_unresolvable_snippet_leaves_keyword_untouched() {
+        let snippet_manager = SnippetManager::new_for_test().unwrap();
+        snippet_manager
+            .create_snippet("loop".into(), "lp".into(), "{snippet name=\"loop\"}".into())
+            .unwrap();
+        let input_manager = Arc::new(RecordingInputManager::default());
+        let trigger = Trigger {
+            typed: "lp".into(),
+            delimiter: None,
+            case: CaseStyle::Unchanged,
+        };
 
-        assert_eq!(result.content, "");
+        let result = replace_keyword(
+            &trigger,
+            "{snippet name=\"loop\"}",
+            None,
+            &snippet_manager,
+            input_manager.clone(),
+            &HashMap::new(),
+        );
+        assert!(matches!(result, Err(AppError::Snippet(_))));
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert!(input_manager.0.lock().unwrap().is_empty());
     }
 
     #[test]
@@ -542,6 +1344,30 @@ mod tests {
         assert_eq!(result.content, "\"line1\\nline2\"");
     }
 
+    #[test]
+    fn test_decoding_modifiers() {
+        assert_eq!(
+            apply_modifiers("a%20b+c%C3%A9".into(), &["percent-decode"]),
+            "a b cé"
+        );
+        assert_eq!(
+            apply_modifiers("aGVsbG8gd29ybGQ=".into(), &["base64-decode", "uppercase"]),
+            "HELLO WORLD"
+        );
+        assert_eq!(
+            apply_modifiers("{\"a\":[1,2]}".into(), &["json-pretty"]),
+            "{\n  \"a\": [\n    1,\n    2\n  ]\n}"
+        );
+        assert_eq!(
+            apply_modifiers(" a b\n\tc ".into(), &["strip-whitespace"]),
+            "abc"
+        );
+        assert_eq!(
+            apply_modifiers("not base64!".into(), &["base64-decode"]),
+            "not base64!"
+        );
+    }
+
     #[test]
     fn test_translate_date_format_string() {
         let raycast_format = "MMMM dd, yyyy 'at' hh:mm:ss a";
@@ -0,0 +1,442 @@
+//! Conversion between snippets and Espanso match files.
+//!
+//! `{clipboard}`, `{cursor}` and the date placeholders are translated to Espanso variables
+//! and back; anything else, such as placeholders with modifiers or `uuid`, is kept as literal
+//! text.
+
+use super::engine::{
+    default_date_format, parse_attributes, translate_date_format, OFFSET_REGEX, PLACEHOLDER_REGEX,
+};
+use super::types::{SnippetExport, TriggerMode};
+use crate::error::AppError;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+const ESPANSO_CURSOR: &str = "$|$";
+const DATE_PLACEHOLDERS: &[&str] = &["date", "time", "datetime", "day"];
+
+static VARIABLE_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\{\{\s*(?P<name>[\w.]+)\s*\}\}").unwrap());
+
+/// The parts of an Espanso match file that snippets map to. Other keys are ignored.
+#[derive(Serialize, Deserialize, Default)]
+struct MatchFile {
+    #[serde(default, skip_serializing)]
+    global_vars: Vec<Var>,
+    matches: Option<Vec<Match>>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct Match {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trigger: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    triggers: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    replace: Option<String>,
+    #[serde(skip_serializing_if = "is_false")]
+    word: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    left_word: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    right_word: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    propagate_case: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    vars: Vec<Var>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Var {
+    name: String,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    #[serde(default, skip_serializing_if = "VarParams::is_empty")]
+    params: VarParams,
+}
+
+/// The parameters of the variable types that have a placeholder equivalent.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct VarParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    echo: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offset: Option<i64>,
+}
+
+impl VarParams {
+    fn is_empty(&self) -> bool {
+        self.echo.is_none() && self.format.is_none() && self.offset.is_none()
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// Turns a strftime format into the `yyyy-MM-dd` style used by placeholders. Literal text
+/// containing letters is quoted; unsupported specifiers are kept as literal text.
+fn strftime_to_date_format(strftime: &str) -> String {
+    let mut format = String::new();
+    let mut literal = String::new();
+    let flush = |format: &mut String, literal: &mut String| {
+        if literal.chars().any(|c| c.is_ascii_alphabetic()) {
+            format.push('\'');
+            format.push_str(literal);
+            format.push('\'');
+        } else {
+            format.push_str(literal);
+        }
+        literal.clear();
+    };
+
+    let mut chars = strftime.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            // Quotes delimit literals in the placeholder format and can't be escaped.
+            if c != '\'' {
+                literal.push(c);
+            }
+            continue;
+        }
+        let mut spec = chars.next().unwrap_or('%');
+        let unpadded = spec == '-';
+        if unpadded {
+            spec = chars.next().unwrap_or('-');
+        }
+        let token = match (spec, unpadded) {
+            ('Y', _) => "yyyy",
+            ('y', _) => "yy",
+            ('B', _) => "MMMM",
+            ('b' | 'h', _) => "MMM",
+            ('m', false) => "MM",
+            ('m', true) => "M",
+            ('A', _) => "EEEE",
+            ('a', _) => "EEE",
+            ('d', false) => "dd",
+            ('d', true) | ('e', _) => "d",
+            ('H', false) => "HH",
+            ('H', true) => "H",
+            ('I', false) => "hh",
+            ('I', true) => "h",
+            ('M', false) => "mm",
+            ('M', true) => "m",
+            ('S', false) => "ss",
+            ('S', true) => "s",
+            ('f', _) => "SSS",
+            ('p' | 'P', _) => "a",
+            ('z', _) => "Z",
+            ('F', _) => "yyyy-MM-dd",
+            ('D', _) => "MM/dd/yy",
+            ('T', _) => "HH:mm:ss",
+            ('R', _) => "HH:mm",
+            ('n', _) => {
+                literal.push('\n');
+                continue;
+            }
+            ('t', _) => {
+                literal.push('\t');
+                continue;
+            }
+            ('%', _) => {
+                literal.push('%');
+                continue;
+            }
+            (other, _) => {
+                literal.push('%');
+                if unpadded {
+                    literal.push('-');
+                }
+                literal.push(other);
+                continue;
+            }
+        };
+        flush(&mut format, &mut literal);
+        format.push_str(token);
+    }
+    flush(&mut format, &mut literal);
+    format
+}
+
+const SECONDS_PER_UNIT: &[(&str, i64)] = &[("d", 86_400), ("h", 3_600), ("m", 60)];
+
+/// Espanso offsets are in seconds. Years and months count as 365 and 30 days.
+fn offset_to_seconds(offset: &str) -> i64 {
+    OFFSET_REGEX
+        .captures_iter(offset)
+        .map(|cap| {
+            let num: i64 = cap["num"].parse().unwrap_or(0);
+            let unit = match &cap["unit"] {
+                "y" => 365 * 86_400,
+                "M" => 30 * 86_400,
+                "d" => 86_400,
+                "h" => 3_600,
+                _ => 60,
+            };
+            if &cap["sign"] == "-" {
+                -num * unit
+            } else {
+                num * unit
+            }
+        })
+        .sum()
+}
+
+/// The inverse of `offset_to_seconds` in days, hours and minutes. Leftover seconds are
+/// dropped.
+fn seconds_to_offset(seconds: i64) -> Option<String> {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let mut remaining = seconds.abs();
+    let mut offset = String::new();
+    for (unit, size) in SECONDS_PER_UNIT {
+        let count = remaining / size;
+        remaining %= size;
+        if count > 0 {
+            offset.push_str(&format!("{}{}{}", sign, count, unit));
+        }
+    }
+    (!offset.is_empty()).then_some(offset)
+}
+
+/// Rewrites Espanso variables in `replace` as placeholders. Variables that have no
+/// equivalent are kept as they are.
+fn import_text(replace: &str, vars: &[&Var]) -> String {
+    let text = VARIABLE_REGEX.replace_all(replace, |cap: &regex::Captures| {
+        let name = &cap["name"];
+        let var = vars.iter().find(|var| var.name == name);
+        let kind = var.and_then(|var| var.kind.as_deref());
+        let params = var.map(|var| &var.params);
+        match kind {
+            Some("clipboard") => "{clipboard}".to_string(),
+            None if name == "clipboard" => "{clipboard}".to_string(),
+            Some("echo") => params
+                .and_then(|params| params.echo.clone())
+                .unwrap_or_default(),
+            Some("date") => {
+                let mut placeholder = "{date".to_string();
+                if let Some(format) = params.and_then(|params| params.format.as_deref()) {
+                    placeholder
+                        .push_str(&format!(" format=\"{}\"", strftime_to_date_format(format)));
+                }
+                let offset = params.and_then(|params| params.offset);
+                if let Some(offset) = offset.and_then(seconds_to_offset) {
+                    placeholder.push_str(&format!(" offset=\"{}\"", offset));
+                }
+                placeholder.push('}');
+                placeholder
+            }
+            _ => cap[0].to_string(),
+        }
+    });
+    text.replace(ESPANSO_CURSOR, "{cursor}")
+}
+
+/// Reads the matches of an Espanso match file. A match with several triggers becomes one
+/// snippet per trigger; regex triggers and matches without a `replace` text are skipped.
+pub fn parse_match_file(yaml: &str) -> Result<Vec<SnippetExport>, AppError> {
+    let file: MatchFile = serde_norway::from_str(yaml)
+        .map_err(|e| AppError::Snippet(format!("Invalid Espanso file: {}", e)))?;
+    let matches = file
+        .matches
+        .ok_or_else(|| AppError::Snippet("Espanso file has no `matches`".into()))?;
+
+    let mut snippets = Vec::new();
+    for entry in &matches {
+        let Some(replace) = &entry.replace else {
+            continue;
+        };
+        let vars: Vec<&Var> = entry.vars.iter().chain(&file.global_vars).collect();
+        let text = import_text(replace, &vars);
+        // A match that only checks the right side still needs a delimiter to expand.
+        let trigger_mode = if entry.word || entry.right_word {
+            TriggerMode::Delimiter
+        } else if entry.left_word {
+            TriggerMode::WordBoundary
+        } else {
+            TriggerMode::Immediate
+        };
+        let triggers = entry.trigger.iter().chain(&entry.triggers);
+        for trigger in triggers.filter(|trigger| !trigger.is_empty()) {
+            snippets.push(SnippetExport {
+                name: entry.label.as_ref().unwrap_or(trigger).clone(),
+                text: text.clone(),
+                keyword: trigger.clone(),
+                folder: String::new(),
+                tags: Vec::new(),
+                trigger_mode,
+                ignore_case: entry.propagate_case,
+            });
+        }
+    }
+    Ok(snippets)
+}
+
+/// Rewrites the placeholders of `content` as Espanso variables. Returns the `replace` text
+/// and the variables it uses.
+fn export_text(content: &str) -> (String, Vec<Var>) {
+    let mut vars: Vec<Var> = Vec::new();
+    let mut date_vars: Vec<(String, String)> = Vec::new();
+    let mut text = String::with_capacity(content.len());
+    let mut last_end = 0;
+
+    for cap in PLACEHOLDER_REGEX.captures_iter(content) {
+        let full_match = cap.get(0).unwrap();
+        text.push_str(&content[last_end..full_match.start()]);
+        last_end = full_match.end();
+
+        let name = &cap["name"];
+        let attributes = parse_attributes(cap.name("attributes").map_or("", |m| m.as_str()));
+        let has_modifiers = cap
+            .name("modifiers")
+            .is_some_and(|m| !m.as_str().trim().is_empty());
+        let replacement = match name {
+            _ if has_modifiers => None,
+            "cursor" => Some(ESPANSO_CURSOR.to_string()),
+            "clipboard" if attributes.is_empty() => {
+                if !vars.iter().any(|var| var.name == "clipboard") {
+                    vars.push(Var {
+                        name: "clipboard".to_string(),
+                        kind: Some("clipboard".to_string()),
+                        params: VarParams::default(),
+                    });
+                }
+                Some("{{clipboard}}".to_string())
+            }
+            name if DATE_PLACEHOLDERS.contains(&name) => {
+                let placeholder = full_match.as_str().to_string();
+                let var_name = match date_vars.iter().find(|(p, _)| *p == placeholder) {
+                    Some((_, var_name)) => var_name.clone(),
+                    None => {
+                        let var_name = format!("date{}", date_vars.len() + 1);
+                        let offset = attributes.get("offset").map_or(0, |o| offset_to_seconds(o));
+                        vars.push(Var {
+                            name: var_name.clone(),
+                            kind: Some("date".to_string()),
+                            params: VarParams {
+                                echo: None,
+                                format: Some(
+                                    attributes
+                                        .get("format")
+                                        .map(|format| translate_date_format(format))
+                                        .unwrap_or_else(|| default_date_format(name).to_string()),
+                                ),
+                                offset: (offset != 0).then_some(offset),
+                            },
+                        });
+                        date_vars.push((placeholder, var_name.clone()));
+                        var_name
+                    }
+                };
+                Some(format!("{{{{{}}}}}", var_name))
+            }
+            _ => None,
+        };
+        text.push_str(&replacement.unwrap_or_else(|| full_match.as_str().to_string()));
+    }
+    text.push_str(&content[last_end..]);
+    (text, vars)
+}
+
+/// Writes `snippets` as an Espanso match file.
+pub fn to_match_file(snippets: &[SnippetExport]) -> Result<String, AppError> {
+    let matches = snippets
+        .iter()
+        .map(|snippet| {
+            let (replace, vars) = export_text(&snippet.text);
+            Match {
+                trigger: Some(snippet.keyword.clone()),
+                label: Some(snippet.name.clone()),
+                replace: Some(replace),
+                word: snippet.trigger_mode == TriggerMode::Delimiter,
+                left_word: snippet.trigger_mode == TriggerMode::WordBoundary,
+                propagate_case: snippet.ignore_case,
+                vars,
+                ..Match::default()
+            }
+        })
+        .collect();
+    let file = MatchFile {
+        matches: Some(matches),
+        ..MatchFile::default()
+    };
+    serde_norway::to_string(&file).map_err(|e| AppError::Serialization(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_translates_variables() {
+        let yaml = r#"
+global_vars:
+  - name: greeting
+    type: echo
+    params:
+      echo: Hello
+matches:
+  - trigger: ":mail"
+    label: Mail
+    replace: "{{greeting}}, {{clip}}$|$ on {{today}} {{unknown}}"
+    vars:
+      - name: clip
+        type: clipboard
+      - name: today
+        type: date
+        params:
+          format: "%-d %B at %H:%M"
+          offset: -86400
+  - triggers: [":x", ":y"]
+    replace: "{{clipboard}}"
+  - regex: ":(?P<n>\\d+)"
+    replace: "skipped"
+"#;
+        let snippets = parse_match_file(yaml).unwrap();
+        assert_eq!(snippets.len(), 3);
+        assert_eq!(snippets[0].name, "Mail");
+        assert_eq!(snippets[0].keyword, ":mail");
+        assert_eq!(
+            snippets[0].text,
+            "Hello, {clipboard}{cursor} on {date format=\"d MMMM' at 'HH:mm\" offset=\"-1d\"} {{unknown}}"
+        );
+        assert_eq!(snippets[1].name, ":x");
+        assert_eq!(snippets[2].keyword, ":y");
+        assert_eq!(snippets[2].text, "{clipboard}");
+    }
+
+    #[test]
+    fn test_export_round_trips_through_import() {
+        let snippet = SnippetExport {
+            name: "Note \"quoted\"".into(),
+            text: "{clipboard}\n{date format=\"yyyy-MM-dd\" offset=\"+1d+2h\"} {time}{cursor} {uuid} {clipboard | uppercase}".into(),
+            keyword: ":note".into(),
+            folder: String::new(),
+            tags: Vec::new(),
+            trigger_mode: TriggerMode::Delimiter,
+            ignore_case: true,
+        };
+        let yaml = to_match_file(std::slice::from_ref(&snippet)).unwrap();
+        let file: MatchFile = serde_norway::from_str(&yaml).unwrap();
+        let exported = &file.matches.unwrap()[0];
+        assert_eq!(
+            exported.replace.as_deref(),
+            Some("{{clipboard}}\n{{date1}} {{date2}}$|$ {uuid} {clipboard | uppercase}")
+        );
+        assert_eq!(exported.vars[1].params.offset, Some(93600));
+
+        let imported = parse_match_file(&yaml).unwrap();
+        assert_eq!(imported[0].name, snippet.name);
+        assert_eq!(
+            imported[0].text,
+            "{clipboard}\n{date format=\"yyyy-MM-dd\" offset=\"+1d+2h\"} {date format=\"h:mm a\"}{cursor} {uuid} {clipboard | uppercase}"
+        );
+        assert_eq!(imported[0].trigger_mode, TriggerMode::Delimiter);
+        assert!(imported[0].ignore_case);
+    }
+}
@@ -17,6 +17,11 @@ use xkbcommon::xkb;
 #[derive(Debug, Clone)]
 pub enum InputEvent {
     KeyPress(char),
+    /// A key other than a modifier that types nothing, such as an arrow. It may have moved
+    /// the caret.
+    OtherKey,
+    /// A mouse button press, which may have moved the caret or the focus.
+    Click,
 }
 
 struct InternalClipboardGuard;
@@ -98,6 +103,8 @@ impl InputManager for RdevInputManager {
                     }
                     if let Some(ch) = key_to_char(&key, shift_pressed) {
                         callback(InputEvent::KeyPress(ch));
+                    } else if !is_modifier(&key) {
+                        callback(InputEvent::OtherKey);
                     }
                 }
                 rdev::EventType::KeyRelease(key) => {
@@ -105,6 +112,7 @@ impl InputManager for RdevInputManager {
                         shift_pressed = false;
                     }
                 }
+                rdev::EventType::ButtonPress(_) => callback(InputEvent::Click),
                 _ => (),
             };
             if let Err(error) = rdev::listen(cb) {
@@ -262,6 +270,7 @@ impl EvdevInputManager {
             KeyCode::KEY_V,
             KeyCode::KEY_BACKSPACE,
             KeyCode::KEY_LEFT,
+            KeyCode::KEY_RIGHT,
         ]);
 
         let mut attribute_set = evdev::AttributeSet::new();
@@ -299,6 +308,7 @@ impl EvdevInputManager {
     fn enigo_to_evdev(key: EnigoKey) -> Option<KeyCode> {
         match key {
             EnigoKey::LeftArrow => Some(KeyCode::KEY_LEFT),
+            EnigoKey::RightArrow => Some(KeyCode::KEY_RIGHT),
             EnigoKey::Backspace => Some(KeyCode::KEY_BACKSPACE),
             _ => None,
         }
@@ -311,8 +321,10 @@ impl InputManager for EvdevInputManager {
         let devices: Vec<_> = evdev::enumerate()
             .map(|(_, device)| device)
             .filter(|d| {
-                d.supported_keys()
-                    .map_or(false, |keys| keys.contains(evdev::KeyCode::KEY_ENTER))
+                d.supported_keys().map_or(false, |keys| {
+                    keys.contains(evdev::KeyCode::KEY_ENTER)
+                        || keys.contains(evdev::KeyCode::BTN_LEFT)
+                })
             })
             .collect();
 
@@ -349,6 +361,14 @@ impl InputManager for EvdevInputManager {
                                 if ev.event_type() != evdev::EventType::KEY {
                                     continue;
                                 }
+                                if (evdev::KeyCode::BTN_LEFT.0..=evdev::KeyCode::BTN_TASK.0)
+                                    .contains(&ev.code())
+                                {
+                                    if ev.value() == 1 {
+                                        callback(InputEvent::Click);
+                                    }
+                                    continue;
+                                }
 
                                 const XKB_KEYCODE_OFFSET: u16 = 8;
                                 let keycode = ev.code() + XKB_KEYCODE_OFFSET;
@@ -362,15 +382,17 @@ impl InputManager for EvdevInputManager {
                                     xkb::KeyDirection::Down => {
                                         xkb_state.update_key(keycode.into(), direction);
 
-                                        if xkb_state.key_get_one_sym(keycode.into())
-                                            == xkb::keysyms::KEY_BackSpace.into()
-                                        {
+                                        let keysym = xkb_state.key_get_one_sym(keycode.into());
+                                        if keysym == xkb::keysyms::KEY_BackSpace.into() {
                                             callback(InputEvent::KeyPress('\u{8}'));
                                         } else {
                                             let utf8_str = xkb_state.key_get_utf8(keycode.into());
                                             for ch in utf8_str.chars() {
                                                 callback(InputEvent::KeyPress(ch));
                                             }
+                                            if utf8_str.is_empty() && !keysym.is_modifier_key() {
+                                                callback(InputEvent::OtherKey);
+                                            }
                                         }
                                     }
                                     _ => {
@@ -491,6 +513,22 @@ lazy_static! {
     };
 }
 
+fn is_modifier(key: &Key) -> bool {
+    matches!(
+        key,
+        Key::ShiftLeft
+            | Key::ShiftRight
+            | Key::ControlLeft
+            | Key::ControlRight
+            | Key::Alt
+            | Key::AltGr
+            | Key::MetaLeft
+            | Key::MetaRight
+            | Key::CapsLock
+            | Key::NumLock
+    )
+}
+
 pub fn key_to_char(key: &Key, is_shifted: bool) -> Option<char> {
     match key {
         Key::Backspace => Some('\u{8}'),
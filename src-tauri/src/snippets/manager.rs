@@ -1,9 +1,13 @@
 use crate::error::AppError;
-use crate::snippets::types::Snippet;
+use crate::snippets::trigger::KeywordMatcher;
+use crate::snippets::types::{
+    AppFilter, ImportResult, Snippet, SnippetExport, SnippetFolder, TriggerMode,
+};
 use crate::store::{Storable, Store};
 use chrono::{DateTime, Utc};
-use rusqlite::params;
-use std::sync::Arc;
+use rusqlite::{params, OptionalExtension};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
 use tauri::AppHandle;
 
 const SNIPPETS_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS snippets (
@@ -15,9 +19,103 @@ const SNIPPETS_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS snippets (
     updated_at INTEGER NOT NULL
 )";
 
+const SNIPPET_TAGS_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS snippet_tags (
+    snippet_id INTEGER NOT NULL,
+    tag TEXT NOT NULL COLLATE NOCASE,
+    PRIMARY KEY (snippet_id, tag)
+)";
+
+/// Folders whose snippets, including those in subfolders, are not auto-expanded.
+const DISABLED_FOLDERS_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS snippet_disabled_folders (
+    folder TEXT PRIMARY KEY
+)";
+
+const SNIPPET_SETTINGS_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS snippet_settings (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+)";
+
+const APP_FILTER_SETTING: &str = "appFilter";
+
+/// Columns added after the table was first shipped, with their definitions.
+const ADDED_COLUMNS: &[(&str, &str)] = &[
+    ("times_used", "INTEGER NOT NULL DEFAULT 0"),
+    ("last_used_at", "INTEGER NOT NULL DEFAULT 0"),
+    ("folder", "TEXT NOT NULL DEFAULT ''"),
+    ("trigger_mode", "TEXT NOT NULL DEFAULT 'immediate'"),
+    ("ignore_case", "INTEGER NOT NULL DEFAULT 0"),
+    // JSON of an `AppFilter`, NULL to follow the global rules.
+    ("app_filter", "TEXT"),
+];
+
+/// Separates the tags aggregated by `SELECT_SNIPPETS`; tags can't contain control characters.
+const TAG_SEPARATOR: char = '\u{1f}';
+
+const SELECT_SNIPPETS: &str = "SELECT id, name, keyword, content, created_at, updated_at, times_used, last_used_at, folder,
+    (SELECT GROUP_CONCAT(tag, char(31)) FROM snippet_tags WHERE snippet_id = snippets.id),
+    NOT EXISTS (SELECT 1 FROM snippet_disabled_folders d WHERE snippets.folder = d.folder OR substr(snippets.folder, 1, length(d.folder) + 1) = d.folder || '/'),
+    trigger_mode, ignore_case, app_filter
+    FROM snippets";
+
+fn init_tables(store: &Store) -> Result<(), AppError> {
+    store.init_table(SNIPPETS_SCHEMA)?;
+    store.init_table(SNIPPET_TAGS_SCHEMA)?;
+    store.init_table(DISABLED_FOLDERS_SCHEMA)?;
+    store.init_table(SNIPPET_SETTINGS_SCHEMA)?;
+
+    let db = store.conn();
+    let mut stmt = db.prepare("PRAGMA table_info(snippets)")?;
+    let columns: Vec<String> = stmt
+        .query_map([], |row| row.get(1))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (name, definition) in ADDED_COLUMNS {
+        if !columns.iter().any(|column| column == name) {
+            db.execute(
+                &format!("ALTER TABLE snippets ADD COLUMN {} {}", name, definition),
+                [],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Trims every segment of a slash-separated folder path and drops empty ones, so
+/// ` Team//Support/ ` becomes `Team/Support`.
+pub fn normalize_folder(folder: &str) -> String {
+    folder
+        .split('/')
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Whether `folder` is `ancestor` or one of its subfolders. Everything is inside the root.
+fn is_within(folder: &str, ancestor: &str) -> bool {
+    ancestor.is_empty()
+        || folder == ancestor
+        || folder
+            .strip_prefix(ancestor)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Trims tags and drops empty and repeated ones, ignoring case.
+fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag: String = tag.trim().chars().filter(|c| !c.is_control()).collect();
+        if !tag.is_empty() && !normalized.iter().any(|t| t.eq_ignore_ascii_case(&tag)) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
 #[derive(Clone)]
 pub struct SnippetManager {
     store: Arc<Store>,
+    /// Built from the expandable snippets on first use, and dropped whenever they change.
+    matcher: Arc<Mutex<Option<Arc<KeywordMatcher>>>>,
 }
 
 impl Storable for Snippet {
@@ -25,6 +123,11 @@ impl Storable for Snippet {
         let created_at_ts: i64 = row.get(4)?;
         let updated_at_ts: i64 = row.get(5)?;
         let last_used_at_ts: i64 = row.get(7)?;
+        let mut tags: Vec<String> = row
+            .get::<_, Option<String>>(9)?
+            .map(|tags| tags.split(TAG_SEPARATOR).map(str::to_string).collect())
+            .unwrap_or_default();
+        tags.sort_by_key(|tag| tag.to_lowercase());
         Ok(Snippet {
             id: row.get(0)?,
             name: row.get(1)?,
@@ -34,6 +137,14 @@ impl Storable for Snippet {
             updated_at: DateTime::from_timestamp_nanos(updated_at_ts),
             times_used: row.get(6)?,
             last_used_at: DateTime::from_timestamp_nanos(last_used_at_ts),
+            folder: row.get(8)?,
+            tags,
+            expansion_enabled: row.get(10)?,
+            trigger_mode: TriggerMode::from_str(&row.get::<_, String>(11)?).unwrap_or_default(),
+            ignore_case: row.get(12)?,
+            app_filter: row
+                .get::<_, Option<String>>(13)?
+                .and_then(|filter| serde_json::from_str(&filter).ok()),
         })
     }
 }
@@ -41,53 +152,22 @@ impl Storable for Snippet {
 impl SnippetManager {
     pub fn new(app_handle: &AppHandle) -> Result<Self, AppError> {
         let store = Store::new(app_handle, "snippets.sqlite")?;
-        store.init_table(SNIPPETS_SCHEMA)?;
-
-        {
-            let db = store.conn();
-            let mut stmt = db.prepare("PRAGMA table_info(snippets)")?;
-            let columns: Vec<String> = stmt
-                .query_map([], |row| row.get(1))?
-                .collect::<Result<Vec<_>, _>>()?;
-
-            if !columns.contains(&"times_used".to_string()) {
-                db.execute(
-                    "ALTER TABLE snippets ADD COLUMN times_used INTEGER NOT NULL DEFAULT 0",
-                    [],
-                )?;
-            }
-            if !columns.contains(&"last_used_at".to_string()) {
-                db.execute(
-                    "ALTER TABLE snippets ADD COLUMN last_used_at INTEGER NOT NULL DEFAULT 0",
-                    [],
-                )?;
-            }
-        }
+        init_tables(&store)?;
 
         Ok(Self {
             store: Arc::new(store),
+            matcher: Arc::new(Mutex::new(None)),
         })
     }
 
     #[cfg(test)]
     pub fn new_for_test() -> Result<Self, AppError> {
         let store = Store::new_in_memory()?;
-        store.init_table(SNIPPETS_SCHEMA)?;
-
-        {
-            let db = store.conn();
-            db.execute(
-                "ALTER TABLE snippets ADD COLUMN times_used INTEGER NOT NULL DEFAULT 0",
-                [],
-            )?;
-            db.execute(
-                "ALTER TABLE snippets ADD COLUMN last_used_at INTEGER NOT NULL DEFAULT 0",
-                [],
-            )?;
-        }
+        init_tables(&store)?;
 
         Ok(Self {
             store: Arc::new(store),
+            matcher: Arc::new(Mutex::new(None)),
         })
     }
 
@@ -103,23 +183,81 @@ impl SnippetManager {
              VALUES (?1, ?2, ?3, ?4, ?4, 0, 0)",
             params![name, keyword, content, now],
         )?;
+        self.snippets_changed();
         Ok(self.store.last_insert_rowid())
     }
 
-    pub fn list_snippets(&self, search_term: Option<String>) -> Result<Vec<Snippet>, AppError> {
-        let mut query = "SELECT id, name, keyword, content, created_at, updated_at, times_used, last_used_at FROM snippets".to_string();
-
-        if let Some(term) = search_term {
-            if !term.is_empty() {
-                query.push_str(" WHERE name LIKE ?1 OR keyword LIKE ?1 OR content LIKE ?1");
-                query.push_str(" ORDER BY updated_at DESC");
-                let search_param = format!("%{}%", term);
-                return self.store.query(&query, params![search_param]);
+    /// Lists snippets matching `search_term` (name, keyword, content or tag), inside
+    /// `folder` or one of its subfolders, and tagged `tag`. Empty filters are ignored.
+    pub fn list_snippets(
+        &self,
+        search_term: Option<String>,
+        folder: Option<String>,
+        tag: Option<String>,
+    ) -> Result<Vec<Snippet>, AppError> {
+        let mut query = SELECT_SNIPPETS.to_string();
+        let mut where_clauses: Vec<&str> = Vec::new();
+        let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+
+        if let Some(term) = search_term.filter(|term| !term.is_empty()) {
+            where_clauses.push(
+                "(name LIKE ? OR keyword LIKE ? OR content LIKE ? OR EXISTS (SELECT 1 FROM snippet_tags t WHERE t.snippet_id = snippets.id AND t.tag LIKE ?))",
+            );
+            let search_param = format!("%{}%", term);
+            for _ in 0..4 {
+                params_vec.push(Box::new(search_param.clone()));
             }
         }
 
+        let folder = folder.map(|folder| normalize_folder(&folder));
+        if let Some(folder) = folder.filter(|folder| !folder.is_empty()) {
+            where_clauses.push("(folder = ? OR substr(folder, 1, ?) = ?)");
+            let prefix = format!("{}/", folder);
+            params_vec.push(Box::new(folder));
+            params_vec.push(Box::new(prefix.chars().count() as i64));
+            params_vec.push(Box::new(prefix));
+        }
+
+        if let Some(tag) = tag.filter(|tag| !tag.trim().is_empty()) {
+            where_clauses.push(
+                "EXISTS (SELECT 1 FROM snippet_tags t WHERE t.snippet_id = snippets.id AND t.tag = ?)",
+            );
+            params_vec.push(Box::new(tag.trim().to_string()));
+        }
+
+        if !where_clauses.is_empty() {
+            query.push_str(" WHERE ");
+            query.push_str(&where_clauses.join(" AND "));
+        }
         query.push_str(" ORDER BY updated_at DESC");
-        self.store.query(&query, [])
+
+        let params_ref: Vec<&dyn rusqlite::ToSql> = params_vec.iter().map(|b| b.as_ref()).collect();
+        self.store.query(&query, &params_ref[..])
+    }
+
+    /// The snippets the expansion engine listens for.
+    pub fn list_expandable_snippets(&self) -> Result<Vec<Snippet>, AppError> {
+        Ok(self
+            .list_snippets(None, None, None)?
+            .into_iter()
+            .filter(|snippet| snippet.expansion_enabled)
+            .collect())
+    }
+
+    /// The matcher for the keywords of `list_expandable_snippets`, built again after any
+    /// change to the snippets.
+    pub fn keyword_matcher(&self) -> Result<Arc<KeywordMatcher>, AppError> {
+        let mut matcher = self.matcher.lock().unwrap();
+        if let Some(matcher) = matcher.as_ref() {
+            return Ok(matcher.clone());
+        }
+        let built = Arc::new(KeywordMatcher::new(self.list_expandable_snippets()?)?);
+        *matcher = Some(built.clone());
+        Ok(built)
+    }
+
+    fn snippets_changed(&self) {
+        *self.matcher.lock().unwrap() = None;
     }
 
     pub fn update_snippet(
@@ -134,15 +272,255 @@ impl SnippetManager {
             "UPDATE snippets SET name = ?1, keyword = ?2, content = ?3, updated_at = ?4 WHERE id = ?5",
             params![name, keyword, content, now, id],
         )?;
+        self.snippets_changed();
+        Ok(())
+    }
+
+    /// Moves a snippet into `folder`, see `normalize_folder`.
+    pub fn set_snippet_folder(&self, id: i64, folder: &str) -> Result<(), AppError> {
+        self.store.execute(
+            "UPDATE snippets SET folder = ?1 WHERE id = ?2",
+            params![normalize_folder(folder), id],
+        )?;
+        self.snippets_changed();
+        Ok(())
+    }
+
+    /// Sets when typing the snippet's keyword expands it.
+    pub fn set_snippet_trigger(
+        &self,
+        id: i64,
+        trigger_mode: TriggerMode,
+        ignore_case: bool,
+    ) -> Result<(), AppError> {
+        self.store.execute(
+            "UPDATE snippets SET trigger_mode = ?1, ignore_case = ?2 WHERE id = ?3",
+            params![trigger_mode.as_str(), ignore_case, id],
+        )?;
+        self.snippets_changed();
+        Ok(())
+    }
+
+    /// Makes the snippet expand in the applications of `app_filter` instead of those of the
+    /// global rules, or follow the global rules again when it's `None`.
+    pub fn set_snippet_app_filter(
+        &self,
+        id: i64,
+        app_filter: Option<&AppFilter>,
+    ) -> Result<(), AppError> {
+        let app_filter = app_filter
+            .map(serde_json::to_string)
+            .transpose()
+            .map_err(|e| AppError::Serialization(e.to_string()))?;
+        self.store.execute(
+            "UPDATE snippets SET app_filter = ?1 WHERE id = ?2",
+            params![app_filter, id],
+        )?;
+        self.snippets_changed();
+        Ok(())
+    }
+
+    /// The applications snippets expand in, unless they have rules of their own.
+    pub fn app_filter(&self) -> Result<AppFilter, AppError> {
+        let db = self.store.conn();
+        let value: Option<String> = db
+            .query_row(
+                "SELECT value FROM snippet_settings WHERE key = ?1",
+                params![APP_FILTER_SETTING],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match value {
+            Some(value) => {
+                serde_json::from_str(&value).map_err(|e| AppError::Serialization(e.to_string()))
+            }
+            None => Ok(AppFilter::default()),
+        }
+    }
+
+    pub fn set_app_filter(&self, app_filter: &AppFilter) -> Result<(), AppError> {
+        let value = serde_json::to_string(app_filter)
+            .map_err(|e| AppError::Serialization(e.to_string()))?;
+        self.store.execute(
+            "INSERT OR REPLACE INTO snippet_settings (key, value) VALUES (?1, ?2)",
+            params![APP_FILTER_SETTING, value],
+        )?;
+        Ok(())
+    }
+
+    /// Whether `snippet` expands in `app`, following its own rules or the global ones.
+    pub fn expands_in(&self, snippet: &Snippet, app: Option<&str>) -> Result<bool, AppError> {
+        Ok(match &snippet.app_filter {
+            Some(app_filter) => app_filter.allows(app),
+            None => self.app_filter()?.allows(app),
+        })
+    }
+
+    /// Replaces the tags of a snippet.
+    pub fn set_snippet_tags(&self, id: i64, tags: &[String]) -> Result<(), AppError> {
+        let db = self.store.conn();
+        let tx = db.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM snippet_tags WHERE snippet_id = ?1",
+            params![id],
+        )?;
+        for tag in normalize_tags(tags) {
+            tx.execute(
+                "INSERT INTO snippet_tags (snippet_id, tag) VALUES (?1, ?2)",
+                params![id, tag],
+            )?;
+        }
+        tx.commit()?;
         Ok(())
     }
 
     pub fn delete_snippet(&self, id: i64) -> Result<(), AppError> {
+        self.store.execute(
+            "DELETE FROM snippet_tags WHERE snippet_id = ?1",
+            params![id],
+        )?;
         self.store
             .execute("DELETE FROM snippets WHERE id = ?1", params![id])?;
+        self.snippets_changed();
+        Ok(())
+    }
+
+    /// Every folder holding snippets, with its parents, sorted by path.
+    pub fn list_folders(&self) -> Result<Vec<SnippetFolder>, AppError> {
+        let snippets = self.list_snippets(None, None, None)?;
+        let mut folders: BTreeMap<String, SnippetFolder> = BTreeMap::new();
+        for snippet in &snippets {
+            let segments: Vec<&str> = snippet.folder.split('/').collect();
+            for depth in 1..=segments.len() {
+                let path = segments[..depth].join("/");
+                if path.is_empty() {
+                    continue;
+                }
+                folders
+                    .entry(path.clone())
+                    .or_insert_with(|| SnippetFolder {
+                        path,
+                        snippet_count: 0,
+                        enabled: true,
+                    })
+                    .snippet_count += 1;
+            }
+        }
+
+        let disabled = self.disabled_folders()?;
+        for folder in folders.values_mut() {
+            folder.enabled = !disabled
+                .iter()
+                .any(|disabled| is_within(&folder.path, disabled));
+        }
+        Ok(folders.into_values().collect())
+    }
+
+    fn disabled_folders(&self) -> Result<Vec<String>, AppError> {
+        let db = self.store.conn();
+        let mut stmt = db.prepare("SELECT folder FROM snippet_disabled_folders")?;
+        let folders = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(folders)
+    }
+
+    /// Turns auto-expansion on or off for every snippet in `folder` and its subfolders. A
+    /// subfolder can't be enabled while one of its parents is disabled.
+    pub fn set_folder_enabled(&self, folder: &str, enabled: bool) -> Result<(), AppError> {
+        let folder = normalize_folder(folder);
+        if folder.is_empty() {
+            return Err(AppError::Snippet(
+                "Snippets outside a folder can't be disabled together".into(),
+            ));
+        }
+        if enabled {
+            self.store.execute(
+                "DELETE FROM snippet_disabled_folders WHERE folder = ?1",
+                params![folder],
+            )?;
+        } else {
+            self.store.execute(
+                "INSERT OR IGNORE INTO snippet_disabled_folders (folder) VALUES (?1)",
+                params![folder],
+            )?;
+        }
+        self.snippets_changed();
         Ok(())
     }
 
+    /// Every tag in use, sorted ignoring case.
+    pub fn list_tags(&self) -> Result<Vec<String>, AppError> {
+        let db = self.store.conn();
+        let mut stmt = db.prepare(
+            "SELECT MIN(tag) FROM snippet_tags GROUP BY tag COLLATE NOCASE ORDER BY tag COLLATE NOCASE",
+        )?;
+        let tags = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(tags)
+    }
+
+    /// Exports the snippets inside `folder` and tagged `tag`, oldest first.
+    pub fn export_snippets(
+        &self,
+        folder: Option<String>,
+        tag: Option<String>,
+    ) -> Result<Vec<SnippetExport>, AppError> {
+        let mut snippets = self.list_snippets(None, folder, tag)?;
+        snippets.reverse();
+        Ok(snippets
+            .into_iter()
+            .map(|snippet| SnippetExport {
+                name: snippet.name,
+                text: snippet.content,
+                keyword: snippet.keyword,
+                folder: snippet.folder,
+                tags: snippet.tags,
+                trigger_mode: snippet.trigger_mode,
+                ignore_case: snippet.ignore_case,
+            })
+            .collect())
+    }
+
+    /// Adds `snippets`, skipping those whose keyword is already taken. Snippets without a
+    /// folder of their own go into `folder`, and `extra_tags` are added to every one.
+    pub fn import_snippets(
+        &self,
+        snippets: Vec<SnippetExport>,
+        folder: Option<&str>,
+        extra_tags: &[String],
+    ) -> Result<ImportResult, AppError> {
+        let mut result = ImportResult::default();
+        for snippet in snippets {
+            let id = match self.create_snippet(snippet.name, snippet.keyword, snippet.text) {
+                Ok(id) => id,
+                Err(AppError::Rusqlite(rusqlite::Error::SqliteFailure(e, Some(msg))))
+                    if e.code == rusqlite::ErrorCode::ConstraintViolation
+                        && msg.contains("UNIQUE constraint failed: snippets.keyword") =>
+                {
+                    result.duplicates_skipped += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let snippet_folder = match (snippet.folder.as_str(), folder) {
+                ("", Some(folder)) => folder,
+                (own, _) => own,
+            };
+            self.set_snippet_folder(id, snippet_folder)?;
+            let tags: Vec<String> = snippet
+                .tags
+                .into_iter()
+                .chain(extra_tags.iter().cloned())
+                .collect();
+            self.set_snippet_tags(id, &tags)?;
+            self.set_snippet_trigger(id, snippet.trigger_mode, snippet.ignore_case)?;
+            result.snippets_added += 1;
+        }
+        Ok(result)
+    }
+
     pub fn snippet_was_used(&self, id: i64) -> Result<(), AppError> {
         let now = Utc::now().timestamp_nanos_opt().unwrap_or_default();
         self.store.execute(
@@ -152,16 +530,24 @@ impl SnippetManager {
         Ok(())
     }
 
+    pub fn find_snippet_by_id(&self, id: i64) -> Result<Option<Snippet>, AppError> {
+        self.store
+            .query_row(&format!("{} WHERE id = ?1", SELECT_SNIPPETS), params![id])
+    }
+
     pub fn find_snippet_by_keyword(&self, keyword: &str) -> Result<Option<Snippet>, AppError> {
         self.store.query_row(
-            "SELECT id, name, keyword, content, created_at, updated_at, times_used, last_used_at FROM snippets WHERE keyword = ?1",
+            &format!("{} WHERE keyword = ?1", SELECT_SNIPPETS),
             params![keyword],
         )
     }
 
     pub fn find_snippet_by_name(&self, name: &str) -> Result<Option<Snippet>, AppError> {
         self.store.query_row(
-            "SELECT id, name, keyword, content, created_at, updated_at, times_used, last_used_at FROM snippets WHERE name = ?1 ORDER BY updated_at DESC LIMIT 1",
+            &format!(
+                "{} WHERE name = ?1 ORDER BY updated_at DESC LIMIT 1",
+                SELECT_SNIPPETS
+            ),
             params![name],
         )
     }
@@ -170,6 +556,7 @@ impl SnippetManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::snippets::types::AppFilterMode;
     use std::{thread, time::Duration};
 
     #[test]
@@ -183,7 +570,7 @@ mod tests {
             )
             .unwrap();
 
-        let snippets = manager.list_snippets(None).unwrap();
+        let snippets = manager.list_snippets(None, None, None).unwrap();
         assert_eq!(snippets.len(), 1);
         assert_eq!(snippets[0].name, "Test Snippet");
         assert_eq!(snippets[0].keyword, "testkey");
@@ -227,16 +614,31 @@ mod tests {
             .unwrap();
 
         assert_eq!(
-            manager.list_snippets(Some("email".into())).unwrap().len(),
+            manager
+                .list_snippets(Some("email".into()), None, None)
+                .unwrap()
+                .len(),
             2
         );
-        assert_eq!(manager.list_snippets(Some("sig".into())).unwrap().len(), 1);
         assert_eq!(
-            manager.list_snippets(Some("regards".into())).unwrap().len(),
+            manager
+                .list_snippets(Some("sig".into()), None, None)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            manager
+                .list_snippets(Some("regards".into()), None, None)
+                .unwrap()
+                .len(),
             1
         );
         assert_eq!(
-            manager.list_snippets(Some("nothing".into())).unwrap().len(),
+            manager
+                .list_snippets(Some("nothing".into()), None, None)
+                .unwrap()
+                .len(),
             0
         );
     }
@@ -261,6 +663,11 @@ mod tests {
         assert_eq!(snippet.id, id);
         assert_eq!(snippet.name, "Updated");
         assert_eq!(snippet.content, "updated content");
+        assert_eq!(
+            manager.find_snippet_by_id(id).unwrap().unwrap().keyword,
+            "updated"
+        );
+        assert!(manager.find_snippet_by_id(id + 1).unwrap().is_none());
     }
 
     #[test]
@@ -269,9 +676,9 @@ mod tests {
         let id = manager
             .create_snippet("To Delete".into(), "del".into(), "delete me".into())
             .unwrap();
-        assert_eq!(manager.list_snippets(None).unwrap().len(), 1);
+        assert_eq!(manager.list_snippets(None, None, None).unwrap().len(), 1);
         manager.delete_snippet(id).unwrap();
-        assert!(manager.list_snippets(None).unwrap().is_empty());
+        assert!(manager.list_snippets(None, None, None).unwrap().is_empty());
     }
 
     #[test]
@@ -316,4 +723,207 @@ mod tests {
         let not_found = manager.find_snippet_by_name("Non Existent").unwrap();
         assert!(not_found.is_none());
     }
+
+    #[test]
+    fn test_filter_by_folder_and_tag() {
+        let manager = SnippetManager::new_for_test().unwrap();
+        let support = manager
+            .create_snippet("Refund".into(), "refund".into(), "...".into())
+            .unwrap();
+        manager
+            .set_snippet_folder(support, " Team// Support ")
+            .unwrap();
+        manager
+            .set_snippet_tags(support, &["billing".into(), "Billing".into(), " ".into()])
+            .unwrap();
+        let team = manager
+            .create_snippet("Standup".into(), "standup".into(), "...".into())
+            .unwrap();
+        manager.set_snippet_folder(team, "Team").unwrap();
+        let teams = manager
+            .create_snippet("Teammate".into(), "mate".into(), "...".into())
+            .unwrap();
+        manager.set_snippet_folder(teams, "Teams").unwrap();
+
+        let names = |folder: Option<&str>, tag: Option<&str>| -> Vec<String> {
+            let mut names: Vec<String> = manager
+                .list_snippets(None, folder.map(Into::into), tag.map(Into::into))
+                .unwrap()
+                .into_iter()
+                .map(|snippet| snippet.name)
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(Some("Team"), None), ["Refund", "Standup"]);
+        assert_eq!(names(Some("Team/Support"), None), ["Refund"]);
+        assert_eq!(names(None, Some("BILLING")), ["Refund"]);
+
+        let refund = manager.find_snippet_by_keyword("refund").unwrap().unwrap();
+        assert_eq!(refund.folder, "Team/Support");
+        assert_eq!(refund.tags, ["billing"]);
+        assert_eq!(
+            manager
+                .list_snippets(Some("bill".into()), None, None)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_disabled_folder_is_not_expanded() {
+        let manager = SnippetManager::new_for_test().unwrap();
+        let nested = manager
+            .create_snippet("Nested".into(), "nested".into(), "...".into())
+            .unwrap();
+        manager.set_snippet_folder(nested, "Team/Support").unwrap();
+        manager
+            .create_snippet("Loose".into(), "loose".into(), "...".into())
+            .unwrap();
+
+        manager.set_folder_enabled("Team", false).unwrap();
+        let expandable: Vec<String> = manager
+            .list_expandable_snippets()
+            .unwrap()
+            .into_iter()
+            .map(|snippet| snippet.keyword)
+            .collect();
+        assert_eq!(expandable, ["loose"]);
+        assert_eq!(
+            manager.list_folders().unwrap(),
+            [
+                SnippetFolder {
+                    path: "Team".into(),
+                    snippet_count: 1,
+                    enabled: false,
+                },
+                SnippetFolder {
+                    path: "Team/Support".into(),
+                    snippet_count: 1,
+                    enabled: false,
+                },
+            ]
+        );
+
+        manager.set_folder_enabled("Team", true).unwrap();
+        assert_eq!(manager.list_expandable_snippets().unwrap().len(), 2);
+        assert!(manager.set_folder_enabled("/", false).is_err());
+    }
+
+    #[test]
+    fn test_export_and_import_keep_folders_and_tags() {
+        let source = SnippetManager::new_for_test().unwrap();
+        let id = source
+            .create_snippet("Greeting".into(), "hi".into(), "Hello".into())
+            .unwrap();
+        source.set_snippet_folder(id, "Sales").unwrap();
+        source.set_snippet_tags(id, &["intro".into()]).unwrap();
+        source
+            .create_snippet("Bye".into(), "bye".into(), "Goodbye".into())
+            .unwrap();
+        let exported = source.export_snippets(None, Some("intro".into())).unwrap();
+        assert_eq!(exported.len(), 1);
+
+        let json = serde_json::to_string(&exported).unwrap();
+        let raycast = r#"[{"name":"Sig","text":"Regards","keyword":"sig"}]"#;
+        let mut imported: Vec<SnippetExport> = serde_json::from_str(&json).unwrap();
+        imported.extend(serde_json::from_str::<Vec<SnippetExport>>(raycast).unwrap());
+
+        let target = SnippetManager::new_for_test().unwrap();
+        target
+            .create_snippet("Existing".into(), "sig".into(), "...".into())
+            .unwrap();
+        let result = target
+            .import_snippets(imported, Some("Imported"), &["team".into()])
+            .unwrap();
+        assert_eq!(result.snippets_added, 1);
+        assert_eq!(result.duplicates_skipped, 1);
+
+        let greeting = target.find_snippet_by_keyword("hi").unwrap().unwrap();
+        assert_eq!(greeting.folder, "Sales");
+        assert_eq!(greeting.tags, ["intro", "team"]);
+    }
+
+    #[test]
+    fn test_keyword_matcher_follows_changes() {
+        let manager = SnippetManager::new_for_test().unwrap();
+        let matches = |typed| {
+            manager
+                .keyword_matcher()
+                .unwrap()
+                .find(typed, None)
+                .map(|(snippet, _)| snippet.content.clone())
+        };
+        assert_eq!(matches("sig"), None);
+
+        let id = manager
+            .create_snippet("Signature".into(), "sig".into(), "Regards".into())
+            .unwrap();
+        assert_eq!(matches("sig"), Some("Regards".into()));
+
+        manager
+            .update_snippet(id, "Signature".into(), "sig".into(), "Cheers".into())
+            .unwrap();
+        assert_eq!(matches("sig"), Some("Cheers".into()));
+
+        manager.set_snippet_folder(id, "Mail").unwrap();
+        manager.set_folder_enabled("Mail", false).unwrap();
+        assert_eq!(matches("sig"), None);
+
+        manager.set_folder_enabled("Mail", true).unwrap();
+        manager.delete_snippet(id).unwrap();
+        assert_eq!(matches("sig"), None);
+    }
+
+    #[test]
+    fn test_app_filters() {
+        let manager = SnippetManager::new_for_test().unwrap();
+        let id = manager
+            .create_snippet("Signature".into(), "sig".into(), "Regards".into())
+            .unwrap();
+        let snippet =
+            |manager: &SnippetManager| manager.find_snippet_by_keyword("sig").unwrap().unwrap();
+
+        assert!(manager
+            .expands_in(&snippet(&manager), Some("firefox"))
+            .unwrap());
+        assert!(!manager
+            .expands_in(&snippet(&manager), Some("keepassxc"))
+            .unwrap());
+
+        manager
+            .set_app_filter(&AppFilter {
+                mode: AppFilterMode::Allow,
+                apps: vec!["Thunderbird".into()],
+            })
+            .unwrap();
+        assert!(manager
+            .expands_in(&snippet(&manager), Some("thunderbird"))
+            .unwrap());
+        assert!(!manager
+            .expands_in(&snippet(&manager), Some("firefox"))
+            .unwrap());
+        assert!(!manager.expands_in(&snippet(&manager), None).unwrap());
+
+        let terminals = AppFilter {
+            mode: AppFilterMode::Deny,
+            apps: vec!["kitty".into()],
+        };
+        manager
+            .set_snippet_app_filter(id, Some(&terminals))
+            .unwrap();
+        assert_eq!(snippet(&manager).app_filter, Some(terminals));
+        assert!(manager
+            .expands_in(&snippet(&manager), Some("firefox"))
+            .unwrap());
+        assert!(!manager
+            .expands_in(&snippet(&manager), Some("kitty"))
+            .unwrap());
+
+        manager.set_snippet_app_filter(id, None).unwrap();
+        assert!(!manager
+            .expands_in(&snippet(&manager), Some("firefox"))
+            .unwrap());
+    }
 }
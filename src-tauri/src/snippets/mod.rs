@@ -1,59 +1,155 @@
 pub mod engine;
+mod espanso;
 pub mod input_manager;
 pub mod manager;
+mod trigger;
 pub mod types;
 
 use crate::clipboard_history;
-use crate::error::AppError;
-use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tauri::{AppHandle, Manager};
-use types::Snippet;
+use types::{
+    AppFilter, ImportResult, Snippet, SnippetExport, SnippetFolder, SnippetFormat, TriggerMode,
+};
 
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ImportSnippet {
-    name: String,
-    text: String,
-    keyword: String,
-}
-
-#[derive(Serialize, Clone, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct ImportResult {
-    snippets_added: u32,
-    duplicates_skipped: u32,
+/// Applies the optional folder, tags and trigger settings sent along with a created or
+/// updated snippet. Trigger settings that weren't sent keep their current value.
+fn organize_snippet(
+    manager: &manager::SnippetManager,
+    id: i64,
+    folder: Option<String>,
+    tags: Option<Vec<String>>,
+    trigger_mode: Option<TriggerMode>,
+    ignore_case: Option<bool>,
+) -> Result<(), String> {
+    if let Some(folder) = folder {
+        manager
+            .set_snippet_folder(id, &folder)
+            .map_err(|e| e.to_string())?;
+    }
+    if let Some(tags) = tags {
+        manager
+            .set_snippet_tags(id, &tags)
+            .map_err(|e| e.to_string())?;
+    }
+    if trigger_mode.is_some() || ignore_case.is_some() {
+        let current = manager
+            .find_snippet_by_id(id)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Snippet {} not found", id))?;
+        manager
+            .set_snippet_trigger(
+                id,
+                trigger_mode.unwrap_or(current.trigger_mode),
+                ignore_case.unwrap_or(current.ignore_case),
+            )
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub fn create_snippet(
     app: AppHandle,
     name: String,
     keyword: String,
     content: String,
+    folder: Option<String>,
+    tags: Option<Vec<String>>,
+    trigger_mode: Option<TriggerMode>,
+    ignore_case: Option<bool>,
 ) -> Result<i64, String> {
-    app.state::<manager::SnippetManager>()
+    let manager = app.state::<manager::SnippetManager>();
+    let id = manager
         .create_snippet(name, keyword, content)
-        .map_err(|e| e.to_string())
+        .map_err(|e| e.to_string())?;
+    organize_snippet(&manager, id, folder, tags, trigger_mode, ignore_case)?;
+    Ok(id)
 }
 
 #[tauri::command]
-pub fn list_snippets(app: AppHandle, search_term: Option<String>) -> Result<Vec<Snippet>, String> {
+pub fn list_snippets(
+    app: AppHandle,
+    search_term: Option<String>,
+    folder: Option<String>,
+    tag: Option<String>,
+) -> Result<Vec<Snippet>, String> {
     app.state::<manager::SnippetManager>()
-        .list_snippets(search_term)
+        .list_snippets(search_term, folder, tag)
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub fn update_snippet(
     app: AppHandle,
     id: i64,
     name: String,
     keyword: String,
     content: String,
+    folder: Option<String>,
+    tags: Option<Vec<String>>,
+    trigger_mode: Option<TriggerMode>,
+    ignore_case: Option<bool>,
 ) -> Result<(), String> {
-    app.state::<manager::SnippetManager>()
+    let manager = app.state::<manager::SnippetManager>();
+    manager
         .update_snippet(id, name, keyword, content)
+        .map_err(|e| e.to_string())?;
+    organize_snippet(&manager, id, folder, tags, trigger_mode, ignore_case)
+}
+
+#[tauri::command]
+pub fn list_snippet_folders(app: AppHandle) -> Result<Vec<SnippetFolder>, String> {
+    app.state::<manager::SnippetManager>()
+        .list_folders()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn list_snippet_tags(app: AppHandle) -> Result<Vec<String>, String> {
+    app.state::<manager::SnippetManager>()
+        .list_tags()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn set_snippet_folder_enabled(
+    app: AppHandle,
+    folder: String,
+    enabled: bool,
+) -> Result<(), String> {
+    app.state::<manager::SnippetManager>()
+        .set_folder_enabled(&folder, enabled)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_snippet_app_filter(app: AppHandle) -> Result<AppFilter, String> {
+    app.state::<manager::SnippetManager>()
+        .app_filter()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn set_snippet_app_filter(app: AppHandle, app_filter: AppFilter) -> Result<(), String> {
+    app.state::<manager::SnippetManager>()
+        .set_app_filter(&app_filter)
+        .map_err(|e| e.to_string())
+}
+
+/// Gives a snippet rules of its own, or makes it follow the global ones when `app_filter`
+/// is `None`.
+#[tauri::command]
+pub fn set_snippet_app_override(
+    app: AppHandle,
+    id: i64,
+    app_filter: Option<AppFilter>,
+) -> Result<(), String> {
+    app.state::<manager::SnippetManager>()
+        .set_snippet_app_filter(id, app_filter.as_ref())
         .map_err(|e| e.to_string())
 }
 
@@ -71,6 +167,16 @@ pub fn snippet_was_used(app: AppHandle, id: i64) -> Result<(), String> {
         .map_err(|e| e.to_string())
 }
 
+/// Answers an `ArgumentsRequest`; `values` is `None` when the form was dismissed.
+#[tauri::command]
+pub fn submit_snippet_arguments(
+    app: AppHandle,
+    request_id: u32,
+    values: Option<HashMap<String, String>>,
+) -> Result<(), String> {
+    engine::submit_arguments(&app, request_id, values).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub fn paste_snippet_content(app: AppHandle, content: String) -> Result<(), String> {
     let snippet_manager = app.state::<manager::SnippetManager>().inner();
@@ -112,32 +218,42 @@ pub fn paste_snippet_content(app: AppHandle, content: String) -> Result<(), Stri
     Ok(())
 }
 
+/// Imports snippets exported by Raycast, Espanso or `export_snippets`. Snippets without a
+/// folder go into `folder`, and `tags` are added to all of them.
 #[tauri::command]
-pub fn import_snippets(app: AppHandle, json_content: String) -> Result<ImportResult, String> {
-    let snippets: Vec<ImportSnippet> =
-        serde_json::from_str(&json_content).map_err(|e| e.to_string())?;
-
-    let manager = app.state::<manager::SnippetManager>();
-    let mut snippets_added = 0;
-    let mut duplicates_skipped = 0;
+pub fn import_snippets(
+    app: AppHandle,
+    content: String,
+    format: Option<SnippetFormat>,
+    folder: Option<String>,
+    tags: Option<Vec<String>>,
+) -> Result<ImportResult, String> {
+    let snippets: Vec<SnippetExport> = match format.unwrap_or_default() {
+        SnippetFormat::Raycast => serde_json::from_str(&content).map_err(|e| e.to_string())?,
+        SnippetFormat::Espanso => espanso::parse_match_file(&content).map_err(|e| e.to_string())?,
+    };
 
-    for snippet in snippets {
-        let keyword = snippet.keyword;
+    app.state::<manager::SnippetManager>()
+        .import_snippets(snippets, folder.as_deref(), &tags.unwrap_or_default())
+        .map_err(|e| e.to_string())
+}
 
-        match manager.create_snippet(snippet.name, keyword, snippet.text) {
-            Ok(_) => snippets_added += 1,
-            Err(AppError::Rusqlite(rusqlite::Error::SqliteFailure(e, Some(msg))))
-                if e.code == rusqlite::ErrorCode::ConstraintViolation
-                    && msg.contains("UNIQUE constraint failed: snippets.keyword") =>
-            {
-                duplicates_skipped += 1;
-            }
-            Err(e) => return Err(e.to_string()),
+/// Exports the snippets in `folder` and tagged `tag` in a format `import_snippets` reads.
+#[tauri::command]
+pub fn export_snippets(
+    app: AppHandle,
+    folder: Option<String>,
+    tag: Option<String>,
+    format: Option<SnippetFormat>,
+) -> Result<String, String> {
+    let snippets = app
+        .state::<manager::SnippetManager>()
+        .export_snippets(folder, tag)
+        .map_err(|e| e.to_string())?;
+    match format.unwrap_or_default() {
+        SnippetFormat::Raycast => {
+            serde_json::to_string_pretty(&snippets).map_err(|e| e.to_string())
         }
+        SnippetFormat::Espanso => espanso::to_match_file(&snippets).map_err(|e| e.to_string()),
     }
-
-    Ok(ImportResult {
-        snippets_added,
-        duplicates_skipped,
-    })
 }
@@ -0,0 +1,308 @@
+//! Decides which snippet the keys typed so far trigger, following each snippet's
+//! `TriggerMode` and `ignore_case` setting.
+
+use super::types::{Snippet, TriggerMode};
+use crate::error::AppError;
+use aho_corasick::AhoCorasick;
+
+/// How the typed keyword was capitalised. Snippets that ignore case carry it over to their
+/// expansion.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseStyle {
+    Unchanged,
+    /// `Sig` turns `best regards` into `Best regards`.
+    Capitalized,
+    /// `SIG` turns `best regards` into `BEST REGARDS`.
+    Uppercase,
+}
+
+impl CaseStyle {
+    fn of(typed: &str, keyword: &str) -> Self {
+        if typed == keyword {
+            return CaseStyle::Unchanged;
+        }
+        let letters: Vec<char> = typed.chars().filter(|c| c.is_alphabetic()).collect();
+        if letters.len() > 1 && letters.iter().all(|c| c.is_uppercase()) {
+            CaseStyle::Uppercase
+        } else if letters.first().is_some_and(|c| c.is_uppercase()) {
+            CaseStyle::Capitalized
+        } else {
+            CaseStyle::Unchanged
+        }
+    }
+
+    /// Applies the style to `text`, keeping `cursor`, a char index, at the same place.
+    pub fn apply(self, text: &str, cursor: Option<usize>) -> (String, Option<usize>) {
+        let split = cursor.map_or(text.len(), |pos| {
+            text.char_indices().nth(pos).map_or(text.len(), |(i, _)| i)
+        });
+        let (before, after) = text.split_at(split);
+        let (before, after) = match self {
+            CaseStyle::Unchanged => return (text.to_string(), cursor),
+            CaseStyle::Uppercase => (before.to_uppercase(), after.to_uppercase()),
+            CaseStyle::Capitalized => match capitalize(before) {
+                Some(before) => (before, after.to_string()),
+                None => (before.to_string(), capitalize(after).unwrap_or_default()),
+            },
+        };
+        let cursor = cursor.map(|_| before.chars().count());
+        (before + &after, cursor)
+    }
+}
+
+/// Uppercases the first letter of `text`, or returns `None` when it has none.
+fn capitalize(text: &str) -> Option<String> {
+    let (i, letter) = text.char_indices().find(|(_, c)| c.is_alphabetic())?;
+    Some(format!(
+        "{}{}{}",
+        &text[..i],
+        letter.to_uppercase(),
+        &text[i + letter.len_utf8()..]
+    ))
+}
+
+/// The typed text a snippet replaces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trigger {
+    /// The keyword as it was typed, which may differ in case from the snippet's.
+    pub typed: String,
+    /// The delimiter typed after the keyword, typed again after the expansion.
+    pub delimiter: Option<char>,
+    pub case: CaseStyle,
+}
+
+impl Trigger {
+    /// Presses of backspace needed to delete what was typed.
+    pub fn backspaces(&self) -> usize {
+        self.typed.chars().count() + usize::from(self.delimiter.is_some())
+    }
+}
+
+/// Keys that complete a keyword of a `TriggerMode::Delimiter` snippet. Enter isn't one,
+/// since it usually sends what was typed before the expansion could replace it.
+pub fn is_delimiter(ch: char) -> bool {
+    ch == ' ' || ch == '\t' || ch.is_ascii_punctuation()
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+/// The end of `typed` when it is `keyword`, compared ignoring case if `ignore_case` is set.
+fn keyword_suffix<'a>(typed: &'a str, keyword: &str, ignore_case: bool) -> Option<&'a str> {
+    let length = keyword.chars().count();
+    let start = typed.char_indices().rev().nth(length.checked_sub(1)?)?.0;
+    let suffix = &typed[start..];
+    let matches = if ignore_case {
+        suffix.to_lowercase() == keyword.to_lowercase()
+    } else {
+        suffix == keyword
+    };
+    matches.then_some(suffix)
+}
+
+/// Whether `keyword`, typed after `before`, starts a word. Keywords starting with
+/// punctuation, like `;sig`, always do.
+fn starts_word(before: &str, keyword: &str) -> bool {
+    !keyword.chars().next().is_some_and(is_word_char)
+        || !before.chars().next_back().is_some_and(is_word_char)
+}
+
+/// Finds the first snippet triggered by the last key. When that key is a delimiter, pass
+/// it as `delimiter` with `typed` holding the text before it, and only snippets waiting for
+/// a delimiter are considered. Otherwise `typed` ends with the key and the others are.
+pub fn find_trigger<'a>(
+    snippets: impl IntoIterator<Item = &'a Snippet>,
+    typed: &str,
+    delimiter: Option<char>,
+) -> Option<(&'a Snippet, Trigger)> {
+    snippets.into_iter().find_map(|snippet| {
+        if (snippet.trigger_mode == TriggerMode::Delimiter) != delimiter.is_some() {
+            return None;
+        }
+        let suffix = keyword_suffix(typed, &snippet.keyword, snippet.ignore_case)?;
+        let before = &typed[..typed.len() - suffix.len()];
+        if snippet.trigger_mode != TriggerMode::Immediate && !starts_word(before, &snippet.keyword)
+        {
+            return None;
+        }
+        let case = if snippet.ignore_case {
+            CaseStyle::of(suffix, &snippet.keyword)
+        } else {
+            CaseStyle::Unchanged
+        };
+        Some((
+            snippet,
+            Trigger {
+                typed: suffix.to_string(),
+                delimiter,
+                case,
+            },
+        ))
+    })
+}
+
+/// Finds the keywords at the end of the typed text without going through every snippet.
+/// Keywords are matched lowercased, then `find_trigger` checks the few candidates.
+pub struct KeywordMatcher {
+    /// `None` when there is nothing to expand.
+    automaton: Option<AhoCorasick>,
+    /// The expandable snippets, in the order of the automaton's patterns.
+    snippets: Vec<Snippet>,
+    longest_keyword: usize,
+}
+
+impl KeywordMatcher {
+    pub fn new(snippets: Vec<Snippet>) -> Result<Self, AppError> {
+        let snippets: Vec<Snippet> = snippets
+            .into_iter()
+            .filter(|snippet| !snippet.keyword.is_empty())
+            .collect();
+        let automaton = if snippets.is_empty() {
+            None
+        } else {
+            let keywords = snippets.iter().map(|s| s.keyword.to_lowercase());
+            Some(AhoCorasick::new(keywords).map_err(|e| AppError::Snippet(e.to_string()))?)
+        };
+        let longest_keyword = snippets
+            .iter()
+            .map(|snippet| snippet.keyword.chars().count())
+            .max()
+            .unwrap_or(0);
+        Ok(Self {
+            automaton,
+            snippets,
+            longest_keyword,
+        })
+    }
+
+    /// How many typed characters to keep: the longest keyword and the one before it, which
+    /// tells whether the keyword starts a word.
+    pub fn buffer_size(&self) -> usize {
+        self.longest_keyword + 1
+    }
+
+    /// Like `find_trigger`, preferring the longest keyword when several end the text.
+    pub fn find(&self, typed: &str, delimiter: Option<char>) -> Option<(&Snippet, Trigger)> {
+        let automaton = self.automaton.as_ref()?;
+        let typed_lowercase = typed.to_lowercase();
+        let mut candidates: Vec<&Snippet> = automaton
+            .find_overlapping_iter(&typed_lowercase)
+            .filter(|m| m.end() == typed_lowercase.len())
+            .map(|m| &self.snippets[m.pattern().as_usize()])
+            .collect();
+        candidates.sort_by_key(|snippet| std::cmp::Reverse(snippet.keyword.len()));
+        find_trigger(candidates, typed, delimiter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snippets::manager::SnippetManager;
+
+    fn snippets(keywords: &[(&str, TriggerMode, bool)]) -> Vec<Snippet> {
+        let manager = SnippetManager::new_for_test().unwrap();
+        for (keyword, trigger_mode, ignore_case) in keywords {
+            let id = manager
+                .create_snippet(keyword.to_string(), keyword.to_string(), "text".into())
+                .unwrap();
+            manager
+                .set_snippet_trigger(id, *trigger_mode, *ignore_case)
+                .unwrap();
+        }
+        manager.list_snippets(None, None, None).unwrap()
+    }
+
+    fn triggered(snippets: &[Snippet], typed: &str, delimiter: Option<char>) -> Option<String> {
+        find_trigger(snippets, typed, delimiter).map(|(snippet, _)| snippet.keyword.clone())
+    }
+
+    #[test]
+    fn test_trigger_modes() {
+        let immediate = snippets(&[("sig", TriggerMode::Immediate, false)]);
+        assert_eq!(
+            triggered(&immediate, "design sig", None),
+            Some("sig".into())
+        );
+        assert_eq!(triggered(&immediate, "designsig", None), Some("sig".into()));
+
+        let word = snippets(&[
+            ("sig", TriggerMode::WordBoundary, false),
+            (";addr", TriggerMode::WordBoundary, false),
+        ]);
+        assert_eq!(triggered(&word, "sig", None), Some("sig".into()));
+        assert_eq!(triggered(&word, "(sig", None), Some("sig".into()));
+        assert_eq!(triggered(&word, "designsig", None), None);
+        assert_eq!(triggered(&word, "home;addr", None), Some(";addr".into()));
+
+        let delimited = snippets(&[("sig", TriggerMode::Delimiter, false)]);
+        assert_eq!(triggered(&delimited, "sig", None), None);
+        assert_eq!(
+            triggered(&delimited, "a sig", Some(',')),
+            Some("sig".into())
+        );
+        assert_eq!(triggered(&delimited, "designsig", Some(' ')), None);
+        assert_eq!(triggered(&immediate, "sig", Some(' ')), None);
+    }
+
+    #[test]
+    fn test_ignore_case_propagates_case() {
+        let snippets = snippets(&[
+            ("br", TriggerMode::WordBoundary, true),
+            ("ty", TriggerMode::WordBoundary, false),
+        ]);
+        let case_of = |typed| find_trigger(&snippets, typed, None).map(|(_, t)| t.case);
+        assert_eq!(case_of("br"), Some(CaseStyle::Unchanged));
+        assert_eq!(case_of("Br"), Some(CaseStyle::Capitalized));
+        assert_eq!(case_of("BR"), Some(CaseStyle::Uppercase));
+        assert_eq!(case_of("Ty"), None);
+
+        assert_eq!(
+            CaseStyle::Capitalized.apply("best regards", Some(4)),
+            ("Best regards".into(), Some(4))
+        );
+        assert_eq!(
+            CaseStyle::Capitalized.apply("{ best", Some(0)),
+            ("{ Best".into(), Some(0))
+        );
+        assert_eq!(
+            CaseStyle::Uppercase.apply("straße x", Some(7)),
+            ("STRASSE X".into(), Some(8))
+        );
+    }
+
+    #[test]
+    fn test_keyword_matcher() {
+        let matcher = KeywordMatcher::new(snippets(&[
+            ("sig", TriggerMode::Immediate, false),
+            ("esig", TriggerMode::Immediate, false),
+            ("Addr", TriggerMode::Immediate, true),
+            ("bye", TriggerMode::Delimiter, false),
+        ]))
+        .unwrap();
+        let found = |typed, delimiter| {
+            matcher
+                .find(typed, delimiter)
+                .map(|(snippet, _)| snippet.keyword.clone())
+        };
+        assert_eq!(matcher.buffer_size(), 5);
+        assert_eq!(found("a sig", None), Some("sig".into()));
+        assert_eq!(found("esig", None), Some("esig".into()));
+        assert_eq!(found("my ADDR", None), Some("Addr".into()));
+        assert_eq!(found("sigh", None), None);
+        assert_eq!(found("bye", None), None);
+        assert_eq!(found("bye", Some('.')), Some("bye".into()));
+
+        let empty = KeywordMatcher::new(Vec::new()).unwrap();
+        assert!(empty.find("sig", None).is_none());
+    }
+
+    #[test]
+    fn test_trigger_backspaces() {
+        let snippets = snippets(&[("é!", TriggerMode::Delimiter, false)]);
+        let (_, trigger) = find_trigger(&snippets, "voilà é!", Some(' ')).unwrap();
+        assert_eq!(trigger.typed, "é!");
+        assert_eq!(trigger.backspaces(), 3);
+    }
+}
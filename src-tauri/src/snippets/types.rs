@@ -1,5 +1,91 @@
+use crate::clipboard_history::settings::DEFAULT_EXCLUDED_APPS;
+use crate::error::AppError;
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum AppFilterMode {
+    /// Snippets expand everywhere except in the listed applications.
+    #[default]
+    Deny,
+    /// Snippets expand only in the listed applications.
+    Allow,
+}
+
+/// The applications snippets expand in, by window class.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AppFilter {
+    pub mode: AppFilterMode,
+    pub apps: Vec<String>,
+}
+
+impl Default for AppFilter {
+    /// Password managers are denied until the user says otherwise.
+    fn default() -> Self {
+        Self {
+            mode: AppFilterMode::Deny,
+            apps: DEFAULT_EXCLUDED_APPS
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl AppFilter {
+    /// Whether snippets expand in `app`, compared ignoring case. When the focused application
+    /// can't be identified, only a deny-list lets them expand.
+    pub fn allows(&self, app: Option<&str>) -> bool {
+        let listed = app.is_some_and(|app| {
+            self.apps
+                .iter()
+                .any(|listed| listed.trim().eq_ignore_ascii_case(app))
+        });
+        match self.mode {
+            AppFilterMode::Deny => !listed,
+            AppFilterMode::Allow => listed,
+        }
+    }
+}
+
+/// When typing a snippet's keyword expands it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum TriggerMode {
+    /// As soon as the keyword is typed, even in the middle of a word.
+    #[default]
+    Immediate,
+    /// As soon as the keyword is typed at the start of a word.
+    WordBoundary,
+    /// Once the keyword, typed at the start of a word, is followed by a delimiter such as
+    /// a space, tab or punctuation mark. The delimiter is kept after the expansion.
+    Delimiter,
+}
+
+impl TriggerMode {
+    pub fn from_str(s: &str) -> Result<Self, AppError> {
+        match s {
+            "immediate" => Ok(TriggerMode::Immediate),
+            "wordBoundary" => Ok(TriggerMode::WordBoundary),
+            "delimiter" => Ok(TriggerMode::Delimiter),
+            _ => Err(AppError::Snippet("Invalid trigger mode".into())),
+        }
+    }
+
+    fn is_immediate(&self) -> bool {
+        *self == TriggerMode::Immediate
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TriggerMode::Immediate => "immediate",
+            TriggerMode::WordBoundary => "wordBoundary",
+            TriggerMode::Delimiter => "delimiter",
+        }
+    }
+}
 
 #[derive(Serialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -12,4 +98,60 @@ pub struct Snippet {
     pub updated_at: DateTime<Utc>,
     pub times_used: i32,
     pub last_used_at: DateTime<Utc>,
+    /// Slash-separated path such as `Team/Support`, empty at the top level.
+    pub folder: String,
+    pub tags: Vec<String>,
+    /// `false` while the snippet's folder, or one of its parents, is disabled.
+    pub expansion_enabled: bool,
+    pub trigger_mode: TriggerMode,
+    /// Matches the keyword in any case and carries its capitalisation over to the
+    /// expansion, so `Sig` expands to `Best regards` and `SIG` to `BEST REGARDS`.
+    pub ignore_case: bool,
+    /// Replaces the global application rules for this snippet when set.
+    pub app_filter: Option<AppFilter>,
+}
+
+/// A folder that contains snippets, directly or in a subfolder. Disabling a folder turns
+/// off auto-expansion for everything in it, which is how a collection is switched off.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SnippetFolder {
+    pub path: String,
+    pub snippet_count: u32,
+    pub enabled: bool,
+}
+
+/// The import and export format, compatible with Raycast's `name`/`text`/`keyword` JSON.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SnippetExport {
+    pub name: String,
+    pub text: String,
+    pub keyword: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub folder: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "TriggerMode::is_immediate")]
+    pub trigger_mode: TriggerMode,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ignore_case: bool,
+}
+
+/// File formats snippets can be imported from and exported to.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum SnippetFormat {
+    /// A JSON array of `SnippetExport`.
+    #[default]
+    Raycast,
+    /// An Espanso match file, see `espanso.rs`.
+    Espanso,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportResult {
+    pub snippets_added: u32,
+    pub duplicates_skipped: u32,
 }
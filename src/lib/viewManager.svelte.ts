@@ -17,8 +17,21 @@ export type ViewState =
 	| 'quicklink-form'
 	| 'create-snippet-form'
 	| 'import-snippets'
+	| 'snippet-arguments'
 	| 'file-search';
 
+export type SnippetArgument = {
+	name: string;
+	defaultValue: string | null;
+	options: string[];
+};
+
+export type SnippetArgumentsRequest = {
+	requestId: number;
+	snippetName: string;
+	arguments: SnippetArgument[];
+};
+
 type OauthState = {
 	url: string;
 	providerName: string;
@@ -33,6 +46,8 @@ class ViewManager {
 	commandToConfirm = $state<PluginInfo | null>(null);
 	pluginToSelectInSettings = $state<string | undefined>(undefined);
 	extensionToSelect = $state<Extension | null>(null);
+	snippetArgumentsRequest = $state<SnippetArgumentsRequest | null>(null);
+	private viewBeforeSnippetArguments: ViewState = 'command-palette';
 
 	oauthState: OauthState = $state(null);
 	oauthStatus: 'initial' | 'authorizing' | 'success' | 'error' = $state('initial');
@@ -77,6 +92,19 @@ class ViewManager {
 		this.currentView = 'import-snippets';
 	};
 
+	showSnippetArguments = (request: SnippetArgumentsRequest) => {
+		if (this.currentView !== 'snippet-arguments') {
+			this.viewBeforeSnippetArguments = this.currentView;
+		}
+		this.snippetArgumentsRequest = request;
+		this.currentView = 'snippet-arguments';
+	};
+
+	closeSnippetArguments = () => {
+		this.snippetArgumentsRequest = null;
+		this.currentView = this.viewBeforeSnippetArguments;
+	};
+
 	showFileSearch = () => {
 		this.currentView = 'file-search';
 	};